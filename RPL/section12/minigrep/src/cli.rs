@@ -0,0 +1,24 @@
+use clap::Parser;
+use grep_core::SearchArgs;
+
+// コマンドライン引数の定義
+// 検索と表示のオプションはgrep-coreのSearchArgsで、minigrep2と共通
+// クエリが"-"で始まるときは、"--"の後に書く
+#[derive(Debug, Parser)]
+#[command(
+    name = "minigrep",
+    version,
+    about = "Search a file for lines matching a query",
+    after_help = "Defaults are read from $MINIGREP_CONFIG, or ~/.minigrep.toml if it exists.",
+    args_override_self = true
+)]
+pub struct Cli {
+    /// String (or regular expression with -E) to search for
+    pub query: String,
+
+    /// File to search, or - for standard input
+    pub filename: String,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+}
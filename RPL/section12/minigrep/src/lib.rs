@@ -1,60 +1,77 @@
 use std::error::Error;
-use std::fs::File;
-use std::env;
 use std::io::prelude::*;
+use std::io::{self, BufWriter};
+
+use clap::Parser;
+use stream_search::LineReader;
+
+// コマンドライン引数の定義
+pub mod cli;
+
+pub use cli::Cli;
+pub use grep_core::{
+    count_matches, format_lines, new_matcher, search_lines, CaseMode, ColorMode, ConfigError, FileConfig, Formatter,
+    Line, LineKind, LineSearcher, Matcher, OutputOptions, SearchConfig, SearchOptions,
+};
 
 pub struct Config {
-    pub query: String,
+    // 何をどう探して、どう表示するか
+    pub search: SearchConfig,
     pub filename: String,
-    pub case_sensitive: bool,
 }
 
 impl Config {
-    pub fn new(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() < 3 {
-            return Err("not enough argments.");
-        }
-        
-        let query = args[1].clone();
-        let filename = args[2].clone();
-        // env::var関数は、環境変数がセットされていたら、環境変数の値を含むOk列挙子の成功値になるResultを返す。 
-        // 環境変数がセットされていなければ、Err列挙子を返す
-        // is_errメソッドを使用して、エラー、つまり環境変数がセットされていないことを確認している
-        // これは、大文字小文字を区別して検索することを意味している
-        // `CASE_INSENSITIVE`の値をチェックでなく、環境変数がセットされているかどうかだけを確認している
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
-
-        Ok(Config{ query, filename, case_sensitive })
-
+    // コマンドライン引数（先頭はプログラム名）と環境変数から設定を作る
+    // 環境変数はenvで読むので、テストでは実際の環境を変えずに済む
+    //
+    // それぞれの値は、コマンドライン引数、環境変数、設定ファイル（`MINIGREP_CONFIG`か`~/.minigrep.toml`）、
+    // 既定値の順に優先する
+    pub fn parse<I, E>(args: I, env: E) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let cli = Cli::try_parse_from(args)?;
+        let (search, grep_core::NoExtraKeys {}) = SearchConfig::load("minigrep", cli.query, &cli.search, &env)?;
+        Ok(Config { search, filename: cli.filename })
     }
 }
 
 // 引数にConfigインスタンスを取る
 // Resultが`Ok`の場合は`ユニット型()`、エラーの場合は`Error`トレイトを返す
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let mut f = File::open(config.filename)?;
-    // 空のString型を作成
-    let mut contents = String::new();
-    // すべてのバイトを読み込みバッファに追加する
-    f.read_to_string(&mut contents)?;
-
-    // case_sensitiveがtrue(env::varの結果がErr(環境変数がセットされていない)かつis_err結果がtrue)ならsearch関数を実行
-    // そうでなければ、search_case_insensitiveを実行
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
-    } else {
-        search_case_insensitive(&config.query, &contents)
-    };
-
-    // search関数に`config.query`と`contents`の参照を引数として渡す
-    for line in results {
-        println!("{}", line);
-    }
-
-    //`ユニット型()`を`Ok()`で包む
-    Ok(())
+    // 固定文字列か正規表現か、大文字小文字を区別するかどうかに合わせてMatcherを選ぶ
+    // 正規表現が不正なら、ここでエラーを返す
+    let matcher = config.search.matcher()?;
+
+    // ファイル名が"-"なら標準入力から読む
+    // ファイル全体は読み込まず、少しずつ読みながら検索する
+    let input = stream_search::open(&config.filename).map_err(|e| format!("{}: {}", config.filename, e))?;
+
+    // 1行ごとにprintln!でロックを取らないように、標準出力をまとめて書く
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match search_input(input, &*matcher, &config, &mut out).and_then(|_| out.flush()) {
+        // `| head`などで出力先が先に閉じられたら、そこで静かに止める
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        //`ユニット型()`を`Ok()`で包む
+        result => Ok(result.map(|_| ())?),
+    }
 } 
 
+// readerを1行ずつ検索し、結果をoutに書く。戻り値は条件に合った行の数
+// UTF-8として正しくないバイトは置換文字(U+FFFD)にして、検索を止めないようにする
+pub fn search_input<R: Read, W: Write>(
+    reader: R,
+    matcher: &dyn Matcher,
+    config: &Config,
+    out: &mut W,
+) -> io::Result<usize> {
+    let mut lines = LineReader::new(reader);
+    let search = &config.search;
+    grep_core::write_matches(&mut lines, None, matcher, &search.search_options(), &search.output_options(), out)
+}
+
 // 空のベクタを返す関数
 // search関数に返される値は、search関数にcontents引数で渡されているデータと同じライフタイムを持つ
 // スライスに参照されるデータは、参照が有効になるために有効である必要がある
@@ -118,7 +135,7 @@ Duct tape.";
     fn case_insensitive() {
         let query = "rUsT";
         let contents = "\
-Rust
+Rust:
 safe, fast, productive.
 Pick three.
 Trust me.";
@@ -128,4 +145,189 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
-}
\ No newline at end of file
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn options_anywhere_around_query_and_file() {
+        let config = Config::parse(args(&["minigrep", "-n", "to", "-C", "2", "poem.txt", "-v"]), no_env).unwrap();
+        assert_eq!((config.search.query.as_str(), config.filename.as_str()), ("to", "poem.txt"));
+        assert!(config.search.line_numbers && config.search.invert && !config.search.regex);
+        assert_eq!((config.search.before_context, config.search.after_context), (2, 2));
+        assert!(config.search.output_options().group_separator);
+
+        let config = Config::parse(args(&["minigrep", "-A", "1", "-C", "3", "to", "poem.txt"]), no_env).unwrap();
+        assert_eq!((config.search.before_context, config.search.after_context), (3, 1));
+
+        assert!(Config::parse(args(&["minigrep", "to", "poem.txt", "-A"]), no_env).is_err());
+        assert!(Config::parse(args(&["minigrep", "-o", "to"]), no_env).is_err());
+        assert!(Config::parse(args(&["minigrep", "-A", "many", "to", "poem.txt"]), no_env).is_err());
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let config = Config::parse(args(&["minigrep", "-c", "--", "-n", "poem.txt"]), no_env).unwrap();
+        assert_eq!(config.search.query, "-n");
+        assert!(config.search.count && !config.search.line_numbers);
+    }
+
+    #[test]
+    fn help_and_usage_errors_come_from_clap() {
+        match Config::parse(args(&["minigrep", "--help"]), no_env) {
+            Err(ConfigError::Args(e)) => assert_eq!(e.kind(), clap::error::ErrorKind::DisplayHelp),
+            _ => panic!("--help should be reported as an argument error"),
+        }
+        match Config::parse(args(&["minigrep", "--colour", "to", "poem.txt"]), no_env) {
+            Err(ConfigError::Args(e)) => assert_eq!(e.kind(), clap::error::ErrorKind::UnknownArgument),
+            _ => panic!("unknown options should be rejected"),
+        }
+    }
+
+    #[test]
+    fn case_flags_and_case_insensitive_env() {
+        let insensitive = |name: &str| if name == "CASE_INSENSITIVE" { Some(String::new()) } else { None };
+        let sensitive = |argv: &[&str], env: &dyn Fn(&str) -> Option<String>| {
+            Config::parse(args(argv), env).unwrap().search.case_sensitive
+        };
+
+        assert!(sensitive(&["minigrep", "rust", "poem.txt"], &no_env));
+        // 値が空でも、設定されていれば区別しない
+        assert!(!sensitive(&["minigrep", "rust", "poem.txt"], &insensitive));
+        // フラグは環境変数より優先する
+        assert!(sensitive(&["minigrep", "-s", "rust", "poem.txt"], &insensitive));
+        assert!(!sensitive(&["minigrep", "-i", "rust", "poem.txt"], &no_env));
+        // 後に書いたフラグが勝つ
+        assert!(sensitive(&["minigrep", "-i", "-S", "-s", "rust", "poem.txt"], &no_env));
+        assert!(!sensitive(&["minigrep", "-s", "-i", "rust", "poem.txt"], &no_env));
+        // スマートケースは、クエリに大文字があるときだけ区別する
+        assert!(!sensitive(&["minigrep", "-S", "rust", "poem.txt"], &insensitive));
+        assert!(sensitive(&["minigrep", "-S", "Rust", "poem.txt"], &insensitive));
+    }
+
+    #[test]
+    fn color_follows_flag_file_and_no_color() {
+        let no_color = |name: &str| if name == "NO_COLOR" { Some(String::from("1")) } else { None };
+        let color = |argv: &[&str]| Config::parse(args(argv), no_color).unwrap().search.color;
+        assert!(color(&["minigrep", "--color", "always", "to", "poem.txt"]));
+        assert!(!color(&["minigrep", "--color=auto", "to", "poem.txt"]));
+        assert!(!color(&["minigrep", "to", "poem.txt"]));
+    }
+
+    #[test]
+    fn config_file_sets_defaults_below_env_and_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("minigrep.toml");
+        std::fs::write(&path, "case = \"smart\"\ncolor = \"always\"\nline_number = true\ncontext = 2\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let config = Config::parse(args(&["minigrep", "--config", &path, "rust", "poem.txt"]), no_env).unwrap();
+        assert!(!config.search.case_sensitive && config.search.color && config.search.line_numbers);
+        assert_eq!((config.search.before_context, config.search.after_context), (2, 2));
+
+        // MINIGREP_CONFIGでも指定できる。フラグと環境変数はファイルより優先する
+        let env = |name: &str| match name {
+            "MINIGREP_CONFIG" => Some(path.clone()),
+            "CASE_INSENSITIVE" => Some(String::from("1")),
+            _ => None,
+        };
+        let config = Config::parse(args(&["minigrep", "-N", "-B", "0", "--color=never", "Rust", "poem.txt"]), env).unwrap();
+        assert!(!config.search.case_sensitive && !config.search.color && !config.search.line_numbers);
+        assert_eq!((config.search.before_context, config.search.after_context), (0, 2));
+
+        let config = Config::parse(args(&["minigrep", "--no-config", "rust", "poem.txt"]), env).unwrap();
+        assert!(!config.search.line_numbers);
+        assert!(Config::parse(args(&["minigrep", "--no-config", "--config", &path, "a", "b"]), no_env).is_err());
+    }
+
+    #[test]
+    fn config_file_locations_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path().to_str().unwrap().to_string();
+        let env = |name: &str| if name == "HOME" { Some(home.clone()) } else { None };
+
+        // ホームディレクトリの設定ファイルは、なければ読まない
+        assert!(Config::parse(args(&["minigrep", "to", "poem.txt"]), env).is_ok());
+        std::fs::write(dir.path().join(".minigrep.toml"), "regex = true\n").unwrap();
+        assert!(Config::parse(args(&["minigrep", "to", "poem.txt"]), env).unwrap().search.regex);
+        assert!(!Config::parse(args(&["minigrep", "-F", "to", "poem.txt"]), env).unwrap().search.regex);
+
+        let missing = dir.path().join("missing.toml");
+        match Config::parse(args(&["minigrep", "--config", missing.to_str().unwrap(), "to", "poem.txt"]), no_env) {
+            Err(e @ ConfigError::ReadConfig { .. }) => assert!(e.to_string().contains("missing.toml")),
+            _ => panic!("a missing --config file should be an error"),
+        }
+
+        std::fs::write(dir.path().join(".minigrep.toml"), "colour = \"always\"\n").unwrap();
+        match Config::parse(args(&["minigrep", "to", "poem.txt"]), env) {
+            Err(e @ ConfigError::ParseConfig { .. }) => assert!(e.to_string().contains("colour"), "{}", e),
+            _ => panic!("unknown keys should be rejected"),
+        }
+    }
+
+    // "line N hay"という行をlines行分、その場で作って返すReader
+    // 10万行ごとに、UTF-8として正しくないバイトを含む"needle"の行を混ぜる
+    struct Generated {
+        next: usize,
+        lines: usize,
+        pending: Vec<u8>,
+        offset: usize,
+    }
+
+    impl Read for Generated {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.offset == self.pending.len() {
+                if self.next == self.lines {
+                    return Ok(0);
+                }
+                self.next += 1;
+                self.offset = 0;
+                self.pending = if self.next.is_multiple_of(100_000) {
+                    let mut line = format!("line {} needle ", self.next).into_bytes();
+                    line.extend_from_slice(b"\xff\n");
+                    line
+                } else {
+                    format!("line {} hay\n", self.next).into_bytes()
+                };
+            }
+            let n = buf.len().min(self.pending.len() - self.offset);
+            buf[..n].copy_from_slice(&self.pending[self.offset..self.offset + n]);
+            self.offset += n;
+            Ok(n)
+        }
+    }
+
+    fn generated(lines: usize) -> Generated {
+        Generated { next: 0, lines, pending: Vec::new(), offset: 0 }
+    }
+
+    fn search_generated(argv: &[&str], lines: usize) -> (usize, String) {
+        let config = Config::parse(args(argv), no_env).unwrap();
+        let matcher = config.search.matcher().unwrap();
+        let mut out = Vec::new();
+        let matched = search_input(generated(lines), &*matcher, &config, &mut out).unwrap();
+        (matched, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn streams_generated_input_with_context() {
+        let (matched, out) = search_generated(&["minigrep", "--color=never", "-n", "-B", "1", "needle", "-"], 500_000);
+        assert_eq!(matched, 5);
+        let out: Vec<&str> = out.lines().collect();
+        assert_eq!(out.len(), 5 * 2 + 4);
+        assert_eq!(&out[..3], &["99999-line 99999 hay", "100000:line 100000 needle \u{fffd}", "--"]);
+        assert_eq!(out[13], "500000:line 500000 needle \u{fffd}");
+    }
+
+    #[test]
+    fn counts_generated_input() {
+        let (matched, out) = search_generated(&["minigrep", "-c", "-v", "-E", "needle|line 1 ", "-"], 1_000_000);
+        assert_eq!(matched, 1_000_000 - 11);
+        assert_eq!(out, "999989\n");
+    }
+}
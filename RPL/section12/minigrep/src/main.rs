@@ -1,20 +1,20 @@
 extern crate minigrep;
 use std::env;
 use std::process;
-use minigrep::Config;
+use minigrep::{Config, ConfigError};
 
 fn main() {
-    // イテレータをベクタに変換
-    // `collect()`を使用するときは、コンパイラは型を推論できないないので型注釈すること
-    let args: Vec<String> = env::args().collect();
-
-    // 引数として、`args`を参照する
-    // Config::newのErr値を`err`引数のクロージャに渡している
-    let config = Config::new(&args).unwrap_or_else(|err| {
-        // 引数解析時に問題があった場合
-        eprintln!("Problem parsing argments: {}", err);
-        // 終了コード1でプロセスを終了する
-        process::exit(1);
+    // コマンドライン引数と環境変数から設定を作る
+    // Config::parseのErr値を`err`引数のクロージャに渡している
+    let config = Config::parse(env::args(), |name| env::var(name).ok()).unwrap_or_else(|err| match err {
+        // 引数の誤りや--helpは、clapが使い方を表示して終了する
+        ConfigError::Args(e) => e.exit(),
+        // 設定ファイルに問題があった場合
+        err => {
+            eprintln!("Problem parsing argments: {}", err);
+            // 終了コード1でプロセスを終了する
+            process::exit(1);
+        }
     });
 
     //println!("Searching for {}", config.query);
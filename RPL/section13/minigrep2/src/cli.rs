@@ -0,0 +1,57 @@
+use clap::Parser;
+use grep_core::SearchArgs;
+
+// コマンドライン引数の定義
+// 検索と表示のオプションはgrep-coreのSearchArgsで、minigrepと共通。ここではディレクトリのたどり方を足す
+// クエリが"-"で始まるときは、"--"の後に書く
+#[derive(Debug, Parser)]
+#[command(
+    name = "minigrep2",
+    version,
+    about = "Search files and directories for lines matching a query",
+    after_help = "Defaults are read from $MINIGREP2_CONFIG, or ~/.minigrep2.toml if it exists.",
+    args_override_self = true
+)]
+pub struct Cli {
+    /// String (or regular expression with -E) to search for
+    pub query: String,
+
+    /// Files or directories to search (- for standard input); directories are searched recursively
+    #[arg(required = true, value_name = "PATH")]
+    pub paths: Vec<String>,
+
+    #[command(flatten)]
+    pub search: SearchArgs,
+
+    /// Search binary files as if they were text
+    #[arg(short = 'a', long)]
+    pub text: bool,
+
+    /// Search hidden files and directories
+    #[arg(long, overrides_with = "no_hidden")]
+    pub hidden: bool,
+
+    /// Skip hidden files and directories
+    #[arg(long, overrides_with = "hidden")]
+    pub no_hidden: bool,
+
+    /// Search files listed in .gitignore and .ignore
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Follow symbolic links found in directories
+    #[arg(short = 'L', long, overrides_with = "no_follow")]
+    pub follow: bool,
+
+    /// Skip symbolic links found in directories
+    #[arg(long, overrides_with = "follow")]
+    pub no_follow: bool,
+
+    /// Search only files matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files and directories matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+}
@@ -0,0 +1,15 @@
+use serde::Deserialize;
+
+// 設定ファイルに書ける、minigrep2だけの既定値。共通のキーはgrep-coreのFileConfigで読む
+//
+//     hidden = true
+//     exclude = ["target", "*.min.js"]
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalkFileConfig {
+    pub hidden: Option<bool>,
+    pub follow: Option<bool>,
+    pub no_ignore: Option<bool>,
+    // コマンドラインの--excludeに足される
+    pub exclude: Vec<String>,
+}
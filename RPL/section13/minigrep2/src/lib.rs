@@ -1,66 +1,156 @@
+use std::borrow::Cow;
 use std::error::Error;
-use std::fs::File;
-use std::env;
 use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use clap::Parser;
+use grep_core::cli::flag;
+use stream_search::LineReader;
+
+// コマンドライン引数の定義
+pub mod cli;
+// 設定ファイルに書ける、minigrep2だけの既定値
+pub mod config_file;
+// ディレクトリをたどって、検索するファイルを選ぶ
+pub mod walk;
+
+pub use cli::Cli;
+pub use config_file::WalkFileConfig;
+pub use grep_core::{
+    count_matches, format_count, format_file_lines, format_lines, new_matcher, search_lines, CaseMode, ColorMode,
+    ConfigError, FileConfig, Formatter, Line, LineKind, LineSearcher, Matcher, OutputOptions, SearchConfig,
+    SearchOptions,
+};
+pub use walk::{WalkOptions, Walker};
 
 pub struct Config {
-    pub query: String,
-    pub filename: String,
-    pub case_sensitive: bool,
+    // 何をどう探して、どう表示するか
+    pub search: SearchConfig,
+    // 検索するファイルかディレクトリ。ディレクトリは中のファイルを再帰的に検索する
+    pub paths: Vec<String>,
+    // バイナリファイルもテキストとして検索する（-a）
+    pub text: bool,
+    pub walk: WalkOptions,
 }
 
 impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-        args.next();
-        
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        };
-
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name"),
-        } ;
+    // コマンドライン引数（先頭はプログラム名）と環境変数から設定を作る
+    // 環境変数はenvで読むので、テストでは実際の環境を変えずに済む
+    //
+    // それぞれの値は、コマンドライン引数、環境変数、設定ファイル（`MINIGREP2_CONFIG`か`~/.minigrep2.toml`）、
+    // 既定値の順に優先する
+    pub fn parse<I, E>(args: I, env: E) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let cli = Cli::try_parse_from(args)?;
+        let (search, file): (_, WalkFileConfig) = SearchConfig::load("minigrep2", cli.query, &cli.search, &env)?;
 
-        // env::var関数は、環境変数がセットされていたら、環境変数の値を含むOk列挙子の成功値になるResultを返す。 
-        // 環境変数がセットされていなければ、Err列挙子を返す
-        // is_errメソッドを使用して、エラー、つまり環境変数がセットされていないことを確認している
-        // これは、大文字小文字を区別して検索することを意味している
-        // `CASE_INSENSITIVE`の値をチェックでなく、環境変数がセットされているかどうかだけを確認している
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
-
-        Ok(Config{ query, filename, case_sensitive })
+        // 設定ファイルのexcludeは、コマンドラインで指定したものと合わせて使う
+        let mut exclude = file.exclude;
+        exclude.extend(cli.exclude);
+        let walk = WalkOptions {
+            hidden: flag(cli.hidden, cli.no_hidden).or(file.hidden).unwrap_or(false),
+            no_ignore: cli.no_ignore || file.no_ignore.unwrap_or(false),
+            follow_links: flag(cli.follow, cli.no_follow).or(file.follow).unwrap_or(false),
+            include: cli.include,
+            exclude,
+        };
 
+        Ok(Config {
+            search,
+            paths: cli.paths,
+            text: cli.text,
+            walk,
+        })
     }
 }
 
 // 引数にConfigインスタンスを取る
 // Resultが`Ok`の場合は`ユニット型()`、エラーの場合は`Error`トレイトを返す
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let mut f = File::open(config.filename)?;
-    // 空のString型を作成
-    let mut contents = String::new();
-    // すべてのバイトを読み込みバッファに追加する
-    f.read_to_string(&mut contents)?;
-
-    // case_sensitiveがtrue(env::varの結果がErr(環境変数がセットされていない)かつis_err結果がtrue)ならsearch関数を実行
-    // そうでなければ、search_case_insensitiveを実行
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
-    } else {
-        search_case_insensitive(&config.query, &contents)
-    };
+    // 固定文字列か正規表現か、大文字小文字を区別するかどうかに合わせてMatcherを選ぶ
+    // 正規表現やglobが不正なら、ここでエラーを返す
+    let matcher = config.search.matcher()?;
+    let walker = Walker::new(config.walk.clone())?;
 
-    // search関数に`config.query`と`contents`の参照を引数として渡す
-    for line in results {
-        println!("{}", line);
+    // 複数のファイルを検索するときは、どのファイルの行なのかわかるようにパスを付ける
+    let show_paths = config.paths.len() > 1 || config.paths.iter().any(|path| Path::new(path).is_dir());
+
+    // 1行ごとにprintln!でロックを取らないように、標準出力をまとめて書く
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    // 読めないファイルがあっても、残りのファイルの検索は続ける
+    let mut failures = 0;
+    for path in &config.paths {
+        for file in walker.files(path) {
+            let result = match file {
+                Ok(file) => search_path(&file, show_paths, &*matcher, &config, &mut out),
+                Err(e) => Err(io::Error::other(e)),
+            };
+            match result {
+                Ok(_) => {},
+                // `| head`などで出力先が先に閉じられたら、そこで静かに止める
+                Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+                Err(e) => {
+                    eprintln!("minigrep2: {}", e);
+                    failures += 1;
+                },
+            }
+        }
+    }
+    match out.flush() {
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        result => result?,
+    }
+    if failures > 0 {
+        return Err(format!("{} files could not be searched", failures).into());
     }
 
     //`ユニット型()`を`Ok()`で包む
     Ok(())
 } 
 
+// 1つのファイル（"-"なら標準入力）を開いて検索する。エラーにはパスを付ける
+fn search_path<W: Write>(
+    path: &Path,
+    show_path: bool,
+    matcher: &dyn Matcher,
+    config: &Config,
+    out: &mut W,
+) -> io::Result<usize> {
+    let name = if stream_search::is_stdin(path) {
+        Cow::Borrowed("(standard input)")
+    } else {
+        path.to_string_lossy()
+    };
+    stream_search::open(path)
+        .and_then(|input| search_input(input, if show_path { Some(&name) } else { None }, matcher, config, out))
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))
+}
+
+// readerを1行ずつ検索し、結果をoutに書く。戻り値は条件に合った行の数
+// ファイル全体は読み込まないので、どれだけ大きなファイルでも使うメモリは変わらない
+// UTF-8として正しくないバイトは置換文字(U+FFFD)にして、検索を止めないようにする
+pub fn search_input<R: Read, W: Write>(
+    reader: R,
+    path: Option<&str>,
+    matcher: &dyn Matcher,
+    config: &Config,
+    out: &mut W,
+) -> io::Result<usize> {
+    let mut lines = LineReader::new(reader);
+    // バイナリファイルは何も表示せずに飛ばす
+    if !config.text && lines.is_binary()? {
+        return Ok(0);
+    }
+    let search = &config.search;
+    grep_core::write_matches(&mut lines, path, matcher, &search.search_options(), &search.output_options(), out)
+}
+
 // 空のベクタを返す関数
 // search関数に返される値は、search関数にcontents引数で渡されているデータと同じライフタイムを持つ
 // スライスに参照されるデータは、参照が有効になるために有効である必要がある
@@ -86,6 +176,7 @@ pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a st
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::fs;
 
     #[test]
     fn case_sensitive() {
@@ -106,7 +197,7 @@ Duct tape.";
     fn case_insensitive() {
         let query = "rUsT";
         let contents = "\
-Rust
+Rust:
 safe, fast, productive.
 Pick three.
 Trust me.";
@@ -116,4 +207,180 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn options_anywhere_around_query_and_file() {
+        let config = Config::parse(args(&["minigrep2", "-n", "to", "-C", "2", "poem.txt", "-v"]), no_env).unwrap();
+        assert_eq!((config.search.query.as_str(), config.paths.as_slice()), ("to", &[String::from("poem.txt")][..]));
+        assert!(config.search.line_numbers && config.search.invert && !config.search.regex);
+        assert_eq!((config.search.before_context, config.search.after_context), (2, 2));
+        assert!(config.search.output_options().group_separator);
+
+        let config = Config::parse(args(&["minigrep2", "-A", "1", "-C", "3", "to", "poem.txt"]), no_env).unwrap();
+        assert_eq!((config.search.before_context, config.search.after_context), (3, 1));
+
+        assert!(Config::parse(args(&["minigrep2", "to", "poem.txt", "-A"]), no_env).is_err());
+        assert!(Config::parse(args(&["minigrep2", "-o", "to"]), no_env).is_err());
+    }
+
+    #[test]
+    fn paths_and_walk_options() {
+        let config = Config::parse(
+            args(&[
+                "minigrep2", "--hidden", "to", "src", "-L", "--include", "*.rs", "README.md", "--exclude", "target",
+                "--no-ignore", "-a",
+            ]),
+            no_env,
+        )
+        .unwrap();
+        assert_eq!(config.paths, vec!["src", "README.md"]);
+        assert!(config.text);
+        assert_eq!(
+            config.walk,
+            WalkOptions {
+                hidden: true,
+                no_ignore: true,
+                follow_links: true,
+                include: vec![String::from("*.rs")],
+                exclude: vec![String::from("target")],
+            }
+        );
+        assert!(Config::parse(args(&["minigrep2", "to", "src", "--include"]), no_env).is_err());
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let config = Config::parse(args(&["minigrep2", "-c", "--", "-n", "poem.txt", "--hidden"]), no_env).unwrap();
+        assert_eq!(config.search.query, "-n");
+        assert_eq!(config.paths, vec!["poem.txt", "--hidden"]);
+        assert!(config.search.count && !config.search.line_numbers && !config.walk.hidden);
+    }
+
+    #[test]
+    fn help_and_usage_errors_come_from_clap() {
+        match Config::parse(args(&["minigrep2", "--help"]), no_env) {
+            Err(ConfigError::Args(e)) => assert_eq!(e.kind(), clap::error::ErrorKind::DisplayHelp),
+            _ => panic!("--help should be reported as an argument error"),
+        }
+        match Config::parse(args(&["minigrep2", "--colour", "to", "poem.txt"]), no_env) {
+            Err(ConfigError::Args(e)) => assert_eq!(e.kind(), clap::error::ErrorKind::UnknownArgument),
+            _ => panic!("unknown options should be rejected"),
+        }
+    }
+
+    #[test]
+    fn case_flags_and_case_insensitive_env() {
+        let insensitive = |name: &str| if name == "CASE_INSENSITIVE" { Some(String::new()) } else { None };
+        let sensitive = |argv: &[&str], env: &dyn Fn(&str) -> Option<String>| {
+            Config::parse(args(argv), env).unwrap().search.case_sensitive
+        };
+
+        assert!(sensitive(&["minigrep2", "rust", "poem.txt"], &no_env));
+        // 値が空でも、設定されていれば区別しない
+        assert!(!sensitive(&["minigrep2", "rust", "poem.txt"], &insensitive));
+        // フラグは環境変数より優先する
+        assert!(sensitive(&["minigrep2", "-s", "rust", "poem.txt"], &insensitive));
+        assert!(!sensitive(&["minigrep2", "-i", "rust", "poem.txt"], &no_env));
+        // 後に書いたフラグが勝つ
+        assert!(sensitive(&["minigrep2", "-i", "-S", "-s", "rust", "poem.txt"], &no_env));
+        assert!(!sensitive(&["minigrep2", "-s", "-i", "rust", "poem.txt"], &no_env));
+        // スマートケースは、クエリに大文字があるときだけ区別する
+        assert!(!sensitive(&["minigrep2", "-S", "rust", "poem.txt"], &insensitive));
+        assert!(sensitive(&["minigrep2", "-S", "Rust", "poem.txt"], &insensitive));
+    }
+
+    #[test]
+    fn config_file_sets_defaults_below_env_and_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("minigrep2.toml");
+        fs::write(
+            &path,
+            "case = \"smart\"\ncolor = \"always\"\nline_number = true\nhidden = true\nexclude = [\"target\"]\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let config = Config::parse(args(&["minigrep2", "--config", &path, "rust", "src"]), no_env).unwrap();
+        assert!(!config.search.case_sensitive && config.search.color && config.search.line_numbers && config.walk.hidden);
+        assert_eq!(config.walk.exclude, vec!["target"]);
+
+        // MINIGREP2_CONFIGでも指定できる。フラグと環境変数はファイルより優先し、excludeは足される
+        let env = |name: &str| match name {
+            "MINIGREP2_CONFIG" => Some(path.clone()),
+            "CASE_INSENSITIVE" => Some(String::from("1")),
+            _ => None,
+        };
+        let argv = args(&["minigrep2", "-N", "--no-hidden", "--color=never", "--exclude", "*.log", "Rust", "src"]);
+        let config = Config::parse(argv, env).unwrap();
+        assert!(!config.search.case_sensitive && !config.search.color && !config.search.line_numbers && !config.walk.hidden);
+        assert_eq!(config.walk.exclude, vec!["target", "*.log"]);
+
+        let config = Config::parse(args(&["minigrep2", "--no-config", "rust", "src"]), env).unwrap();
+        assert!(!config.search.line_numbers && config.walk.exclude.is_empty());
+
+        // ホームディレクトリの設定ファイルは、なければ読まない。書き間違えたキーはエラーにする
+        let home = dir.path().to_str().unwrap().to_string();
+        let env = |name: &str| if name == "HOME" { Some(home.clone()) } else { None };
+        assert!(Config::parse(args(&["minigrep2", "to", "src"]), env).is_ok());
+        fs::write(dir.path().join(".minigrep2.toml"), "follow_links = true\n").unwrap();
+        match Config::parse(args(&["minigrep2", "to", "src"]), env) {
+            Err(e @ ConfigError::ParseConfig { .. }) => assert!(e.to_string().contains("follow_links"), "{}", e),
+            _ => panic!("unknown keys should be rejected"),
+        }
+    }
+
+    fn search_bytes(argv: &[&str], path: Option<&str>, input: &[u8]) -> (usize, String) {
+        let config = Config::parse(args(argv), no_env).unwrap();
+        let matcher = config.search.matcher().unwrap();
+        let mut out = Vec::new();
+        let matched = search_input(input, path, &*matcher, &config, &mut out).unwrap();
+        (matched, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn binary_input_is_skipped_and_invalid_utf8_is_replaced() {
+        let binary = b"match\0\x89PNG\nmatch again";
+        assert_eq!(search_bytes(&["minigrep2", "match", "-"], None, binary), (0, String::new()));
+        assert_eq!(
+            search_bytes(&["minigrep2", "-a", "-c", "match", "-"], Some("image.bin"), binary),
+            (2, String::from("image.bin:2\n"))
+        );
+
+        // NULが先頭から離れていればテキストとして扱う
+        let mut late = vec![b'a'; stream_search::BINARY_SNIFF_LEN];
+        late.extend_from_slice(b"\nmatch\0\n");
+        assert_eq!(search_bytes(&["minigrep2", "-c", "match", "-"], None, &late).0, 1);
+
+        let latin1 = b"caf\xe9 ok\nnext\n";
+        assert_eq!(
+            search_bytes(&["minigrep2", "--color=never", "-n", "ok", "-"], Some("latin1.txt"), latin1),
+            (1, String::from("latin1.txt:1:caf\u{fffd} ok\n"))
+        );
+    }
+
+    #[test]
+    fn search_path_names_files_in_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("poem.txt");
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let config = Config::parse(args(&["minigrep2", "--color=never", "two", "poem.txt"]), no_env).unwrap();
+        let matcher = new_matcher("two", false, true).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(search_path(&path, false, &*matcher, &config, &mut out).unwrap(), 1);
+        assert_eq!(out, b"two\n");
+
+        let missing = dir.path().join("missing.txt");
+        let e = search_path(&missing, false, &*matcher, &config, &mut out).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().starts_with(&missing.to_string_lossy().into_owned()), "{}", e);
+    }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 extern crate minigrep2;
 use std::env;
 use std::process;
-use minigrep2::Config;
+use minigrep2::{Config, ConfigError};
 
 fn main() {
     // イテレータをベクタに変換
@@ -9,13 +9,18 @@ fn main() {
     //let args: Vec<String> = env::args().collect();
 
     // `env::args`はイテレータを返す。
-    // イテレータをベクタに集約して、スライスをConfig::newに渡すのではなく、env::argsから返るイテレータの所有権をConfig::newに渡している
-    // Config::newのErr値を`err`引数のクロージャに渡している
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
-        // 引数解析時に問題があった場合
-        eprintln!("Problem parsing argments: {}", err);
-        // 終了コード1でプロセスを終了する
-        process::exit(1);
+    // イテレータをベクタに集約して、スライスをConfig::parseに渡すのではなく、env::argsから返るイテレータの所有権をConfig::parseに渡している
+    // Config::parseのErr値を`err`引数のクロージャに渡している
+    // 環境変数は`env::var`で読む。テストでは別の関数を渡す
+    let config = Config::parse(env::args(), |name| env::var(name).ok()).unwrap_or_else(|err| match err {
+        // 引数の誤りや--helpは、clapが使い方を表示して終了する
+        ConfigError::Args(e) => e.exit(),
+        // 設定ファイルに問題があった場合
+        err => {
+            eprintln!("Problem parsing argments: {}", err);
+            // 終了コード1でプロセスを終了する
+            process::exit(1);
+        }
     });
 
     //println!("Searching for {}", config.query);
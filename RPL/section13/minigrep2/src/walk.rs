@@ -0,0 +1,222 @@
+use std::env;
+use std::fs;
+use std::iter;
+use std::path::PathBuf;
+
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+
+// ディレクトリをたどるときに、どのファイルを検索するか
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalkOptions {
+    // "."で始まるファイルとディレクトリも検索する（--hidden）
+    pub hidden: bool,
+    // .gitignoreや.ignoreに書かれたファイルも検索する（--no-ignore）
+    pub no_ignore: bool,
+    // たどる途中で見つけたシンボリックリンクの先も検索する（-L）
+    // 指定しなければ、コマンドラインで指定したパス以外のシンボリックリンクは飛ばす
+    pub follow_links: bool,
+    // このglobに一致するファイルだけを検索する（--include）
+    pub include: Vec<String>,
+    // このglobに一致するファイルとディレクトリは検索しない（--exclude）
+    pub exclude: Vec<String>,
+}
+
+// 検索するパスを、ファイルのパスに展開するもの
+pub struct Walker {
+    options: WalkOptions,
+    overrides: Override,
+}
+
+impl Walker {
+    // globが不正なら、ここでエラーを返す
+    pub fn new(options: WalkOptions) -> Result<Walker, ignore::Error> {
+        // "/"を含むglobは、カレントディレクトリからの相対パスとして扱う
+        let mut builder = OverrideBuilder::new(env::current_dir()?);
+        for glob in &options.include {
+            builder.add(glob)?;
+        }
+        // Overrideでは"!"で始まるglobが、一致したものを除くという意味になる
+        for glob in &options.exclude {
+            builder.add(&format!("!{}", glob))?;
+        }
+        let overrides = builder.build()?;
+        Ok(Walker { options, overrides })
+    }
+
+    // pathがファイルならそのまま、ディレクトリならその中のファイルを順にすべて返す
+    // コマンドラインで指定したファイルと標準入力の"-"は、ignoreやglobに関係なく検索する
+    pub fn files(&self, path: &str) -> Box<dyn Iterator<Item = Result<PathBuf, ignore::Error>>> {
+        if stream_search::is_stdin(path) {
+            return Box::new(iter::once(Ok(PathBuf::from(path))));
+        }
+        // fs::metadataはシンボリックリンクの先を見る
+        match fs::metadata(path) {
+            Err(e) => Box::new(iter::once(Err(ignore::Error::WithPath {
+                path: PathBuf::from(path),
+                err: Box::new(ignore::Error::from(e)),
+            }))),
+            Ok(metadata) if !metadata.is_dir() => Box::new(iter::once(Ok(PathBuf::from(path)))),
+            Ok(_) => Box::new(self.walk(path)),
+        }
+    }
+
+    fn walk(&self, dir: &str) -> impl Iterator<Item = Result<PathBuf, ignore::Error>> {
+        let ignore = !self.options.no_ignore;
+        let hidden = self.options.hidden;
+        WalkBuilder::new(dir)
+            .hidden(!self.options.hidden)
+            .parents(ignore)
+            .ignore(ignore)
+            .git_ignore(ignore)
+            .git_exclude(ignore)
+            // 利用者ごとの設定で結果が変わらないように、グローバルなgitignoreは読まない
+            .git_global(false)
+            // gitのリポジトリの外でも.gitignoreを使う
+            .require_git(false)
+            // リンクをたどるときは、ループしていればエラーとして返ってくる
+            .follow_links(self.options.follow_links)
+            .overrides(self.overrides.clone())
+            // 結果の順番が毎回同じになるように、名前順にたどる
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build()
+            .filter_map(move |entry| match entry {
+                // ディレクトリと、たどらないシンボリックリンクは飛ばす
+                // --includeのglobに一致したファイルは隠しファイルでも返ってくるので、ここで除く
+                Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => {
+                    if !hidden && entry.file_name().to_string_lossy().starts_with('.') {
+                        return None;
+                    }
+                    Some(Ok(entry.into_path()))
+                },
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    // rootからの相対パスを、"/"区切りで名前順に返す
+    fn files(root: &Path, options: WalkOptions) -> Vec<String> {
+        let walker = Walker::new(options).unwrap();
+        let mut files: Vec<String> = walker
+            .files(root.to_str().unwrap())
+            .map(|path| {
+                let path = path.unwrap();
+                let relative = path.strip_prefix(root).unwrap();
+                relative.to_str().unwrap().replace('\\', "/")
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), ".gitignore", "target/\n*.log\n");
+        write(dir.path(), "src/main.rs", "");
+        write(dir.path(), "src/lib.rs", "");
+        write(dir.path(), "src/.hidden.rs", "");
+        write(dir.path(), "notes.txt", "");
+        write(dir.path(), "debug.log", "");
+        write(dir.path(), "target/out.rs", "");
+        write(dir.path(), "src/nested/.ignore", "skip.rs\n");
+        write(dir.path(), "src/nested/skip.rs", "");
+        dir
+    }
+
+    #[test]
+    fn respects_ignore_files_and_hidden() {
+        let dir = tree();
+        assert_eq!(files(dir.path(), WalkOptions::default()), vec!["notes.txt", "src/lib.rs", "src/main.rs"]);
+
+        let options = WalkOptions { hidden: true, no_ignore: true, ..WalkOptions::default() };
+        assert_eq!(
+            files(dir.path(), options),
+            vec![
+                ".gitignore",
+                "debug.log",
+                "notes.txt",
+                "src/.hidden.rs",
+                "src/lib.rs",
+                "src/main.rs",
+                "src/nested/.ignore",
+                "src/nested/skip.rs",
+                "target/out.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let dir = tree();
+        let options = WalkOptions {
+            no_ignore: true,
+            include: vec![String::from("*.rs")],
+            exclude: vec![String::from("target"), String::from("main.*")],
+            ..WalkOptions::default()
+        };
+        assert_eq!(files(dir.path(), options), vec!["src/lib.rs", "src/nested/skip.rs"]);
+
+        assert!(Walker::new(WalkOptions { include: vec![String::from("[z-a]")], ..WalkOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn explicit_files_and_missing_paths() {
+        let dir = tree();
+        let walker = Walker::new(WalkOptions { include: vec![String::from("*.rs")], ..WalkOptions::default() }).unwrap();
+        // 指定したファイルは、ignoreやglobで除かれていても検索する
+        let log = dir.path().join("debug.log");
+        let found: Vec<_> = walker.files(log.to_str().unwrap()).map(Result::unwrap).collect();
+        assert_eq!(found, vec![log]);
+
+        let stdin: Vec<_> = walker.files("-").map(Result::unwrap).collect();
+        assert_eq!(stdin, vec![PathBuf::from("-")]);
+
+        let missing = dir.path().join("missing");
+        let mut found = walker.files(missing.to_str().unwrap());
+        assert!(found.next().unwrap().is_err());
+        assert!(found.next().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_followed_only_when_asked() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        write(outside.path(), "linked.txt", "");
+        write(dir.path(), "real.txt", "");
+        symlink(outside.path(), dir.path().join("linked_dir")).unwrap();
+        symlink(dir.path().join("real.txt"), dir.path().join("alias.txt")).unwrap();
+
+        assert_eq!(files(dir.path(), WalkOptions::default()), vec!["real.txt"]);
+        assert_eq!(
+            files(dir.path(), WalkOptions { follow_links: true, ..WalkOptions::default() }),
+            vec!["alias.txt", "linked_dir/linked.txt", "real.txt"]
+        );
+
+        // コマンドラインで指定したディレクトリへのリンクはたどる
+        let link = dir.path().join("linked_dir");
+        let walker = Walker::new(WalkOptions::default()).unwrap();
+        assert_eq!(walker.files(link.to_str().unwrap()).count(), 1);
+
+        // 自分の親を指すリンクはループとしてエラーになり、止まらなくなることはない
+        symlink(dir.path(), dir.path().join("loop")).unwrap();
+        let walker = Walker::new(WalkOptions { follow_links: true, ..WalkOptions::default() }).unwrap();
+        let results: Vec<_> = walker.files(dir.path().to_str().unwrap()).collect();
+        assert!(results.iter().any(|result| result.is_err()));
+    }
+}
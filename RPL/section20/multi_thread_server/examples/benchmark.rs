@@ -0,0 +1,154 @@
+use multi_thread_server::{Builder, ThreadPool};
+use std::env;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+// 計測するスレッドプールの共通の操作
+trait Pool: Send + Sync + 'static {
+    fn spawn<F: FnOnce() + Send + 'static>(&self, f: F);
+}
+
+impl Pool for ThreadPool {
+    fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.execute(f);
+    }
+}
+
+// 比較のための、以前の設計のスレッドプール
+// すべてのWorkerが1つのArc<Mutex<mpsc::Receiver>>を取り合ってジョブを受け取る
+struct MutexPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: mpsc::Sender<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+impl MutexPool {
+    fn new(size: usize) -> MutexPool {
+        let (sender, receiver) = mpsc::channel::<Option<Box<dyn FnOnce() + Send>>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let message = receiver.lock().unwrap().recv().unwrap();
+                    match message {
+                        Some(job) => job(),
+                        None => break,
+                    }
+                })
+            })
+            .collect();
+        MutexPool { workers, sender }
+    }
+}
+
+impl Pool for MutexPool {
+    fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.sender.send(Some(Box::new(f))).unwrap();
+    }
+}
+
+impl Drop for MutexPool {
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            self.sender.send(None).unwrap();
+        }
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+fn main() {
+    // 1つめの引数はジョブの数、2つめの引数はスレッド数
+    let mut args = env::args().skip(1);
+    let jobs = parse_arg(args.next(), 1_000_000);
+    let threads = parse_arg(
+        args.next(),
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+    );
+
+    println!("{} tiny jobs on {} threads", jobs, threads);
+    println!("{:<16} {:<10} {:>10} {:>14}", "design", "submitted", "seconds", "jobs/sec");
+
+    for &nested in &[false, true] {
+        let old = Arc::new(MutexPool::new(threads));
+        report("mutex-receiver", nested, jobs, run(&old, nested, jobs, threads));
+
+        let new = Arc::new(
+            Builder::new()
+                .min_workers(threads)
+                .max_workers(threads)
+                .log_jobs(false)
+                // スケジューラだけを比べるので、ジョブの一覧は作らない
+                .track_jobs(false)
+                .build()
+                .unwrap(),
+        );
+        report("work-stealing", nested, jobs, run(&new, nested, jobs, threads));
+    }
+}
+
+fn parse_arg(arg: Option<String>, default: usize) -> usize {
+    match arg {
+        Some(arg) => usize::from_str(&arg).unwrap_or_else(|_| {
+            eprintln!("Usage {} [jobs] [threads]", env::args().next().unwrap());
+            std::process::exit(1);
+        }),
+        None => default,
+    }
+}
+
+// ジョブを何もしないに等しい小さな処理にして、スケジューラのオーバーヘッドを計測する
+// nestedなら、プールの外からはスレッド数だけジョブを投入し、それぞれのジョブの中から残りのジョブを投入する
+fn run<P: Pool>(pool: &Arc<P>, nested: bool, jobs: usize, threads: usize) -> f64 {
+    let done = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+
+    if nested {
+        let per_thread = jobs / threads;
+        for _ in 0..threads {
+            let inner_pool = Arc::clone(pool);
+            let done = Arc::clone(&done);
+            pool.spawn(move || {
+                for _ in 0..per_thread {
+                    let done = Arc::clone(&done);
+                    inner_pool.spawn(move || {
+                        done.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+            });
+        }
+        wait_for(&done, per_thread * threads);
+    } else {
+        for _ in 0..jobs {
+            let done = Arc::clone(&done);
+            pool.spawn(move || {
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        wait_for(&done, jobs);
+    }
+
+    start.elapsed().as_secs_f64()
+}
+
+fn wait_for(done: &AtomicUsize, jobs: usize) {
+    while done.load(Ordering::Relaxed) < jobs {
+        thread::yield_now();
+    }
+}
+
+fn report(design: &str, nested: bool, jobs: usize, seconds: f64) {
+    let submitted = if nested { "in jobs" } else { "outside" };
+    println!(
+        "{:<16} {:<10} {:>10.3} {:>14.0}",
+        design,
+        submitted,
+        seconds,
+        jobs as f64 / seconds
+    );
+}
@@ -1,59 +1,100 @@
 extern crate multi_thread_server;
-use multi_thread_server::ThreadPool;
+use multi_thread_server::http::{Request, Response, Router, Server, StaticFiles, StatusCode};
+use multi_thread_server::{Builder, JobState, ThreadPool};
 
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::sync::{Arc, Weak};
 use std::thread;
 use std::time::Duration;
-// std::io::preludeをスコープに導入して、ストリームから読み書きさせてくれる特定のトレイトにアクセスできるようにしている
-use std::io::prelude::*;
-use std::net::TcpStream;
-use std::net::TcpListener;
-use std::fs::File;
 
 fn main() {
-    let listener = TcpListener::bind("172.31.80.158:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    // 待ち受けるアドレスは最初の引数で、/files/で公開するディレクトリは2番目の引数で変えられる
+    let addr = env::args().nth(1).unwrap_or_else(|| String::from("172.31.80.158:7878"));
+    let root = env::args().nth(2).unwrap_or_else(|| String::from("."));
+    // キープアライブのコネクションは次のリクエストを待つ間もWorkerを使うので、
+    // 混んできたらWorkerを増やす
+    let pool = Builder::new()
+        .min_workers(4)
+        .max_workers(32)
+        .thread_name("http-worker")
+        .build()
+        .unwrap();
+    let pool = Arc::new(pool);
+    // デバッグ用のハンドラーがプールを持ち続けないように、Weakで参照する
+    let server = Server::bind(&addr, router(Arc::downgrade(&pool), StaticFiles::new(root).listing(true))).unwrap();
 
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap();
+    // Ctrl-Cで新しいコネクションの受け付けをやめ、処理中のリクエストを待ってから終了する
+    let shutdown = server.shutdown_handle();
+    ctrlc::set_handler(move || shutdown.shutdown()).expect("failed to set the Ctrl-C handler");
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+    println!("Listening on {}", addr);
+    if let Some(report) = server.run(pool) {
+        if !report.is_complete() {
+            println!("{} connections did not finish in time.", report.unfinished_jobs);
+        }
     }
-    println!("Shutting down.");
 }
-// TcpStreamインスタンスが内部で返すデータを追いかけるため、stream引数は可変としている
-// 内部の状態が変化する可能性があるので、 mutにする必要がある
-fn handle_connection(mut stream: TcpStream) {
-    // スタックに読み取ったデータを保持するbufferをサイズ1024byteで宣言
-    let mut buffer = [0; 1024];
-    // bufferをstream.readに渡し、これがTcpStreamからbyteを読み取ってbufferに置く
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
-
-    // bufferの先頭がgetならtrue
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "../../index.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK\r\n\r\n", "../../index.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "../../404.html")
-    };
 
-    let mut file = File::open(filename).unwrap();
-    let mut contents = String::new();
+fn router(pool: Weak<ThreadPool>, files: StaticFiles) -> Router {
+    let cancel_pool = pool.clone();
+    Router::new()
+        .get("/", |_| page(StatusCode::OK, "index.html"))
+        .get("/sleep", |_| {
+            thread::sleep(Duration::from_secs(5));
+            page(StatusCode::OK, "index.html")
+        })
+        .get("/files/*path", move |request| files.serve(request, request.param("path").unwrap_or("")))
+        // 実行中・待機中のジョブの一覧
+        .get("/debug/jobs", move |_| match pool.upgrade() {
+            Some(pool) => Response::text(StatusCode::OK, jobs(&pool)),
+            None => Response::text(StatusCode::SERVICE_UNAVAILABLE, "Shutting down\n"),
+        })
+        .post("/debug/jobs/:id/cancel", move |request| cancel(&cancel_pool, request))
+        .not_found(|_| page(StatusCode::NOT_FOUND, "404.html"))
+}
 
-    file.read_to_string(&mut contents).unwrap();
+// クレートのディレクトリにあるHTMLファイルを返す
+fn page(status: StatusCode, filename: &str) -> Response {
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), filename);
+    match fs::read(&path) {
+        Ok(contents) => Response::html(status, contents),
+        Err(e) => {
+            println!("Failed to read {}: {}", path, e);
+            Response::text(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error\n")
+        },
+    }
+}
 
-    // 成功したメッセージのデータを保持するresponse変数を定義
-    let response = format!("{}{}", status_line, contents);
+fn jobs(pool: &ThreadPool) -> String {
+    let mut out = String::new();
+    for job in pool.jobs() {
+        let state = match job.state {
+            JobState::Queued => String::from("queued"),
+            JobState::Running { worker, elapsed } => format!("running on worker {} for {:?}", worker, elapsed),
+        };
+        let _ = writeln!(
+            out,
+            "{}\t{}\twaited {:?}\t{}{}",
+            job.id,
+            job.name.as_deref().unwrap_or("-"),
+            job.waited,
+            state,
+            if job.cancelled { "\tcancelled" } else { "" }
+        );
+    }
+    out
+}
 
-    // as_bytesを呼び出し、文字列データをバイトに変換
-    // streamのwriteメソッドは、 &[u8]を取り、コネクションに直接そのバイトを送信
-    stream.write(response.as_bytes()).unwrap();
-    // flushは待機し、 バイトが全てコネクションに書き込まれるまでプログラムが継続するのを防ぐ
-    stream.flush().unwrap();
-}
\ No newline at end of file
+fn cancel(pool: &Weak<ThreadPool>, request: &Request) -> Response {
+    let id = match request.param("id").and_then(|id| id.parse::<u64>().ok()) {
+        Some(id) => id,
+        None => return Response::text(StatusCode::BAD_REQUEST, "Invalid job id\n"),
+    };
+    match pool.upgrade() {
+        Some(pool) if pool.cancel(id.into()) => Response::text(StatusCode::OK, "Cancelled\n"),
+        Some(_) => Response::text(StatusCode::NOT_FOUND, "No such job\n"),
+        None => Response::text(StatusCode::SERVICE_UNAVAILABLE, "Shutting down\n"),
+    }
+}
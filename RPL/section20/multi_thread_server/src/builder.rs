@@ -0,0 +1,184 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use super::{OverflowPolicy, ThreadPool};
+
+/// Workerの数やスレッドの設定を指定してThreadPoolを生成するビルダー。
+///
+/// プールは最初にmin_workers個のWorkerを生成します。ジョブを投入したときに
+/// 空いているWorkerがなければ、max_workers個までWorkerを増やします。
+/// 何もせずにidle_timeoutが経過したWorkerは、min_workers個になるまで1つずつ終了します。
+///
+/// ```
+/// use std::time::Duration;
+/// use multi_thread_server::Builder;
+///
+/// let pool = Builder::new()
+///     .min_workers(2)
+///     .max_workers(8)
+///     .idle_timeout(Duration::from_secs(30))
+///     .thread_name("http-worker")
+///     .build()
+///     .unwrap();
+/// pool.execute(|| println!("hello"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    pub(crate) min_workers: usize,
+    pub(crate) max_workers: usize,
+    pub(crate) idle_timeout: Duration,
+    pub(crate) thread_name: Option<String>,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) overflow_policy: OverflowPolicy,
+    pub(crate) log_jobs: bool,
+    pub(crate) track_jobs: bool,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            min_workers: 1,
+            // CPUの数がわからなければ4つまで
+            max_workers: thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            idle_timeout: Duration::from_secs(60),
+            thread_name: None,
+            stack_size: None,
+            queue_capacity: None,
+            overflow_policy: OverflowPolicy::Block,
+            log_jobs: true,
+            track_jobs: true,
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    // 常に待機させておくWorkerの数
+    pub fn min_workers(mut self, min_workers: usize) -> Builder {
+        self.min_workers = min_workers;
+        self
+    }
+
+    // 同時に動かすWorkerの最大数
+    pub fn max_workers(mut self, max_workers: usize) -> Builder {
+        self.max_workers = max_workers;
+        self
+    }
+
+    // min_workersを超えるWorkerは、この時間ジョブがなければ終了する
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Builder {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    // Workerスレッドの名前。"{name}-{WorkerのID}"という名前になる
+    pub fn thread_name<S: Into<String>>(mut self, name: S) -> Builder {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    // Workerスレッドのスタックサイズ（バイト）
+    pub fn stack_size(mut self, stack_size: usize) -> Builder {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    // キューに溜められるジョブの数。指定しなければ制限しない
+    pub fn queue_capacity(mut self, capacity: usize) -> Builder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    // キューが満杯のときにジョブを投入したらどうするか
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Builder {
+        self.overflow_policy = policy;
+        self
+    }
+
+    // ジョブを受け取るたびに"Worker {} got a job; executing."と表示するか
+    // 小さなジョブを大量に実行するときは、表示しないようにする
+    pub fn log_jobs(mut self, log_jobs: bool) -> Builder {
+        self.log_jobs = log_jobs;
+        self
+    }
+
+    // ThreadPool::jobsで見られるように、まだ終わっていないジョブを一覧にしておくか
+    // 一覧にしないと、ThreadPool::jobsは空になり、ThreadPool::cancelでジョブをキャンセルできない
+    // ごく小さなジョブを大量に実行するときは、一覧にしないほうが速い
+    pub fn track_jobs(mut self, track_jobs: bool) -> Builder {
+        self.track_jobs = track_jobs;
+        self
+    }
+
+    pub fn build(self) -> Result<ThreadPool, BuildError> {
+        if self.max_workers == 0 {
+            return Err(BuildError::NoWorkers);
+        }
+        if self.min_workers > self.max_workers {
+            return Err(BuildError::MinExceedsMax {
+                min_workers: self.min_workers,
+                max_workers: self.max_workers,
+            });
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(BuildError::ZeroQueueCapacity);
+        }
+        ThreadPool::from_builder(self).map_err(BuildError::Spawn)
+    }
+
+    // WorkerのIDからスレッドを生成するためのthread::Builderを作る
+    pub(crate) fn thread_builder(&self, id: usize) -> thread::Builder {
+        let mut builder = thread::Builder::new();
+        if let Some(name) = &self.thread_name {
+            builder = builder.name(format!("{}-{}", name, id));
+        }
+        if let Some(stack_size) = self.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        builder
+    }
+}
+
+/// ThreadPoolを生成できなかった理由
+#[derive(Debug)]
+pub enum BuildError {
+    /// max_workersが0だった
+    NoWorkers,
+    /// min_workersがmax_workersより大きかった
+    MinExceedsMax { min_workers: usize, max_workers: usize },
+    /// queue_capacityが0だった
+    ZeroQueueCapacity,
+    /// Workerスレッドを生成できなかった
+    Spawn(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::NoWorkers => write!(f, "max_workers must be greater than 0"),
+            BuildError::MinExceedsMax { min_workers, max_workers } => write!(
+                f,
+                "min_workers ({}) must not exceed max_workers ({})",
+                min_workers, max_workers
+            ),
+            BuildError::ZeroQueueCapacity => write!(f, "queue_capacity must be greater than 0"),
+            BuildError::Spawn(e) => write!(f, "failed to spawn a worker thread: {}", e),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::Spawn(e) => Some(e),
+            _ => None,
+        }
+    }
+}
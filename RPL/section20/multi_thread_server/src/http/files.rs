@@ -0,0 +1,504 @@
+use std::fmt::Write as _;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{date, percent_encode, Request, Response, StatusCode};
+
+// ディレクトリへのリクエストで、代わりに返すファイル
+const INDEX_FILE: &str = "index.html";
+// これより大きな範囲はメモリに読み込まず、レスポンスを書き出すときにファイルから少しずつ送る
+const MAX_BUFFERED: u64 = 64 * 1024;
+
+/// rootディレクトリの下にあるファイルを返すハンドラー。
+///
+/// ルートの"*name"で取り出したパスと組み合わせて使います。
+/// rootの外を指すパス（".."やrootの外へのシンボリックリンク）には403を返します。
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use multi_thread_server::http::{Router, StaticFiles};
+///
+/// let files = Arc::new(StaticFiles::new("docs").listing(true));
+/// let router = Router::new().get("/docs/*path", move |request| {
+///     files.serve(request, request.param("path").unwrap_or(""))
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    listing: bool,
+}
+
+// Rangeヘッダーで指定された範囲
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    // 最初と最後のバイトの位置
+    Satisfiable(u64, u64),
+    // ファイルの外を指している
+    Unsatisfiable,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            listing: false,
+        }
+    }
+
+    // index.htmlのないディレクトリで、ファイルの一覧を返すか。falseなら403を返す
+    pub fn listing(mut self, listing: bool) -> StaticFiles {
+        self.listing = listing;
+        self
+    }
+
+    /// rootからの相対パスpathにあるファイルを返す。
+    ///
+    /// Rangeヘッダーがあれば指定された範囲だけを206で返し、If-None-MatchやIf-Modified-Sinceが
+    /// ファイルと一致すれば304を返します。ディレクトリならindex.htmlか一覧を返します。
+    pub fn serve(&self, request: &Request, path: &str) -> Response {
+        let resolved = match self.resolve(path) {
+            Ok(resolved) => resolved,
+            Err(status) => return error(status),
+        };
+        let metadata = match fs::metadata(&resolved) {
+            Ok(metadata) => metadata,
+            Err(e) => return error(io_status(&e)),
+        };
+        if !metadata.is_dir() {
+            return serve_file(request, &resolved, &metadata);
+        }
+
+        // 一覧の相対リンクがずれないように、ディレクトリのURLは'/'で終わらせる
+        if !request.path.ends_with('/') {
+            let mut location = percent_encode(&request.path) + "/";
+            if let Some(query) = &request.query {
+                location = location + "?" + query;
+            }
+            return Response::text(StatusCode::MOVED_PERMANENTLY, "Moved Permanently\n").with_header("Location", location);
+        }
+        let index = resolved.join(INDEX_FILE);
+        match fs::metadata(&index) {
+            Ok(metadata) if metadata.is_file() => serve_file(request, &index, &metadata),
+            _ if self.listing => list_directory(request, &resolved, path.trim_matches('/').is_empty()),
+            _ => error(StatusCode::FORBIDDEN),
+        }
+    }
+
+    // pathをrootの下の実際のパスにする。シンボリックリンクをたどった先もrootの下になければエラー
+    fn resolve(&self, path: &str) -> Result<PathBuf, StatusCode> {
+        let mut resolved = self.root.clone();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return Err(StatusCode::FORBIDDEN),
+                // Windowsの区切り文字やドライブ名で、rootの外に出られないようにする
+                _ if segment.contains(['\\', ':', '\0']) => return Err(StatusCode::BAD_REQUEST),
+                _ => resolved.push(segment),
+            }
+        }
+
+        let root = self.root.canonicalize().map_err(|_| StatusCode::NOT_FOUND)?;
+        let resolved = resolved.canonicalize().map_err(|e| io_status(&e))?;
+        if !resolved.starts_with(&root) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(resolved)
+    }
+}
+
+fn serve_file(request: &Request, path: &Path, metadata: &Metadata) -> Response {
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    // 大きさと更新日時が同じなら、同じ内容とみなす
+    let mtime = modified.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_nanos());
+    let etag = format!("\"{:x}-{:x}\"", len, mtime);
+
+    let mut headers = Response::new(StatusCode::OK).with_header("ETag", etag.as_str());
+    if let Some(modified) = modified {
+        headers = headers.with_header("Last-Modified", date::format(modified));
+    }
+    if not_modified(request, &etag, modified) {
+        headers.status = StatusCode::NOT_MODIFIED;
+        return headers;
+    }
+
+    let mut response = headers
+        .with_header("Content-Type", mime_type(path))
+        .with_header("Accept-Ranges", "bytes");
+    let range = match request.header("Range") {
+        Some(range) if if_range_matches(request, &etag, modified) => parse_range(range, len),
+        _ => None,
+    };
+    let (start, end) = match range {
+        Some(ByteRange::Satisfiable(start, end)) => {
+            response.status = StatusCode::PARTIAL_CONTENT;
+            response.headers.insert("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            (start, end + 1)
+        },
+        Some(ByteRange::Unsatisfiable) => {
+            return Response::text(StatusCode::RANGE_NOT_SATISFIABLE, "Range Not Satisfiable\n")
+                .with_header("Content-Range", format!("bytes */{}", len));
+        },
+        None => (0, len),
+    };
+
+    if end - start > MAX_BUFFERED {
+        // 開けないファイルは、ヘッダーを送る前にここでエラーにする
+        return match File::open(path) {
+            Ok(_) => response.with_file(path, start, end - start),
+            Err(e) => error(io_status(&e)),
+        };
+    }
+    match read_range(path, start, end) {
+        Ok(body) => response.with_body(body),
+        Err(e) => error(io_status(&e)),
+    }
+}
+
+fn read_range(path: &Path, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut body = Vec::with_capacity((end - start) as usize);
+    file.take(end - start).read_to_end(&mut body)?;
+    Ok(body)
+}
+
+// 条件付きリクエストが、手元のファイルと同じものを指しているか
+// If-None-Matchがあれば、If-Modified-Sinceは見ない
+fn not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = request.header("If-None-Match") {
+        // 弱いETagとして比べる
+        return tags
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    match (request.header("If-Modified-Since").and_then(date::parse), modified) {
+        (Some(since), Some(modified)) => truncate_to_secs(modified) <= since,
+        _ => false,
+    }
+}
+
+// If-Rangeがなければtrue。あれば、ETagか更新日時が一致するときだけ範囲を返す
+fn if_range_matches(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    match request.header("If-Range").map(str::trim) {
+        None => true,
+        Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => tag == etag,
+        Some(since) => date::parse(since).is_some_and(|since| modified.map(truncate_to_secs) == Some(since)),
+    }
+}
+
+// HTTP-dateは秒までしか表せない
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    UNIX_EPOCH + std::time::Duration::from_secs(time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()))
+}
+
+// "bytes=0-99"、"bytes=100-"、"bytes=-100"の形式を読む。
+// 読めないものと複数の範囲の指定は無視して、ファイル全体を返す
+fn parse_range(value: &str, len: u64) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+
+    // 末尾から数えたバイト数
+    if first.is_empty() {
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        return Some(ByteRange::Satisfiable(len.saturating_sub(suffix), len - 1));
+    }
+
+    let start: u64 = first.parse().ok()?;
+    let end = if last.is_empty() { None } else { Some(last.parse::<u64>().ok()?) };
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+    if start >= len {
+        return Some(ByteRange::Unsatisfiable);
+    }
+    Some(ByteRange::Satisfiable(start, end.map_or(len - 1, |end| end.min(len - 1))))
+}
+
+// ディレクトリの中身をHTMLの一覧にする。ディレクトリを先に、名前の順に並べる
+fn list_directory(request: &Request, dir: &Path, is_root: bool) -> Response {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return error(io_status(&e)),
+    };
+    let mut entries: Vec<(bool, String)> = entries
+        .filter_map(Result::ok)
+        .map(|entry| {
+            let is_dir = entry.path().is_dir();
+            (!is_dir, entry.file_name().to_string_lossy().into_owned())
+        })
+        .collect();
+    entries.sort();
+
+    let title = escape_html(&request.path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    // rootより上へのリンクは出さない
+    if !is_root {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let slash = if is_file { "" } else { "/" };
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{}{}\">{}{}</a></li>",
+            percent_encode(&name),
+            slash,
+            escape_html(&name),
+            slash
+        );
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Response::html(StatusCode::OK, html)
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// 拡張子からContent-Typeを決める。知らない拡張子はバイナリとして返す
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" | "md" | "rs" | "toml" | "log" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        _ => "application/octet-stream",
+    }
+}
+
+fn io_status(e: &io::Error) -> StatusCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn error(status: StatusCode) -> Response {
+    Response::text(status, format!("{}\n", status.reason()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Method, Version};
+    use std::process;
+
+    // テストごとに別のディレクトリを作る
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("multi_thread_server-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs/a b")).unwrap();
+        fs::write(root.join("index.html"), "<h1>home</h1>").unwrap();
+        fs::write(root.join("docs/guide.txt"), "0123456789").unwrap();
+        fs::write(root.join("docs/<script>.txt"), "").unwrap();
+        fs::write(root.join("logo.PNG"), [0x89, b'P', b'N', b'G', 0xff, 0x00]).unwrap();
+        root
+    }
+
+    fn get(path: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request {
+            method: Method::Get,
+            target: path.to_string(),
+            path: path.to_string(),
+            query: None,
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        };
+        for &(name, value) in headers {
+            request.headers.insert(name, value);
+        }
+        request
+    }
+
+    // /files/*pathにマウントしたときのように、先頭の"/files/"を除いて渡す
+    fn serve(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> Response {
+        let request = get(path, headers);
+        files.serve(&request, path.strip_prefix("/files").unwrap().trim_start_matches('/'))
+    }
+
+    #[test]
+    fn serves_text_and_binary_files() {
+        let files = StaticFiles::new(root("types"));
+        let response = serve(&files, "/files/docs/guide.txt", &[]);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, b"0123456789");
+        assert_eq!(response.headers.get("Content-Type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+        assert!(response.headers.contains("Last-Modified"));
+
+        let response = serve(&files, "/files/logo.PNG", &[]);
+        assert_eq!(response.body, [0x89, b'P', b'N', b'G', 0xff, 0x00]);
+        assert_eq!(response.headers.get("Content-Type"), Some("image/png"));
+
+        assert_eq!(serve(&files, "/files/missing.txt", &[]).status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn streams_large_files() {
+        let root = root("large");
+        let data: Vec<u8> = (0..MAX_BUFFERED * 3).map(|i| (i % 251) as u8).collect();
+        fs::write(root.join("big.bin"), &data).unwrap();
+        let files = StaticFiles::new(&root);
+        let written = |response: &Response, head_only: bool| {
+            let mut out = Vec::new();
+            response.write_to(&mut out, head_only).unwrap();
+            out
+        };
+
+        // 大きなファイルは読み込まずに、書き出すときに送る
+        let response = serve(&files, "/files/big.bin", &[]);
+        assert!(response.body.is_empty());
+        assert_eq!(response.file.as_ref().map(|file| (file.start, file.len)), Some((0, data.len() as u64)));
+        let out = written(&response, false);
+        assert!(out.ends_with(&data));
+        let head = written(&response, true);
+        assert!(String::from_utf8(head).unwrap().contains(&format!("Content-Length: {}\r\n", data.len())));
+
+        let start = MAX_BUFFERED / 2;
+        let response = serve(&files, "/files/big.bin", &[("Range", &format!("bytes={}-", start))]);
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert!(written(&response, false).ends_with(&data[start as usize..]));
+
+        // 送るまでにファイルが短くなったら、足りないまま終わらせずにエラーにする
+        fs::write(root.join("big.bin"), &data[..10]).unwrap();
+        assert_eq!(response.write_to(&mut Vec::new(), false).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn stays_inside_root() {
+        let root = root("traversal");
+        let files = StaticFiles::new(root.join("docs"));
+        assert_eq!(serve(&files, "/files/../index.html", &[]).status, StatusCode::FORBIDDEN);
+        assert_eq!(serve(&files, "/files/a b/../../index.html", &[]).status, StatusCode::FORBIDDEN);
+        assert_eq!(serve(&files, "/files/..\\index.html", &[]).status, StatusCode::BAD_REQUEST);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("index.html"), root.join("docs/escape.html")).unwrap();
+            assert_eq!(serve(&files, "/files/escape.html", &[]).status, StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[test]
+    fn serves_byte_ranges() {
+        let files = StaticFiles::new(root("ranges"));
+        let range = |value: &str| serve(&files, "/files/docs/guide.txt", &[("Range", value)]);
+
+        let response = range("bytes=2-4");
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.body, b"234");
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(range("bytes=7-").body, b"789");
+        assert_eq!(range("bytes=-3").body, b"789");
+        assert_eq!(range("bytes=8-100").body, b"89");
+
+        let response = range("bytes=10-");
+        assert_eq!(response.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */10"));
+
+        // 読めない指定と複数の範囲は無視する
+        assert_eq!(range("bytes=5-2").status, StatusCode::OK);
+        assert_eq!(range("bytes=0-1,4-5").body, b"0123456789");
+        assert_eq!(range("lines=1-2").status, StatusCode::OK);
+
+        // If-RangeのETagが古ければ、ファイル全体を返す
+        let response = serve(&files, "/files/docs/guide.txt", &[("Range", "bytes=0-0"), ("If-Range", "\"old\"")]);
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn answers_conditional_requests() {
+        let files = StaticFiles::new(root("conditional"));
+        let response = serve(&files, "/files/docs/guide.txt", &[]);
+        let etag = response.headers.get("ETag").unwrap();
+        let modified = response.headers.get("Last-Modified").unwrap();
+
+        let response = serve(&files, "/files/docs/guide.txt", &[("If-None-Match", &format!("\"x\", {}", etag))]);
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers.get("ETag"), Some(etag));
+        let response = serve(&files, "/files/docs/guide.txt", &[("If-Modified-Since", modified)]);
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+
+        let old = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(serve(&files, "/files/docs/guide.txt", &[("If-Modified-Since", old)]).status, StatusCode::OK);
+        // If-None-Matchがあれば、If-Modified-Sinceは見ない
+        let headers = [("If-None-Match", "\"x\""), ("If-Modified-Since", modified)];
+        assert_eq!(serve(&files, "/files/docs/guide.txt", &headers).status, StatusCode::OK);
+
+        let headers = [("Range", "bytes=0-0"), ("If-Range", etag)];
+        assert_eq!(serve(&files, "/files/docs/guide.txt", &headers).body, b"0");
+    }
+
+    #[test]
+    fn serves_directories() {
+        let files = StaticFiles::new(root("directories"));
+        let response = serve(&files, "/files/docs", &[]);
+        assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers.get("Location"), Some("/files/docs/"));
+
+        // index.htmlがあれば返す
+        assert_eq!(serve(&files, "/files/", &[]).body, b"<h1>home</h1>");
+        assert_eq!(serve(&files, "/files/docs/", &[]).status, StatusCode::FORBIDDEN);
+
+        let files = files.listing(true);
+        fs::remove_file(files.root.join("index.html")).unwrap();
+        assert!(!String::from_utf8(serve(&files, "/files/", &[]).body).unwrap().contains("../"));
+        let listing = String::from_utf8(serve(&files, "/files/docs/", &[]).body).unwrap();
+        assert!(listing.contains("<a href=\"../\">../</a>"));
+        let dir = listing.find("<a href=\"a%20b/\">a b/</a>").unwrap();
+        let escaped = listing.find("<a href=\"%3Cscript%3E.txt\">&lt;script&gt;.txt</a>").unwrap();
+        let file = listing.find("<a href=\"guide.txt\">guide.txt</a>").unwrap();
+        assert!(dir < escaped && escaped < file);
+    }
+}
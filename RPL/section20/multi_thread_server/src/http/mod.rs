@@ -0,0 +1,229 @@
+//! HTTP/1.1のリクエストの解析、レスポンスの書き出し、パスによる振り分け。
+
+use std::fmt;
+
+// HTTP-dateの書き出しと読み込み
+mod date;
+// ディレクトリの下のファイルを返すハンドラー
+mod files;
+// リクエストラインとヘッダー、Content-Lengthとchunkedのボディの解析
+mod request;
+// ステータスコードとレスポンスの書き出し
+mod response;
+// メソッドとパスのパターンによるハンドラーの振り分け
+mod router;
+// キープアライブとタイムアウト付きのコネクションの処理、サーバーの終了
+mod server;
+
+pub use files::StaticFiles;
+pub use request::{Limits, ParseError, Request};
+pub use response::{FileBody, Response, StatusCode};
+pub use router::Router;
+pub use server::{serve_connection, ConnectionConfig, Server, ShutdownHandle};
+
+/// リクエストメソッド
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    /// 上記以外の拡張メソッド
+    Other(String),
+}
+
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Other(method) => method,
+        }
+    }
+
+    // メソッド名は大文字と小文字を区別する
+    fn parse(method: &str) -> Method {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            other => Method::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// HTTPのバージョン。HTTP/1.0とHTTP/1.1だけを扱います。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// ヘッダーの一覧。名前は大文字と小文字を区別せずに比べ、受け取った順序を保ちます。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    // nameのヘッダーのうち最初のものの値
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(entry, _)| entry.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // 同じ名前のヘッダーを置き換える
+    pub fn insert<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    // 同じ名前のヘッダーがあっても、さらに加える
+    pub fn append<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(entry, _)| !entry.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // カンマ区切りのヘッダーの値に、tokenが含まれているか（Connection: keep-alive, Upgradeなど）
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+}
+
+// %XXをバイトに戻す。plusがtrueなら'+'を空白にする（クエリ文字列）
+// 不正な%は、そのまま残す
+pub(crate) fn percent_decode(s: &str, plus: bool) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(&[high, low]) if bytes[i] == b'%' => hex(high).zip(hex(low)),
+            _ => None,
+        };
+        match (escaped, bytes[i]) {
+            (Some((high, low)), _) => {
+                decoded.push(high * 16 + low);
+                i += 3;
+                continue;
+            },
+            (None, b'+') if plus => decoded.push(b' '),
+            (None, byte) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// パスに使えない文字を%XXにする。'/'はそのまま残す
+pub(crate) fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for &byte in s.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.append("Accept", "text/html");
+        headers.append("accept", "text/plain");
+        headers.insert("Connection", "keep-alive, Upgrade");
+        assert_eq!(headers.get("ACCEPT"), Some("text/html"));
+        assert_eq!(headers.get_all("accept").count(), 2);
+        assert!(headers.has_token("connection", "upgrade"));
+
+        headers.insert("ACCEPT", "*/*");
+        assert_eq!(headers.get_all("accept").collect::<Vec<_>>(), vec!["*/*"]);
+        assert_eq!(headers.len(), 2);
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("/a%20b/%E3%81%82", false), "/a b/あ");
+        assert_eq!(percent_decode("a+b%2", true), "a b%2");
+        assert_eq!(percent_decode("%zz", false), "%zz");
+        assert_eq!(percent_encode("/a b/あ?"), "/a%20b/%E3%81%82%3F");
+        assert_eq!(percent_decode(&percent_encode("/a b/%"), false), "/a b/%");
+    }
+}
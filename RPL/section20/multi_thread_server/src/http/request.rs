@@ -0,0 +1,531 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use super::{percent_decode, Headers, Method, Response, StatusCode, Version};
+
+/// リクエストの大きさの上限。超えたリクエストには4xxを返します。
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// リクエストラインとヘッダーの1行の長さ（バイト）
+    pub max_line: usize,
+    /// ヘッダーの数
+    pub max_headers: usize,
+    /// ボディの長さ（バイト）。chunkedならすべてのチャンクの合計
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_line: 8 * 1024,
+            max_headers: 100,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+/// 解析したリクエスト
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    /// リクエストラインに書かれたままのターゲット（"/search?q=rust"など）
+    pub target: String,
+    /// ターゲットのパスの部分。%XXはデコード済み
+    pub path: String,
+    /// ターゲットの'?'より後ろ。デコードしていない
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// ルーターがパスのパターンから取り出した値（"/users/:id"のidなど）
+    pub params: Vec<(String, String)>,
+}
+
+impl Request {
+    /// リクエストラインとヘッダー、ボディをまとめて読む。
+    ///
+    /// 何も読まないうちにコネクションが閉じられたら`None`を返します。
+    pub fn read<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Option<Request>, ParseError> {
+        let mut request = match Request::read_head(reader, limits)? {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+        request.read_body(reader, limits)?;
+        Ok(Some(request))
+    }
+
+    /// リクエストラインとヘッダーだけを読む。ボディは`read_body`で読みます。
+    pub fn read_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Option<Request>, ParseError> {
+        // リクエストの前の空行は読み飛ばす
+        let line = loop {
+            match read_line(reader, limits.max_line, ParseError::UriTooLong)? {
+                None => return Ok(None),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+        let (method, target, version) = parse_request_line(&line)?;
+        let (path, query) = split_target(&method, &target)?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_line(reader, limits.max_line, ParseError::HeadersTooLarge)?
+                .ok_or_else(unexpected_eof)?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            let (name, value) = parse_header(&line)?;
+            headers.append(name, value);
+        }
+
+        // HTTP/1.1ではHostヘッダーが必須
+        if version == Version::Http11 && headers.get_all("Host").count() != 1 {
+            return Err(ParseError::BadRequest("missing or duplicate Host header"));
+        }
+
+        Ok(Some(Request {
+            method,
+            target,
+            path,
+            query,
+            version,
+            headers,
+            body: Vec::new(),
+            params: Vec::new(),
+        }))
+    }
+
+    /// ヘッダーに従ってボディを読む。
+    ///
+    /// Transfer-Encoding: chunkedならチャンクを、そうでなければContent-Lengthのバイト数を読みます。
+    /// どちらもなければボディはありません。
+    pub fn read_body<R: BufRead>(&mut self, reader: &mut R, limits: &Limits) -> Result<(), ParseError> {
+        let (chunked, length) = self.framing(limits)?;
+        self.body = match (chunked, length) {
+            (true, _) => read_chunked(reader, limits)?,
+            (false, Some(length)) => {
+                let mut body = vec![0; length as usize];
+                reader.read_exact(&mut body).map_err(eof_is_io)?;
+                body
+            },
+            (false, None) => Vec::new(),
+        };
+        Ok(())
+    }
+
+    /// ボディを読む前に、ヘッダーからわかる形式と大きさを確かめる。
+    ///
+    /// `read_body`が読み始める前に返すエラーと同じものを、ボディを受け取らずに返します。
+    pub fn check_body(&self, limits: &Limits) -> Result<(), ParseError> {
+        self.framing(limits).map(|_| ())
+    }
+
+    // chunkedか、Content-Lengthのバイト数か
+    fn framing(&self, limits: &Limits) -> Result<(bool, Option<u64>), ParseError> {
+        let chunked = self.chunked()?;
+        let length = self.content_length()?;
+        match (chunked, length) {
+            // 両方あると、前にあるプロキシとボディの終わりの解釈がずれるおそれがある
+            (true, Some(_)) => Err(ParseError::BadRequest("both Content-Length and Transfer-Encoding")),
+            (false, Some(length)) if length > limits.max_body as u64 => Err(ParseError::BodyTooLarge),
+            _ => Ok((chunked, length)),
+        }
+    }
+
+    // Transfer-Encoding: chunkedか。並んだヘッダーもカンマで区切った値もすべて見て、chunkedだけのときに限る
+    // 最初の値しか見ないと、最後の値を見るプロキシとボディの終わりの解釈がずれる
+    fn chunked(&self) -> Result<bool, ParseError> {
+        let mut codings = self.headers.get_all("Transfer-Encoding").flat_map(|value| value.split(','));
+        match (codings.next(), codings.next()) {
+            (None, _) => Ok(false),
+            (Some(coding), None) if coding.trim().eq_ignore_ascii_case("chunked") => Ok(true),
+            _ => Err(ParseError::NotImplemented("unsupported transfer coding")),
+        }
+    }
+
+    // Content-Lengthの値。同じ値が並んでいるのは許す
+    fn content_length(&self) -> Result<Option<u64>, ParseError> {
+        let mut length = None;
+        for value in self.headers.get_all("Content-Length").flat_map(|value| value.split(',')) {
+            let value = value.trim();
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::BadRequest("invalid Content-Length"));
+            }
+            // 桁が多すぎて表せなければ、上限を超えている
+            let parsed = value.parse().map_err(|_| ParseError::BodyTooLarge)?;
+            if length.is_some() && length != Some(parsed) {
+                return Err(ParseError::BadRequest("conflicting Content-Length"));
+            }
+            length = Some(parsed);
+        }
+        Ok(length)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    // パスのパターンから取り出した値
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    // クエリ文字列のnameの値。デコードして返す
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .find(|(key, _)| percent_decode(key, true) == name)
+            .map(|(_, value)| percent_decode(value, true))
+    }
+
+    // ボディを送る前に100 Continueを待っているか
+    // 100-continue以外のExpectには応えられないのでエラーにする。HTTP/1.0のExpectは無視する
+    pub fn expects_continue(&self) -> Result<bool, ParseError> {
+        match self.headers.get("Expect") {
+            _ if self.version != Version::Http11 => Ok(false),
+            None => Ok(false),
+            Some(expect) if expect.eq_ignore_ascii_case("100-continue") => Ok(true),
+            Some(_) => Err(ParseError::ExpectationFailed),
+        }
+    }
+}
+
+/// リクエストを解析できなかった理由
+#[derive(Debug)]
+pub enum ParseError {
+    /// 読み込みに失敗したか、リクエストの途中でコネクションが閉じられた
+    Io(io::Error),
+    /// リクエストの形式が正しくない
+    BadRequest(&'static str),
+    /// リクエストラインが長すぎる
+    UriTooLong,
+    /// ヘッダーが多すぎるか、1行が長すぎる
+    HeadersTooLarge,
+    /// ボディが`Limits::max_body`より大きい
+    BodyTooLarge,
+    /// HTTP/1.0とHTTP/1.1以外のバージョン
+    VersionNotSupported,
+    /// 対応していないTransfer-Encodingなど
+    NotImplemented(&'static str),
+    /// 100-continue以外のExpect
+    ExpectationFailed,
+}
+
+impl ParseError {
+    /// クライアントに返すステータスコード。`Io`ならレスポンスを返せないので`None`
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ParseError::Io(_) => None,
+            ParseError::BadRequest(_) => Some(StatusCode::BAD_REQUEST),
+            ParseError::UriTooLong => Some(StatusCode::URI_TOO_LONG),
+            ParseError::HeadersTooLarge => Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            ParseError::BodyTooLarge => Some(StatusCode::PAYLOAD_TOO_LARGE),
+            ParseError::VersionNotSupported => Some(StatusCode::HTTP_VERSION_NOT_SUPPORTED),
+            ParseError::NotImplemented(_) => Some(StatusCode::NOT_IMPLEMENTED),
+            ParseError::ExpectationFailed => Some(StatusCode::EXPECTATION_FAILED),
+        }
+    }
+
+    // クライアントに返すレスポンス
+    pub(crate) fn to_response(&self) -> Response {
+        let status = self.status().unwrap_or(StatusCode::BAD_REQUEST);
+        Response::text(status, format!("{}\n", self))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "failed to read the request: {}", e),
+            ParseError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            ParseError::UriTooLong => write!(f, "request line is too long"),
+            ParseError::HeadersTooLarge => write!(f, "request headers are too large"),
+            ParseError::BodyTooLarge => write!(f, "request body is too large"),
+            ParseError::VersionNotSupported => write!(f, "HTTP version is not supported"),
+            ParseError::NotImplemented(reason) => write!(f, "not implemented: {}", reason),
+            ParseError::ExpectationFailed => write!(f, "only 100-continue is supported in Expect"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+fn unexpected_eof() -> ParseError {
+    ParseError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed in the middle of a request"))
+}
+
+fn eof_is_io(e: io::Error) -> ParseError {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        unexpected_eof()
+    } else {
+        ParseError::Io(e)
+    }
+}
+
+// 改行までの1行を、改行を除いて読む。何も読まずにEOFならNone
+// max_lineバイトを超えたらtoo_longを返す
+fn read_line<R: BufRead>(reader: &mut R, max_line: usize, too_long: ParseError) -> Result<Option<Vec<u8>>, ParseError> {
+    let mut line = Vec::new();
+    // 改行の2バイトの分だけ多く読めるようにする
+    let limit = max_line as u64 + 2;
+    reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return if line.len() as u64 == limit {
+            Err(too_long)
+        } else {
+            Err(unexpected_eof())
+        };
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > max_line {
+        return Err(too_long);
+    }
+    Ok(Some(line))
+}
+
+fn parse_request_line(line: &[u8]) -> Result<(Method, String, Version), ParseError> {
+    let line = std::str::from_utf8(line).map_err(|_| ParseError::BadRequest("request line is not UTF-8"))?;
+    let mut parts = line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(ParseError::BadRequest("malformed request line")),
+    };
+    if !is_token(method) || target.is_empty() {
+        return Err(ParseError::BadRequest("malformed request line"));
+    }
+
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.starts_with("HTTP/") && v.len() == 8 => return Err(ParseError::VersionNotSupported),
+        _ => return Err(ParseError::BadRequest("malformed HTTP version")),
+    };
+    Ok((Method::parse(method), target.to_string(), version))
+}
+
+// ターゲットをパスとクエリ文字列に分ける
+// "/path?query"のほか、プロキシ向けの"http://host/path"と、OPTIONSの"*"を受け付ける
+fn split_target(method: &Method, target: &str) -> Result<(String, Option<String>), ParseError> {
+    if target == "*" && *method == Method::Options {
+        return Ok((target.to_string(), None));
+    }
+    let origin = match target.find("://") {
+        Some(scheme) if target[..scheme].eq_ignore_ascii_case("http") || target[..scheme].eq_ignore_ascii_case("https") => {
+            let rest = &target[scheme + 3..];
+            rest.find('/').map_or("/", |slash| &rest[slash..])
+        },
+        _ => target,
+    };
+    if !origin.starts_with('/') {
+        return Err(ParseError::BadRequest("invalid request target"));
+    }
+    let (path, query) = match origin.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (origin, None),
+    };
+    Ok((percent_decode(path, false), query))
+}
+
+fn parse_header(line: &[u8]) -> Result<(String, String), ParseError> {
+    // 行頭の空白で前の行の続きを表す古い書き方（obs-fold）は受け付けない
+    if line.first().is_some_and(|b| *b == b' ' || *b == b'\t') {
+        return Err(ParseError::BadRequest("obsolete header line folding"));
+    }
+    let colon = line.iter().position(|b| *b == b':').ok_or(ParseError::BadRequest("header without colon"))?;
+    let name = std::str::from_utf8(&line[..colon]).map_err(|_| ParseError::BadRequest("invalid header name"))?;
+    if !is_token(name) {
+        return Err(ParseError::BadRequest("invalid header name"));
+    }
+    // 値はUTF-8でなくても読めるところだけ使う
+    let value = String::from_utf8_lossy(&line[colon + 1..]);
+    Ok((name.to_string(), value.trim_matches(|c| c == ' ' || c == '\t').to_string()))
+}
+
+// メソッドやヘッダー名に使える文字だけからなるか
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// chunkedのボディを読む。最後のチャンクの後のトレーラーは読み飛ばす
+fn read_chunked<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, limits.max_line, ParseError::BadRequest("chunk size line is too long"))?
+            .ok_or_else(unexpected_eof)?;
+        // ";"より後ろはチャンク拡張なので無視する
+        let size = line.split(|b| *b == b';').next().unwrap_or(&[]);
+        let size = std::str::from_utf8(size)
+            .ok()
+            .map(str::trim)
+            .filter(|size| !size.is_empty())
+            .and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or(ParseError::BadRequest("invalid chunk size"))?;
+
+        if size == 0 {
+            break;
+        }
+        // sizeは16桁の16進数まで受け付けるので、足し算であふれないように残りの容量と比べる
+        if size > limits.max_body - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).map_err(eof_is_io)?;
+
+        let mut crlf = Vec::new();
+        reader.by_ref().take(2).read_until(b'\n', &mut crlf)?;
+        if crlf != b"\r\n" && crlf != b"\n" {
+            return Err(ParseError::BadRequest("missing CRLF after chunk"));
+        }
+    }
+
+    let mut trailers = 0;
+    loop {
+        let line = read_line(reader, limits.max_line, ParseError::HeadersTooLarge)?.ok_or_else(unexpected_eof)?;
+        if line.is_empty() {
+            return Ok(body);
+        }
+        trailers += 1;
+        if trailers > limits.max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(raw: &str) -> Result<Option<Request>, ParseError> {
+        Request::read(&mut Cursor::new(raw.as_bytes()), &Limits::default())
+    }
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let request = parse("\r\nGET /docs/a%20b?q=rust+lang&x HTTP/1.1\r\nHost: localhost\r\nAccept:  text/html \r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.target, "/docs/a%20b?q=rust+lang&x");
+        assert_eq!(request.path, "/docs/a b");
+        assert_eq!(request.query_param("q").as_deref(), Some("rust lang"));
+        assert_eq!(request.query_param("x").as_deref(), Some(""));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.header("accept"), Some("text/html"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn reads_content_length_body() {
+        let request = parse("POST /echo HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello").unwrap().unwrap();
+        assert_eq!(request.body, b"hello");
+
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nhello"),
+            Err(ParseError::Io(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab"),
+            Err(ParseError::BadRequest(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 99999999999999999999999\r\n\r\n"),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn reads_chunked_body() {
+        let raw = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\nGET";
+        let mut reader = Cursor::new(raw.as_bytes());
+        let request = Request::read(&mut reader, &Limits::default()).unwrap().unwrap();
+        assert_eq!(request.body, b"hello, world");
+        // 次のリクエストの先頭は読まずに残す
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "GET");
+
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Err(ParseError::NotImplemented(_))
+        ));
+        // 並んだヘッダーは、全体でchunkedだけでなければ受け付けない
+        for codings in ["chunked\r\nTransfer-Encoding: gzip", "gzip\r\nTransfer-Encoding: chunked", "chunked, chunked"] {
+            let raw = format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {}\r\n\r\n0\r\n\r\n", codings);
+            assert!(matches!(parse(&raw), Err(ParseError::NotImplemented(_))), "{}", codings);
+        }
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"),
+            Err(ParseError::BadRequest(_))
+        ));
+        // 合計があふれるほど大きなチャンクでも、パニックせずに大きすぎると返す
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n"),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let limits = Limits { max_line: 32, max_headers: 2, max_body: 4 };
+        let read = |raw: &str| Request::read(&mut Cursor::new(raw.as_bytes()), &limits);
+
+        assert!(matches!(read(&format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(40))), Err(ParseError::UriTooLong)));
+        assert!(matches!(
+            read("GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\n\r\n"),
+            Err(ParseError::HeadersTooLarge)
+        ));
+        assert!(matches!(
+            read("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello"),
+            Err(ParseError::BodyTooLarge)
+        ));
+        assert!(matches!(
+            read("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n"),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        assert!(parse("").unwrap().is_none());
+        assert!(matches!(parse("GET / HTTP/1.1\r\nHost: a\r\n"), Err(ParseError::Io(_))));
+        assert!(matches!(parse("GET /  HTTP/1.1\r\n\r\n"), Err(ParseError::BadRequest(_))));
+        assert!(matches!(parse("GET / HTTP/2.0\r\n\r\n"), Err(ParseError::VersionNotSupported)));
+        assert!(matches!(parse("GET / HTTP/1.1\r\n\r\n"), Err(ParseError::BadRequest(_))));
+        assert!(matches!(parse("GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n"), Err(ParseError::BadRequest(_))));
+        assert!(matches!(parse("GET / HTTP/1.1\r\nHost : a\r\n\r\n"), Err(ParseError::BadRequest(_))));
+        assert!(matches!(parse("GET index.html HTTP/1.0\r\n\r\n"), Err(ParseError::BadRequest(_))));
+
+        // HTTP/1.0ならHostヘッダーはなくてよい。プロキシ向けの形式のターゲットも受け付ける
+        let request = parse("GET http://example.com/a?b HTTP/1.0\r\n\r\n").unwrap().unwrap();
+        assert_eq!((request.path.as_str(), request.query.as_deref()), ("/a", Some("b")));
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use super::Headers;
+
+/// ステータスコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const EXPECTATION_FAILED: StatusCode = StatusCode(417);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+
+    /// 100から999までの値からステータスコードを作る。範囲外なら`None`
+    pub fn from_u16(code: u16) -> Option<StatusCode> {
+        if (100..1000).contains(&code) {
+            Some(StatusCode(code))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+
+    // ステータスラインに書く理由句。知らないコードは空にする
+    pub fn reason(self) -> &'static str {
+        match self.0 {
+            100 => "Continue",
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            503 => "Service Unavailable",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
+    }
+
+    // このステータスのレスポンスにボディを付けられるか
+    fn allows_body(self) -> bool {
+        !(self.0 < 200 || self.0 == 204 || self.0 == 304)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason())
+    }
+}
+
+/// ボディとして返すファイルの範囲。
+///
+/// メモリには読み込まず、レスポンスを書き出すときに開いて少しずつ送ります。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileBody {
+    pub path: PathBuf,
+    pub start: u64,
+    pub len: u64,
+}
+
+impl FileBody {
+    fn copy_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.start))?;
+        let copied = io::copy(&mut file.take(self.len), writer)?;
+        // Content-Lengthはもう送ってしまったので、足りなければコネクションごと諦める
+        if copied < self.len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while being sent"));
+        }
+        Ok(())
+    }
+}
+
+/// 返すレスポンス
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// あればbodyの代わりに送る
+    pub file: Option<FileBody>,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            file: None,
+        }
+    }
+
+    // text/plainのレスポンス
+    pub fn text<S: Into<String>>(status: StatusCode, body: S) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    // text/htmlのレスポンス
+    pub fn html<B: Into<Vec<u8>>>(status: StatusCode, body: B) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    pub fn with_header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    // pathのstartからlenバイトを、書き出すときにファイルから読んで送る
+    pub fn with_file<P: Into<PathBuf>>(mut self, path: P, start: u64, len: u64) -> Response {
+        self.body = Vec::new();
+        self.file = Some(FileBody { path: path.into(), start, len });
+        self
+    }
+
+    /// ステータスライン、ヘッダー、ボディを書き出す。
+    ///
+    /// Content-Lengthはボディ（fileがあればその範囲）の長さから付けます。head_onlyなら（HEADへの応答）、
+    /// Content-Lengthはそのままでボディを書きません。
+    pub fn write_to<W: Write>(&self, writer: &mut W, head_only: bool) -> io::Result<()> {
+        write!(writer, "HTTP/1.1 {}\r\n", self.status)?;
+        for (name, value) in self.headers.iter().filter(|(name, _)| !name.eq_ignore_ascii_case("Content-Length")) {
+            write!(writer, "{}: {}\r\n", name, value)?;
+        }
+        let allows_body = self.status.allows_body();
+        if allows_body {
+            let len = self.file.as_ref().map_or(self.body.len() as u64, |file| file.len);
+            write!(writer, "Content-Length: {}\r\n", len)?;
+        }
+        writer.write_all(b"\r\n")?;
+        if allows_body && !head_only {
+            match &self.file {
+                Some(file) => file.copy_to(writer)?,
+                None => writer.write_all(&self.body)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(response: &Response, head_only: bool) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out, head_only).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn writes_status_headers_and_body() {
+        let response = Response::text(StatusCode::NOT_FOUND, "nope").with_header("X-Test", "1");
+        assert_eq!(
+            written(&response, false),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nX-Test: 1\r\nContent-Length: 4\r\n\r\nnope"
+        );
+        assert!(written(&response, true).ends_with("Content-Length: 4\r\n\r\n"));
+    }
+
+    #[test]
+    fn no_body_for_not_modified() {
+        let response = Response::new(StatusCode::NOT_MODIFIED).with_body("ignored");
+        assert_eq!(written(&response, false), "HTTP/1.1 304 Not Modified\r\n\r\n");
+        assert_eq!(StatusCode::from_u16(42), None);
+    }
+}
@@ -0,0 +1,258 @@
+use super::{Method, Request, Response, StatusCode};
+
+// リクエストを受け取ってレスポンスを返すハンドラー。複数のWorkerから同時に呼ばれる
+type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync + 'static>;
+
+/// メソッドとパスのパターンで、リクエストをハンドラーに振り分けるルーター。
+///
+/// パターンの":name"はパスの1つのセグメントに、"*name"は残りのすべてのセグメントに一致し、
+/// 一致した値を`Request::param`で取り出せます。先に登録したルートが優先されます。
+///
+/// ```
+/// use multi_thread_server::http::{Response, Router, StatusCode};
+///
+/// let router = Router::new()
+///     .get("/", |_| Response::text(StatusCode::OK, "home"))
+///     .get("/users/:id", |request| {
+///         Response::text(StatusCode::OK, format!("user {}", request.param("id").unwrap()))
+///     })
+///     .get("/files/*path", |request| {
+///         Response::text(StatusCode::OK, request.param("path").unwrap().to_string())
+///     });
+/// ```
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    // 残りのすべてのセグメント。パターンの最後にだけ書ける
+    Rest(String),
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_| Response::text(StatusCode::NOT_FOUND, "Not Found\n")),
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// methodでpatternに一致するリクエストを、handlerで処理する。
+    ///
+    /// # パニック
+    ///
+    /// patternが'/'で始まらないか、"*name"が最後のセグメントでなければパニックします。
+    pub fn route<H>(mut self, method: Method, pattern: &str, handler: H) -> Router
+        where
+            H: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        assert!(pattern.starts_with('/'), "route pattern must start with '/': {}", pattern);
+        let segments: Vec<Segment> = split_path(pattern)
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Rest(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+        let rest = segments.iter().position(|segment| matches!(segment, Segment::Rest(_)));
+        assert!(
+            rest.is_none_or(|i| i == segments.len() - 1),
+            "'*' segment must be the last one: {}",
+            pattern
+        );
+
+        self.routes.push(Route {
+            method,
+            segments,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H>(self, pattern: &str, handler: H) -> Router
+        where
+            H: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H>(self, pattern: &str, handler: H) -> Router
+        where
+            H: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<H>(self, pattern: &str, handler: H) -> Router
+        where
+            H: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<H>(self, pattern: &str, handler: H) -> Router
+        where
+            H: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    // どのルートにも一致しないリクエストを処理するハンドラー。指定しなければ404を返す
+    pub fn not_found<H>(mut self, handler: H) -> Router
+        where
+            H: Fn(&Request) -> Response + Send + Sync + 'static
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// requestを一致したルートのハンドラーで処理する。
+    ///
+    /// パスに一致するルートがあってもメソッドが違えば、405とAllowヘッダーを返します。
+    /// HEADのルートがなければ、GETのルートで処理します。
+    pub fn handle(&self, request: &mut Request) -> Response {
+        let mut allowed: Vec<&Method> = Vec::new();
+        let mut head_fallback = None;
+
+        for route in &self.routes {
+            let params = match route.matches(&request.path) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method == request.method {
+                request.params = params;
+                return (route.handler)(request);
+            }
+            if request.method == Method::Head && route.method == Method::Get && head_fallback.is_none() {
+                head_fallback = Some((route, params));
+            }
+            if !allowed.contains(&&route.method) {
+                allowed.push(&route.method);
+            }
+        }
+
+        if let Some((route, params)) = head_fallback {
+            request.params = params;
+            return (route.handler)(request);
+        }
+        if allowed.is_empty() {
+            return (self.not_found)(request);
+        }
+
+        if allowed.contains(&&Method::Get) && !allowed.contains(&&Method::Head) {
+            allowed.push(&Method::Head);
+        }
+        let names: Vec<&str> = allowed.iter().map(|method| method.as_str()).collect();
+        Response::text(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed\n").with_header("Allow", names.join(", "))
+    }
+}
+
+impl Route {
+    // pathがパターンに一致すれば、取り出した値を返す
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let mut params = Vec::new();
+        let mut segments = split_path(path);
+
+        for pattern in &self.segments {
+            match pattern {
+                Segment::Literal(literal) => {
+                    if segments.next()? != literal {
+                        return None;
+                    }
+                },
+                Segment::Param(name) => params.push((name.clone(), segments.next()?.to_string())),
+                Segment::Rest(name) => {
+                    params.push((name.clone(), segments.by_ref().collect::<Vec<_>>().join("/")));
+                },
+            }
+        }
+        if segments.next().is_some() {
+            return None;
+        }
+        Some(params)
+    }
+}
+
+// "/"は空、"/a/b/"は["a", "b", ""]になる
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    path.split('/').filter(move |_| !path.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Version};
+
+    fn request(method: Method, path: &str) -> Request {
+        Request {
+            method,
+            target: path.to_string(),
+            path: path.to_string(),
+            query: None,
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.body).unwrap()
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_| Response::text(StatusCode::OK, "home"))
+            .get("/users/:id", |r| Response::text(StatusCode::OK, format!("get {}", r.param("id").unwrap())))
+            .delete("/users/:id", |r| Response::text(StatusCode::OK, format!("delete {}", r.param("id").unwrap())))
+            .get("/files/*path", |r| Response::text(StatusCode::OK, format!("file {}", r.param("path").unwrap())))
+    }
+
+    #[test]
+    fn routes_by_path_and_method() {
+        let router = router();
+        assert_eq!(body(router.handle(&mut request(Method::Get, "/"))), "home");
+        assert_eq!(body(router.handle(&mut request(Method::Get, "/users/42"))), "get 42");
+        assert_eq!(body(router.handle(&mut request(Method::Delete, "/users/42"))), "delete 42");
+        assert_eq!(body(router.handle(&mut request(Method::Get, "/files/a/b.txt"))), "file a/b.txt");
+        assert_eq!(body(router.handle(&mut request(Method::Get, "/files"))), "file ");
+        // HEADはGETのルートで処理する
+        assert_eq!(body(router.handle(&mut request(Method::Head, "/users/1"))), "get 1");
+
+        assert_eq!(router.handle(&mut request(Method::Get, "/users")).status, StatusCode::NOT_FOUND);
+        assert_eq!(router.handle(&mut request(Method::Get, "/users/1/x")).status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn method_not_allowed_lists_allowed_methods() {
+        let response = router().handle(&mut request(Method::Post, "/users/1"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers.get("Allow"), Some("GET, DELETE, HEAD"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn rest_segment_must_be_last() {
+        let _ = Router::new().get("/*rest/more", |_| Response::new(StatusCode::OK));
+    }
+}
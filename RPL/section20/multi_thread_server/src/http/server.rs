@@ -0,0 +1,449 @@
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Limits, Method, ParseError, Request, Response, Router, StatusCode, Version};
+use crate::{Job, ShutdownReport, ThreadPool};
+
+// アイドル状態のコネクションが、終了の指示が来ていないか確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// コネクションごとのタイムアウトと上限
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// リクエストを読み始めてから、次のデータが届くまで待つ時間。過ぎたら408を返して閉じる
+    pub read_timeout: Duration,
+    /// レスポンスの書き込みが進まないときに待つ時間。過ぎたら閉じる
+    pub write_timeout: Duration,
+    /// キープアライブで次のリクエストを待つ時間。過ぎたら黙って閉じる
+    pub idle_timeout: Duration,
+    /// 1つのコネクションで処理するリクエストの数。最後のレスポンスでコネクションを閉じる
+    pub max_requests: usize,
+    /// falseなら、リクエストを1つ処理するごとにコネクションを閉じる
+    pub keep_alive: bool,
+    pub limits: Limits,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+            keep_alive: true,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// サーバーに終了を指示するハンドル。クローンしてシグナルハンドラーなどに渡せます。
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownState>,
+}
+
+#[derive(Debug, Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    // 接続を待っているサーバーを起こすためのアドレス
+    wake: Option<SocketAddr>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    /// 新しいコネクションの受け付けをやめさせる。
+    ///
+    /// 処理中のリクエストはレスポンスを返してからコネクションを閉じ、
+    /// 次のリクエストを待っているコネクションはすぐに閉じます。
+    pub fn shutdown(&self) {
+        self.inner.requested.store(true, Ordering::SeqCst);
+        // acceptで止まっているサーバーに接続して、終了の指示に気づかせる
+        if let Some(addr) = self.inner.wake {
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// コネクションを受け付けて、ThreadPoolで処理するHTTPサーバー。
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use multi_thread_server::http::{Response, Router, Server, StatusCode};
+/// use multi_thread_server::ThreadPool;
+///
+/// let router = Router::new().get("/", |_| Response::text(StatusCode::OK, "hello\n"));
+/// let server = Server::bind("127.0.0.1:7878", router).unwrap();
+/// let shutdown = server.shutdown_handle();
+/// // 別のスレッドから shutdown.shutdown() を呼ぶと、runが戻る
+/// server.run(Arc::new(ThreadPool::new(4)));
+/// ```
+pub struct Server {
+    listener: TcpListener,
+    router: Arc<Router>,
+    config: ConnectionConfig,
+    shutdown: ShutdownHandle,
+    grace_period: Duration,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, router: Router) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        // 0.0.0.0で待ち受けているなら、自分にはループバックで接続する
+        let wake_ip = match local.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        let shutdown = ShutdownHandle {
+            inner: Arc::new(ShutdownState {
+                requested: AtomicBool::new(false),
+                wake: Some(SocketAddr::new(wake_ip, local.port())),
+            }),
+        };
+
+        Ok(Server {
+            listener,
+            router: Arc::new(router),
+            config: ConnectionConfig::default(),
+            shutdown,
+            grace_period: Duration::from_secs(10),
+        })
+    }
+
+    pub fn connection_config(mut self, config: ConnectionConfig) -> Server {
+        self.config = config;
+        self
+    }
+
+    // 終了の指示の後、処理中のリクエストが終わるのを待つ時間
+    pub fn grace_period(mut self, grace_period: Duration) -> Server {
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 終了の指示があるまでコネクションを受け付けて、poolで処理する。
+    ///
+    /// 終了の指示があったら、最大grace_periodだけ処理中のコネクションを待ってからプールを終了し、
+    /// その結果を返します。他にもpoolを持っているところがあれば、プールの終了はそちらに任せて`None`を返します。
+    pub fn run(self, pool: Arc<ThreadPool>) -> Option<ShutdownReport> {
+        for stream in self.listener.incoming() {
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+            // 受け付けに失敗しても、サーバーは止めずに次のコネクションを待つ
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("Failed to accept a connection: {}", e);
+                    // ファイルディスクリプタが足りないときなどに、空回りしないようにする
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                },
+            };
+
+            let name = match stream.peer_addr() {
+                Ok(peer) => format!("connection from {}", peer),
+                Err(_) => String::from("connection"),
+            };
+            let router = Arc::clone(&self.router);
+            let config = self.config.clone();
+            let shutdown = self.shutdown.clone();
+            pool.execute(Job::new(move || {
+                // 読み書きに失敗したら、そのコネクションを閉じるだけにする
+                if let Err(e) = serve_connection(stream, &router, &config, &shutdown) {
+                    println!("Connection error: {}", e);
+                }
+            }).name(name));
+        }
+
+        println!("Shutting down.");
+        drop(self.listener);
+        let pool = Arc::try_unwrap(pool).ok()?;
+        Some(pool.shutdown_timeout(self.grace_period))
+    }
+}
+
+/// 1つのコネクションで、閉じられるまでリクエストを順に処理する。
+///
+/// HTTP/1.1ではキープアライブがデフォルトで、パイプラインで続けて届いたリクエストにも
+/// 順にレスポンスを返します。解析できないリクエストには4xxか5xxのレスポンスを返して閉じます。
+/// 読み書きに失敗したらエラーを返すので、呼び出し側はコネクションを捨てます。
+pub fn serve_connection(
+    stream: TcpStream,
+    router: &Router,
+    config: &ConnectionConfig,
+    shutdown: &ShutdownHandle,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(config.write_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    for served in 1.. {
+        // パイプラインで次のリクエストがもう届いていれば、まとめて書き出さずに続けて処理する
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+            // 終了の指示が来ても、受け付けたコネクションの最初のリクエストには応える
+            let shutdown = if served == 1 { None } else { Some(shutdown) };
+            if !wait_for_request(&mut reader, config.idle_timeout, shutdown)? {
+                return Ok(());
+            }
+        }
+        reader.get_ref().set_read_timeout(Some(config.read_timeout))?;
+
+        let (mut response, head_only, keep_alive) = match read_request(&mut reader, &mut writer, &config.limits) {
+            Ok(Some(mut request)) => {
+                let mut response = respond(router, &mut request);
+                let keep_alive = config.keep_alive
+                    && served < config.max_requests
+                    && wants_keep_alive(&request)
+                    && !response.headers.has_token("Connection", "close")
+                    && !shutdown.is_shutdown();
+                if keep_alive && request.version == Version::Http10 {
+                    response.headers.insert("Connection", "keep-alive");
+                }
+                (response, request.method == Method::Head, keep_alive)
+            },
+            // 空行だけを送って閉じられた
+            Ok(None) => return writer.flush(),
+            Err(ParseError::Io(e)) if is_timeout(&e) => {
+                (Response::text(StatusCode::REQUEST_TIMEOUT, "Request Timeout\n"), false, false)
+            },
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => (e.to_response(), false, false),
+        };
+
+        if !keep_alive {
+            response.headers.insert("Connection", "close");
+        }
+        response.write_to(&mut writer, head_only)?;
+        if !keep_alive {
+            return writer.flush();
+        }
+    }
+    Ok(())
+}
+
+// 次のリクエストの最初のデータが届くまで、最大idle_timeoutだけ待つ
+// データが届いたらtrue、時間切れか閉じられたか、終了の指示が来たらfalseを返す
+fn wait_for_request(
+    reader: &mut BufReader<TcpStream>,
+    idle_timeout: Duration,
+    shutdown: Option<&ShutdownHandle>,
+) -> io::Result<bool> {
+    let deadline = Instant::now() + idle_timeout;
+    loop {
+        if shutdown.is_some_and(ShutdownHandle::is_shutdown) {
+            return Ok(false);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        reader.get_ref().set_read_timeout(Some((deadline - now).min(POLL_INTERVAL)))?;
+        match reader.fill_buf() {
+            Ok(buffer) => return Ok(!buffer.is_empty()),
+            Err(e) if is_timeout(&e) || e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// リクエストを読む。Expect: 100-continueなら、ボディを読む前に100 Continueを返す
+// 受け取れないボディなら、送らせる前に413や501で断る
+fn read_request<W: Write>(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut W,
+    limits: &Limits,
+) -> Result<Option<Request>, ParseError> {
+    let mut request = match Request::read_head(reader, limits)? {
+        Some(request) => request,
+        None => return Ok(None),
+    };
+    if request.expects_continue()? {
+        request.check_body(limits)?;
+        Response::new(StatusCode::CONTINUE).write_to(writer, false)?;
+        writer.flush()?;
+    }
+    request.read_body(reader, limits)?;
+    Ok(Some(request))
+}
+
+// ハンドラーがパニックしたら500を返す。Workerスレッドは巻き込まない
+fn respond(router: &Router, request: &mut Request) -> Response {
+    match panic::catch_unwind(AssertUnwindSafe(|| router.handle(request))) {
+        Ok(response) => response,
+        Err(_) => Response::text(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error\n"),
+    }
+}
+
+// HTTP/1.1はConnection: closeでなければ、HTTP/1.0はConnection: keep-aliveならコネクションを保つ
+fn wants_keep_alive(request: &Request) -> bool {
+    match request.version {
+        Version::Http11 => !request.headers.has_token("Connection", "close"),
+        Version::Http10 => request.headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+// タイムアウトしたときの読み書きのエラーは、プラットフォームによって種類が違う
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn echo() -> Router {
+        Router::new()
+            .post("/echo", |r| Response::new(StatusCode::OK).with_body(r.body.clone()))
+            .get("/", |_| Response::text(StatusCode::OK, "home"))
+    }
+
+    // routerで1つのコネクションを処理させ、rawを送って、閉じられるまでに受け取ったものを返す
+    fn exchange_with(router: Router, config: ConnectionConfig, raw: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, &router, &config, &ShutdownHandle::new())
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(raw).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap().unwrap();
+        response
+    }
+
+    fn exchange(router: Router, raw: &[u8]) -> String {
+        let config = ConnectionConfig {
+            idle_timeout: Duration::from_millis(200),
+            ..ConnectionConfig::default()
+        };
+        exchange_with(router, config, raw)
+    }
+
+    #[test]
+    fn answers_bad_and_failing_requests() {
+        let response = exchange(
+            echo(),
+            b"POST /echo HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi",
+        );
+        assert!(response.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nhi"));
+
+        // 受け取れないボディは、100 Continueを返さずに断る
+        let response = exchange(echo(), b"POST /echo HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 99999999999\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", response);
+        assert!(response.contains("Connection: close\r\n"));
+        let response = exchange(echo(), b"POST /echo HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nTransfer-Encoding: gzip\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"), "{}", response);
+        let response = exchange(echo(), b"POST /echo HTTP/1.1\r\nHost: a\r\nExpect: 200-ok\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 417 Expectation Failed\r\n"), "{}", response);
+
+        let response = exchange(echo(), b"GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        // ハンドラーがパニックしても500を返す
+        let panics = Router::new().get("/", |_| panic!("handler failed"));
+        let response = exchange(panics, b"GET / HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let response = exchange(
+            echo(),
+            b"POST /echo HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nfirstGET / HTTP/1.1\r\nHost: a\r\n\r\n\
+              POST /echo HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nthird\r\n0\r\n\r\n",
+        );
+        let bodies: Vec<&str> = response
+            .split("HTTP/1.1 200 OK\r\n")
+            .skip(1)
+            .map(|part| part.split("\r\n\r\n").nth(1).unwrap())
+            .collect();
+        assert_eq!(bodies, vec!["first", "home", "third"]);
+        // 次のリクエストが来ないまま、idle_timeoutで閉じる
+        assert!(!response.contains("Connection: close"));
+    }
+
+    #[test]
+    fn keep_alive_depends_on_version_and_limits() {
+        // HTTP/1.0はConnection: keep-aliveのときだけコネクションを保つ
+        let response = exchange(echo(), b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n");
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(response.contains("Connection: keep-alive\r\n"));
+        assert!(response.ends_with("Connection: close\r\nContent-Length: 4\r\n\r\nhome"));
+
+        // max_requests個目のレスポンスで閉じ、残りのリクエストは処理しない
+        let config = ConnectionConfig {
+            max_requests: 2,
+            ..ConnectionConfig::default()
+        };
+        let response = exchange_with(echo(), config, "GET / HTTP/1.1\r\nHost: a\r\n\r\n".repeat(3).as_bytes());
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(response.ends_with("Connection: close\r\nContent-Length: 4\r\n\r\nhome"));
+    }
+
+    #[test]
+    fn slow_request_times_out() {
+        let config = ConnectionConfig {
+            read_timeout: Duration::from_millis(100),
+            ..ConnectionConfig::default()
+        };
+        let response = exchange_with(echo(), config, b"GET / HTTP/1.1\r\nHost: a\r\n");
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn server_shuts_down_gracefully() {
+        let router = Router::new().get("/slow", |_| {
+            thread::sleep(Duration::from_millis(200));
+            Response::text(StatusCode::OK, "done")
+        });
+        let server = Server::bind("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let pool = Arc::new(crate::Builder::new().min_workers(2).max_workers(2).log_jobs(false).build().unwrap());
+        let running = thread::spawn(move || server.run(pool));
+
+        // 処理中のリクエストには、終了の指示の後もレスポンスを返してからコネクションを閉じる
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        shutdown.shutdown();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        let report = running.join().unwrap().unwrap();
+        assert!(report.is_complete());
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+use super::{lock, Priority};
+
+/// プールに投入するジョブ。名前やキャンセル用のトークンを付けられます。
+///
+/// ```
+/// use multi_thread_server::{Job, ThreadPool};
+///
+/// let pool = ThreadPool::new(1);
+/// let job = Job::cancellable(|token| {
+///     while !token.is_cancelled() {
+///         // 少しずつ処理を進める
+///         # break;
+///     }
+/// })
+/// .name("report");
+/// let token = job.token().unwrap().clone();
+/// pool.execute(job);
+/// token.cancel();
+/// ```
+pub struct Job {
+    name: Option<String>,
+    token: Option<CancellationToken>,
+    f: Box<dyn FnOnce() + Send + 'static>,
+}
+
+impl Job {
+    pub fn new<F>(f: F) -> Job
+        where
+            F: FnOnce() + Send + 'static
+    {
+        Job {
+            name: None,
+            token: None,
+            f: Box::new(f),
+        }
+    }
+
+    /// 自分のキャンセル用のトークンを受け取るジョブを作る。
+    ///
+    /// 実行中のジョブは、トークンを見て自分で処理を打ち切ります。
+    pub fn cancellable<F>(f: F) -> Job
+        where
+            F: FnOnce(&CancellationToken) + Send + 'static
+    {
+        let token = CancellationToken::new();
+        let job_token = token.clone();
+        Job {
+            name: None,
+            token: Some(token),
+            f: Box::new(move || f(&job_token)),
+        }
+    }
+
+    // デバッグ用のスナップショットに表示する名前
+    pub fn name<S: Into<String>>(mut self, name: S) -> Job {
+        self.name = Some(name.into());
+        self
+    }
+
+    // 他のジョブとトークンを共有して、まとめてキャンセルできるようにする
+    // Job::cancellableのジョブに渡されるトークンは置き換わらない
+    pub fn cancel_token(mut self, token: CancellationToken) -> Job {
+        self.token = Some(token);
+        self
+    }
+
+    // Job::cancellableで作ったか、cancel_tokenで付けたトークン
+    pub fn token(&self) -> Option<&CancellationToken> {
+        self.token.as_ref()
+    }
+}
+
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("name", &self.name)
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+/// プールに投入できる値。クロージャと`Job`に実装されています。
+///
+/// 自分の型に実装すれば、その型の値をそのまま投入できます。
+pub trait IntoJob: Send + 'static {
+    fn into_job(self) -> Job;
+}
+
+impl IntoJob for Job {
+    fn into_job(self) -> Job {
+        self
+    }
+}
+
+impl<F> IntoJob for F
+    where
+        F: FnOnce() + Send + 'static
+{
+    fn into_job(self) -> Job {
+        Job::new(self)
+    }
+}
+
+/// ジョブのキャンセルを伝えるトークン。クローンしたトークンは同じ状態を共有します。
+///
+/// 実行前にキャンセルされたジョブは、実行されずに捨てられます。
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// プールの中でジョブを区別するID。投入した順に振られます。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(u64);
+
+impl From<u64> for JobId {
+    fn from(id: u64) -> JobId {
+        JobId(id)
+    }
+}
+
+impl From<JobId> for u64 {
+    fn from(id: JobId) -> u64 {
+        id.0
+    }
+}
+
+impl fmt::Display for JobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// `ThreadPool::jobs`が返す、1つのジョブの状態
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobSnapshot {
+    pub id: JobId,
+    pub name: Option<String>,
+    /// 投入したときの優先度。ジョブの中から投入されてWorkerのローカルキューに入ったジョブはNone
+    pub priority: Option<Priority>,
+    /// 投入されてから実行が始まるまで（まだ始まっていなければ今まで）の時間
+    pub waited: Duration,
+    pub state: JobState,
+    /// キャンセルされたか。キャンセルされても、実行中のジョブは終わるまで一覧に残る
+    pub cancelled: bool,
+}
+
+impl JobSnapshot {
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, JobState::Running { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    /// キューに入っていて、まだ実行されていない
+    Queued,
+    /// workerのWorkerで、elapsedの間実行されている
+    Running { worker: usize, elapsed: Duration },
+}
+
+// Workerが実行するジョブ。キューに入れるときに、投入されたジョブから作る
+pub(crate) struct Task {
+    pub(crate) info: Arc<JobInfo>,
+    pub(crate) f: Box<dyn FnOnce() + Send + 'static>,
+}
+
+// 投入されたジョブの情報。スナップショットはここから作る
+// 小さなジョブを大量に実行しても遅くならないように、ロックを使わずに書き換える
+pub(crate) struct JobInfo {
+    id: JobId,
+    name: Option<String>,
+    priority: Option<Priority>,
+    submitted_at: Instant,
+    // ThreadPool::cancelでキャンセルされたか
+    cancelled: AtomicBool,
+    // Jobに付けられたトークン
+    token: Option<CancellationToken>,
+    // 実行を始めた時刻をsubmitted_atからのナノ秒で表し、1を足したもの。0ならまだ始まっていない
+    started: AtomicU64,
+    // 実行しているWorkerのID
+    worker: AtomicUsize,
+}
+
+impl JobInfo {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.token.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(token) = &self.token {
+            token.cancel();
+        }
+    }
+
+    pub(crate) fn start(&self, worker: usize) -> Instant {
+        let now = Instant::now();
+        self.worker.store(worker, Ordering::SeqCst);
+        self.started.store(nanos(now.saturating_duration_since(self.submitted_at)).saturating_add(1), Ordering::SeqCst);
+        now
+    }
+
+    fn snapshot(&self, now: Instant) -> JobSnapshot {
+        let (waited, state) = match self.started.load(Ordering::SeqCst) {
+            0 => (now.saturating_duration_since(self.submitted_at), JobState::Queued),
+            started => {
+                let waited = Duration::from_nanos(started - 1);
+                let running = JobState::Running {
+                    worker: self.worker.load(Ordering::SeqCst),
+                    elapsed: now.saturating_duration_since(self.submitted_at + waited),
+                };
+                (waited, running)
+            },
+        };
+        JobSnapshot {
+            id: self.id,
+            name: self.name.clone(),
+            priority: self.priority,
+            waited,
+            state,
+            cancelled: self.is_cancelled(),
+        }
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+// ジョブの一覧を分ける数
+const SHARDS: usize = 32;
+
+// 投入されてまだ終わっていないジョブの一覧と、終わったジョブの集計
+pub(crate) struct Registry {
+    next_id: AtomicU64,
+    // falseなら一覧を作らず、集計だけをする
+    tracking: bool,
+    // IDで分けた一覧。投入するスレッドと実行を終えたWorkerが、同じロックを取り合わないようにする
+    // 実行されずに捨てられたジョブはWeakが切れるので、スナップショットを作るときに取り除く
+    shards: Vec<Mutex<HashMap<JobId, Weak<JobInfo>>>>,
+    completed: AtomicU64,
+    cancelled: AtomicU64,
+    // 終わったジョブの実行時間の合計と最大値（ナノ秒）
+    total_run_nanos: AtomicU64,
+    max_run_nanos: AtomicU64,
+}
+
+// ThreadPool::metricsに含める、終わったジョブの集計
+pub(crate) struct RunStats {
+    pub(crate) completed: u64,
+    pub(crate) cancelled: u64,
+    pub(crate) total_run_time: Duration,
+    pub(crate) max_run_time: Duration,
+}
+
+impl Registry {
+    pub(crate) fn new(tracking: bool) -> Registry {
+        Registry {
+            next_id: AtomicU64::new(0),
+            tracking,
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+            completed: AtomicU64::new(0),
+            cancelled: AtomicU64::new(0),
+            total_run_nanos: AtomicU64::new(0),
+            max_run_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, id: JobId) -> MutexGuard<'_, HashMap<JobId, Weak<JobInfo>>> {
+        lock(&self.shards[(id.0 % SHARDS as u64) as usize])
+    }
+
+    pub(crate) fn next_id(&self) -> JobId {
+        JobId(self.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+
+    // 投入されたジョブを一覧に加えて、Workerが実行するTaskにする
+    pub(crate) fn track(&self, id: JobId, job: Job, priority: Option<Priority>) -> Task {
+        let info = Arc::new(JobInfo {
+            id,
+            name: job.name,
+            priority,
+            submitted_at: Instant::now(),
+            cancelled: AtomicBool::new(false),
+            token: job.token,
+            started: AtomicU64::new(0),
+            worker: AtomicUsize::new(0),
+        });
+        if self.tracking {
+            self.shard(id).insert(id, Arc::downgrade(&info));
+        }
+        Task { info, f: job.f }
+    }
+
+    // 実行を終えたジョブを一覧から取り除き、実行時間を集計する
+    pub(crate) fn finished(&self, info: &JobInfo, run_time: Duration) {
+        self.forget(info);
+        let nanos = nanos(run_time);
+        self.completed.fetch_add(1, Ordering::SeqCst);
+        self.total_run_nanos.fetch_add(nanos, Ordering::SeqCst);
+        self.max_run_nanos.fetch_max(nanos, Ordering::SeqCst);
+    }
+
+    // 実行前にキャンセルされていたジョブを一覧から取り除く
+    pub(crate) fn skipped(&self, info: &JobInfo) {
+        self.forget(info);
+        self.cancelled.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn forget(&self, info: &JobInfo) {
+        if self.tracking {
+            self.shard(info.id).remove(&info.id);
+        }
+    }
+
+    pub(crate) fn cancel(&self, id: JobId) -> bool {
+        match self.shard(id).get(&id).and_then(Weak::upgrade) {
+            Some(info) => {
+                info.cancel();
+                true
+            },
+            None => false,
+        }
+    }
+
+    // まだ終わっていないジョブの状態を、IDの順に返す
+    pub(crate) fn snapshot(&self) -> Vec<JobSnapshot> {
+        let now = Instant::now();
+        let mut snapshot = Vec::new();
+        for shard in &self.shards {
+            let mut jobs = lock(shard);
+            jobs.retain(|_, info| info.strong_count() > 0);
+            snapshot.extend(jobs.values().filter_map(Weak::upgrade).map(|info| info.snapshot(now)));
+        }
+        snapshot.sort_by_key(|job| job.id);
+        snapshot
+    }
+
+    pub(crate) fn stats(&self) -> RunStats {
+        RunStats {
+            completed: self.completed.load(Ordering::SeqCst),
+            cancelled: self.cancelled.load(Ordering::SeqCst),
+            total_run_time: Duration::from_nanos(self.total_run_nanos.load(Ordering::SeqCst)),
+            max_run_time: Duration::from_nanos(self.max_run_nanos.load(Ordering::SeqCst)),
+        }
+    }
+}
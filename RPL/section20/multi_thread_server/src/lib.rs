@@ -1,94 +1,576 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+// Workerの数やスレッドの設定を指定してThreadPoolを生成する
+mod builder;
+// 優先度ごとのレーンを持つ、容量を制限できるジョブキュー
+mod queue;
+// Workerごとのローカルキューと、他のWorkerからのジョブの盗み出し
+mod steal;
+// ローカル変数を借用できるジョブと、スライスを並列に処理するヘルパー
+mod scope;
+// ジョブのID、名前、キャンセル用のトークンと、実行中・待機中のジョブの一覧
+mod job;
+// HTTP/1.1のリクエストの解析とルーター
+pub mod http;
+
+pub use builder::{BuildError, Builder};
+pub use job::{CancellationToken, IntoJob, Job, JobId, JobSnapshot, JobState};
+pub use queue::{OverflowPolicy, Priority, Rejected};
+pub use scope::Scope;
+use job::{Registry, Task};
+use queue::{JobQueue, Pushed};
+use steal::Stealers;
+
+use crossbeam_deque::Worker as Deque;
 
 enum Message {
-    NewJob(Job),
+    NewJob(Task),
     Terminate,
 }
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    shared: Arc<Shared>,
+    // shutdown_timeoutで終了済みなら、Dropで再び終了処理をしない
+    terminated: bool,
 }
 
-trait FnBox {
-    // self: Box<Self>を取ってselfの所有権を奪い、Box<T>から値をムーブする
-    fn call_box(self: Box<Self>);
+// すべてのWorkerとThreadPoolで共有する状態
+struct Shared {
+    // プールの外から投入されたジョブのキュー
+    // Arc型で複数のWorkerにキューを共有させ、1つの仕事をたった1つのWorkerが受け取ることを保証する
+    queue: JobQueue,
+    // ジョブの中から投入されたジョブが入る、各Workerのローカルキューから盗むためのStealer
+    stealers: Stealers,
+    // Workerの数とスレッドの設定
+    config: Builder,
+    // 生成したWorker。終了したWorkerは、次にWorkerを生成するときにjoinして取り除く
+    workers: Mutex<Vec<Worker>>,
+    // 次に生成するWorkerのID。作り直したWorkerにも新しいIDを振る
+    next_id: AtomicUsize,
+    // 投入されたが、まだ終わっていないジョブの数
+    pending_jobs: AtomicUsize,
+    // ジョブを待っているWorkerの数
+    idle_workers: AtomicUsize,
+    // キューが満杯で拒否したジョブと、捨てたジョブの数
+    rejected_jobs: AtomicU64,
+    dropped_jobs: AtomicU64,
+    // 他のWorkerのローカルキューから盗んだジョブの数
+    stolen_jobs: AtomicU64,
+    // まだ終わっていないジョブの一覧と、終わったジョブの実行時間
+    jobs: Registry,
+    // 動作中のWorkerスレッドの数。Workerが終了するたびにexitedで通知する
+    // Workerを増やすか、アイドル状態のWorkerを終了させるかは、このロックを取って決める
+    live_workers: Mutex<usize>,
+    exited: Condvar,
 }
 
-// FnOnce()トレイトを実装する任意の型Fに対してFnBoxトレイトを実装
-// あらゆるFnOnce()クロージャがcall_boxメソッドを使用できる
-impl<F: FnOnce()> FnBox for F {
-    // (*self)()を使用してBox<T>からクロージャをムーブし、クロージャを呼び出す
-    fn call_box(self: Box<F>) {
-        (*self)()
-    }
+// Mutexのロックを取得する
+// ジョブはロックの外で実行するので、ジョブがパニックしてもMutexは毒されない
+// 万一毒されていても、中身はそのまま使える
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
-type Job = Box<dyn FnBox + Send + 'static>;
+impl Shared {
+    // ローカルキューがどのプールのものかを区別するための値
+    fn pool_id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    // 新しいIDでWorkerスレッドを生成してworkersに加え、そのIDを返す
+    // localには新しいWorkerに引き継ぐローカルキューを入れておく。生成に失敗したら、そのまま残る
+    // workersのロックは呼び出し側で取得しておく（ロックはworkers、live_workersの順に取る）
+    fn spawn_worker(
+        self: &Arc<Self>,
+        workers: &mut Vec<Worker>,
+        local: &Arc<Mutex<Option<Deque<Task>>>>,
+    ) -> io::Result<usize> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let shared = Arc::clone(self);
+        let local = Arc::clone(local);
+
+        // Workerがすぐに終了しても数が合うように、スレッドを生成する前に数えておく
+        *lock(&self.live_workers) += 1;
+        let spawned = self.config.thread_builder(id).spawn(move || {
+            let local = lock(&local).take().unwrap_or_else(steal::new_deque);
+            Worker::run(id, shared, local)
+        });
+        match spawned {
+            Ok(thread) => {
+                Worker::reap(workers);
+                workers.push(Worker {
+                    id,
+                    thread: Some(thread),
+                });
+                Ok(id)
+            },
+            Err(e) => {
+                self.worker_exited();
+                Err(e)
+            },
+        }
+    }
+
+    // ジョブを投入したときに空いているWorkerがいなければ、max_workersまでWorkerを増やす
+    fn grow_if_busy(self: &Arc<Self>) {
+        // ほとんどの場合はロックを取らずに判断できる
+        if self.idle_workers.load(Ordering::SeqCst) > 0 || self.config.min_workers == self.config.max_workers {
+            return;
+        }
+        let mut workers = lock(&self.workers);
+        {
+            let live_workers = lock(&self.live_workers);
+            if self.idle_workers.load(Ordering::SeqCst) > 0 || *live_workers >= self.config.max_workers {
+                return;
+            }
+        }
+        if let Err(e) = self.spawn_worker(&mut workers, &Default::default()) {
+            // 今いるWorkerがいずれジョブを処理するので、増やせなくても続ける
+            println!("Failed to spawn a worker: {}", e);
+        }
+    }
+
+    // アイドル状態が続いたWorkerを終了させてよいか判断し、よければ終了したものとして数える
+    // ジョブが投入された直後なら、そのジョブを処理するWorkerがいなくならないように終了させない
+    fn retire_idle_worker(&self) -> bool {
+        let mut live_workers = lock(&self.live_workers);
+        if *live_workers <= self.config.min_workers || self.queue.len() > 0 || self.stealers.len() > 0 {
+            return false;
+        }
+        *live_workers -= 1;
+        self.exited.notify_all();
+        true
+    }
+
+    // 自分のローカルキュー、プールのキュー、他のWorkerのローカルキューの順にジョブを探す
+    fn find_job(&self, id: usize) -> Option<Task> {
+        steal::pop()
+            .or_else(|| self.queue.try_pop())
+            .or_else(|| {
+                let job = self.stealers.steal(id);
+                if job.is_some() {
+                    self.stolen_jobs.fetch_add(1, Ordering::SeqCst);
+                }
+                job
+            })
+    }
+
+    // ジョブを投入する。ThreadPool::submitとスコープ付きのジョブから呼ばれる
+    fn submit<J: IntoJob>(self: &Arc<Self>, priority: Priority, job: J) -> Result<JobId, Rejected<J>> {
+        let id = self.jobs.next_id();
+        // Workerがジョブを終えて数を減らす前に、数えておく
+        self.pending_jobs.fetch_add(1, Ordering::SeqCst);
+
+        let job = match steal::push(self.pool_id(), job, |job| self.jobs.track(id, job.into_job(), None)) {
+            Ok(()) => {
+                // 眠っているWorkerがいれば起こして、盗みに行かせる
+                self.queue.notify_sleeper();
+                self.grow_if_busy();
+                return Ok(id);
+            },
+            Err(job) => job,
+        };
+
+        match self.queue.push(priority, job, |job| self.jobs.track(id, job.into_job(), Some(priority))) {
+            Ok(Pushed::Queued) => {},
+            Ok(Pushed::DroppedOldest(task)) => {
+                drop(task);
+                self.pending_jobs.fetch_sub(1, Ordering::SeqCst);
+                self.dropped_jobs.fetch_add(1, Ordering::SeqCst);
+            },
+            Ok(Pushed::DroppedNew) => {
+                self.pending_jobs.fetch_sub(1, Ordering::SeqCst);
+                self.dropped_jobs.fetch_add(1, Ordering::SeqCst);
+                return Ok(id);
+            },
+            Err(rejected) => {
+                self.pending_jobs.fetch_sub(1, Ordering::SeqCst);
+                self.rejected_jobs.fetch_add(1, Ordering::SeqCst);
+                return Err(rejected);
+            },
+        }
+
+        self.grow_if_busy();
+        Ok(id)
+    }
+
+    // workerのWorkerでジョブを実行し、終わったジョブとして数える。ジョブがパニックしたらfalseを返す
+    // 実行する前にキャンセルされていたら、実行せずに捨てる
+    fn run_job(&self, worker: usize, task: Task) -> bool {
+        let Task { info, f } = task;
+        if info.is_cancelled() {
+            self.jobs.skipped(&info);
+            self.pending_jobs.fetch_sub(1, Ordering::SeqCst);
+            return true;
+        }
+
+        let started_at = info.start(worker);
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        self.jobs.finished(&info, started_at.elapsed());
+        self.pending_jobs.fetch_sub(1, Ordering::SeqCst);
+        result.is_ok()
+    }
+
+    // Workerスレッドが終了したことを記録し、終了を待っているスレッドに通知する
+    fn worker_exited(&self) {
+        *lock(&self.live_workers) -= 1;
+        self.exited.notify_all();
+    }
+
+    // 他のすべてのWorkerスレッドが終了するまで待つ。timeoutがNoneなら無期限に待つ
+    // ジョブの中でプールを捨てたときは、そのジョブを実行しているWorker自身は待たない
+    // 時間内にすべて終了したらtrueを返す
+    fn wait_for_workers(&self, timeout: Option<Duration>) -> bool {
+        let this_worker = usize::from(steal::is_worker_of(self.pool_id()));
+        let live_workers = lock(&self.live_workers);
+        match timeout {
+            None => {
+                let _guard = self.exited
+                    .wait_while(live_workers, |live| *live > this_worker)
+                    .unwrap_or_else(PoisonError::into_inner);
+                true
+            },
+            Some(timeout) => {
+                let (_guard, result) = self.exited
+                    .wait_timeout_while(live_workers, timeout, |live| *live > this_worker)
+                    .unwrap_or_else(PoisonError::into_inner);
+                !result.timed_out()
+            },
+        }
+    }
+}
 
 impl ThreadPool {
     /// 新しいThreadPoolを生成する。
     ///
-    /// sizeがプールのスレッド数です。
+    /// sizeがプールのスレッド数です。Workerの数を増減させるには`Builder`を使います。
     ///
     /// # パニック
     ///
-    /// sizeが0なら、`new`関数はパニックします。
+    /// sizeが0か、スレッドを生成できなければ、`new`関数はパニックします。
     ///
-    // id番号を取り、idと空のクロージャで大量生産されるスレッドを保持するWorkerインスタンスを返す
     pub fn new(size: usize) -> ThreadPool {
         assert!(size > 0);
 
-        // 新しいチャンネルを作成
-        let (sender, receiver) = mpsc::channel();
+        Builder::new()
+            .min_workers(size)
+            .max_workers(size)
+            .build()
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Builder::buildから呼ばれ、min_workers個のWorkerを生成する
+    fn from_builder(config: Builder) -> io::Result<ThreadPool> {
+        // 新しいジョブキューを作成
+        let queue = JobQueue::new(config.queue_capacity, config.overflow_policy);
+        let min_workers = config.min_workers;
+        let track_jobs = config.track_jobs;
 
-        let receiver = Arc::new(Mutex::new(receiver));
-        // size個の要素を保持できる新しい空のベクタを生成
-        let mut workers = Vec::with_capacity(size);
-        // ThreadPool::newでforループカウンタを使用してidを生成し、そのidで新しいWorkerを生成し、ベクタにWorkerを格納
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+        let pool = ThreadPool {
+            shared: Arc::new(Shared {
+                queue,
+                stealers: Stealers::new(),
+                config,
+                workers: Mutex::new(Vec::with_capacity(min_workers)),
+                next_id: AtomicUsize::new(0),
+                pending_jobs: AtomicUsize::new(0),
+                idle_workers: AtomicUsize::new(0),
+                rejected_jobs: AtomicU64::new(0),
+                dropped_jobs: AtomicU64::new(0),
+                stolen_jobs: AtomicU64::new(0),
+                jobs: Registry::new(track_jobs),
+                live_workers: Mutex::new(0),
+                exited: Condvar::new(),
+            }),
+            terminated: false,
+        };
+        // 途中で失敗したら、生成済みのWorkerはpoolのDropで終了させる
+        {
+            let mut workers = lock(&pool.shared.workers);
+            for _ in 0..min_workers {
+                pool.shared.spawn_worker(&mut workers, &Default::default())?;
+            }
         }
 
-        ThreadPool {
-            workers,
-            sender,
+        Ok(pool)
+    }
+
+    // 動作中のWorkerの数
+    pub fn workers(&self) -> usize {
+        *lock(&self.shared.live_workers)
+    }
+
+    // キューの状態やジョブの数
+    pub fn metrics(&self) -> Metrics {
+        let (lane_depths, peak_queue_depth) = self.shared.queue.depths();
+        let stats = self.shared.jobs.stats();
+        Metrics {
+            queue_depth: lane_depths.iter().sum(),
+            lane_depths,
+            peak_queue_depth,
+            local_queue_depth: self.shared.stealers.len(),
+            pending_jobs: self.shared.pending_jobs.load(Ordering::SeqCst),
+            rejected_jobs: self.shared.rejected_jobs.load(Ordering::SeqCst),
+            dropped_jobs: self.shared.dropped_jobs.load(Ordering::SeqCst),
+            stolen_jobs: self.shared.stolen_jobs.load(Ordering::SeqCst),
+            completed_jobs: stats.completed,
+            cancelled_jobs: stats.cancelled,
+            total_run_time: stats.total_run_time,
+            max_run_time: stats.max_run_time,
+            workers: self.workers(),
+            idle_workers: self.shared.idle_workers.load(Ordering::SeqCst),
         }
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// 通常の優先度でジョブを投入する。
+    ///
+    /// クロージャのほか、名前やキャンセル用のトークンを付けた`Job`も投入できます。
+    /// キューが満杯のときは`Builder::overflow_policy`に従います。`OverflowPolicy::Reject`で
+    /// 拒否されたジョブは捨てられます。拒否されたジョブを取り戻すには`submit`を使います。
+    pub fn execute<J: IntoJob>(&self, job: J) {
+        if self.submit(Priority::Normal, job).is_err() {
+            println!("Job queue is full; dropping the rejected job.");
+        }
+    }
+
+    /// 優先度を指定してジョブを投入し、ジョブのIDを返す。
+    ///
+    /// キューが満杯で、`OverflowPolicy::Reject`なら、ジョブを`Rejected`に入れて返します。
+    ///
+    /// このプールのジョブの中から投入したジョブは、優先度やキューの容量に関係なく、
+    /// そのWorkerのローカルキューに入ります。ローカルキューのジョブはそのWorkerが
+    /// 後に入ったものから実行し、手の空いた他のWorkerは先に入ったものから盗んで実行します。
+    pub fn submit<J: IntoJob>(&self, priority: Priority, job: J) -> Result<JobId, Rejected<J>> {
+        self.shared.submit(priority, job)
+    }
+
+    /// まだ終わっていないジョブ（実行中のものとキューで待っているもの）の状態を、IDの順に返す。
+    pub fn jobs(&self) -> Vec<JobSnapshot> {
+        self.shared.jobs.snapshot()
+    }
+
+    /// idのジョブをキャンセルする。ジョブがすでに終わっていれば`false`を返します。
+    ///
+    /// まだ始まっていないジョブは実行されずに捨てられます。実行中のジョブは、
+    /// `Job::cancellable`で受け取ったトークンを見て自分で処理を打ち切ります。
+    pub fn cancel(&self, id: JobId) -> bool {
+        self.shared.jobs.cancel(id)
+    }
+
+    /// ジョブを実行し、その戻り値を受け取るためのハンドルを返す。
+    ///
+    /// ジョブがパニックした場合は、パニックのペイロードをハンドルから受け取れます。
+    /// このときWorkerスレッドは影響を受けません。
+    pub fn execute_with_result<F, T>(&self, f: F) -> JobHandle<T>
         where
-            F: FnOnce() + Send + 'static
+            F: FnOnce() -> T + Send + 'static,
+            T: Send + 'static
     {
-        let job = Box::new(f);
+        let (sender, receiver) = mpsc::channel();
+
+        // 拒否されたジョブは捨てるので、ハンドルからはJobError::Lostが返る
+        self.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            // ハンドルがすでに捨てられていたら、結果も捨てる
+            let _ = sender.send(result);
+        });
 
-        self.sender.send(Message::NewJob(job)).unwrap();
+        JobHandle { receiver }
     }
-}
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
+    /// すべてのWorkerに終了を指示し、最大timeoutだけ終了を待つ。
+    ///
+    /// 時間内に終わったジョブの結果は通常どおり受け取れます。時間切れになったら、
+    /// まだ始まっていないジョブは実行せずに捨て、実行中のジョブのWorkerスレッドは
+    /// 待たずに切り離します。終わらなかったジョブの数を`ShutdownReport`で返します。
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> ShutdownReport {
+        self.terminate(Some(timeout))
+    }
+
+    fn terminate(&mut self, timeout: Option<Duration>) -> ShutdownReport {
+        self.terminated = true;
+
         println!("Sending terminate message to all workers.");
 
-        for _ in &mut self.workers {
-            self.sender.send(Message::Terminate).unwrap();
-        }
+        // プールの終了中はWorkerが増えないので、今動作中のWorkerの数だけ指示すればよい
+        // パニックしたWorkerの作り直しやアイドル状態での終了で余った指示は、誰も受け取らずに捨てられる
+        let live_workers = *lock(&self.shared.live_workers);
+        self.shared.queue.terminate(live_workers);
 
         println!("Shutting down all workers.");
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        let finished = self.shared.wait_for_workers(timeout);
+        let unfinished_jobs = self.shared.pending_jobs.load(Ordering::SeqCst);
+        if !finished {
+            // 時間切れになったので、まだ始まっていないジョブは実行せずに捨てる
+            let abandoned = self.shared.queue.clear() + self.shared.stealers.clear();
+            self.shared.pending_jobs.fetch_sub(abandoned, Ordering::SeqCst);
+        }
 
+        let workers = mem::take(&mut *lock(&self.shared.workers));
+        let mut running_workers = 0;
+        for mut worker in workers {
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                if thread.thread().id() == thread::current().id() {
+                    // ジョブの中でプールを捨てたので、このWorkerはジョブを終えてから終了する
+                    continue;
+                }
+                // 時間切れのときは、終了済みのスレッドだけをjoinする
+                if finished || thread.is_finished() {
+                    println!("Shutting down worker {}", worker.id);
+                    // ジョブのパニックはWorkerの中で捕まえているので、joinは失敗しない
+                    let _ = thread.join();
+                } else {
+                    println!("Worker {} did not stop in time; detaching.", worker.id);
+                    running_workers += 1;
+                }
             }
         }
+
+        ShutdownReport {
+            unfinished_jobs,
+            running_workers,
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        if !self.terminated {
+            self.terminate(None);
+        }
+    }
+}
+
+/// `ThreadPool::metrics`が返す、キューの状態やジョブの数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    /// キューに溜まっているジョブの数
+    pub queue_depth: usize,
+    /// 優先度ごとのキューに溜まっているジョブの数。`Priority::ALL`の順
+    pub lane_depths: [usize; 3],
+    /// これまでで最も多くキューにジョブが溜まったときの数
+    pub peak_queue_depth: usize,
+    /// 投入されたが、まだ終わっていないジョブの数（キューに溜まっているものと実行中のもの）
+    pub pending_jobs: usize,
+    /// キューが満杯で拒否したジョブの数
+    pub rejected_jobs: u64,
+    /// キューが満杯で捨てたジョブの数
+    pub dropped_jobs: u64,
+    /// ジョブの中から投入され、Workerのローカルキューに溜まっているジョブの数
+    pub local_queue_depth: usize,
+    /// 他のWorkerのローカルキューから盗んで実行したジョブの数
+    pub stolen_jobs: u64,
+    /// 実行を終えたジョブの数（パニックしたものを含む）
+    pub completed_jobs: u64,
+    /// 実行前にキャンセルされて捨てたジョブの数
+    pub cancelled_jobs: u64,
+    /// 実行を終えたジョブの実行時間の合計と、最も長かったもの
+    pub total_run_time: Duration,
+    pub max_run_time: Duration,
+    /// 動作中のWorkerの数
+    pub workers: usize,
+    /// ジョブを待っているWorkerの数
+    pub idle_workers: usize,
+}
+
+/// `ThreadPool::shutdown_timeout`の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// 時間内に終わらなかったジョブの数（実行中だったものと、実行されずに捨てられたもの）
+    pub unfinished_jobs: usize,
+    /// 時間内に終了せず、切り離したWorkerスレッドの数
+    pub running_workers: usize,
+}
+
+impl ShutdownReport {
+    // すべてのジョブが終わり、すべてのWorkerが終了したか
+    pub fn is_complete(&self) -> bool {
+        self.unfinished_jobs == 0 && self.running_workers == 0
+    }
+}
+
+/// `ThreadPool::execute_with_result`で投入したジョブの結果を受け取るハンドル
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<thread::Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    // ジョブが終わるまで待ち、その戻り値を返す
+    pub fn join(self) -> Result<T, JobError> {
+        match self.receiver.recv() {
+            Ok(result) => result.map_err(JobError::Panicked),
+            Err(mpsc::RecvError) => Err(JobError::Lost),
+        }
+    }
+
+    // ジョブが終わるまで最大timeoutだけ待つ
+    // 時間切れならJobError::Timeoutを返すので、もう一度待つこともできる
+    pub fn join_timeout(&self, timeout: Duration) -> Result<T, JobError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result.map_err(JobError::Panicked),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(JobError::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(JobError::Lost),
+        }
     }
 }
 
+/// ジョブの結果を受け取れなかった理由
+#[derive(Debug)]
+pub enum JobError {
+    /// ジョブがパニックした。パニックのペイロードを保持する
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// ジョブが実行されずに捨てられた（プールが終了した）か、結果をすでに受け取った
+    Lost,
+    /// 待ち時間内にジョブが終わらなかった
+    Timeout,
+}
+
+impl JobError {
+    // panic!に渡されたメッセージ。メッセージが文字列でなければNone
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            JobError::Panicked(payload) => panic_message(payload.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked(payload) => match panic_message(payload.as_ref()) {
+                Some(message) => write!(f, "job panicked: {}", message),
+                None => write!(f, "job panicked"),
+            },
+            JobError::Lost => write!(f, "job was dropped before it finished"),
+            JobError::Timeout => write!(f, "timed out waiting for the job"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+// panic!("...")のペイロードは&str、panic!("{}", x)のペイロードはStringになる
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+
 // idとJoinHandle<()>を保持するWorker構造体
 struct Worker {
     id: usize,
@@ -98,34 +580,587 @@ struct Worker {
 
 impl Worker {
     // 複数のスレッドで所有権を共有しつつ、 スレッドに値を可変化させるためには、Arc<Mutex<T>>を使用
-    // Arc型で複数のWorkerにreceiverを共有させ、Mutexにより、1度にreceiverから1つの仕事をたった1つのWorkerが受け取ることを保証する
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) ->
-        Worker {
-        // 空のクロージャを使って新しいスレッドを立ち上げ、生成されるJoinHandle<()>インスタンスを格納
-        let thread = thread::spawn(move ||{
-            loop {
-                // receiverに対して`lock()`を呼び出し、mutexを取得。失敗する可能性があるため`unwrap()`を使用
-                // recvを呼び出してチャンネルからJobを受け取る
-                let message = receiver.lock().unwrap().recv().unwrap();
-
-                match message {
-                    Message::NewJob(job) => {
+    // Workerは共有状態をArcで保持し、その中のキューからジョブを受け取る
+    fn run(id: usize, shared: Arc<Shared>, local: Deque<Task>) {
+        shared.stealers.register(id, local.stealer());
+        steal::install(shared.pool_id(), id, local);
+
+        loop {
+            // ローカルキュー、プールのキュー、他のWorkerのローカルキューの順にJobを探す
+            // 見つからなければ眠り、idle_timeoutの間に何も来なければNone
+            shared.idle_workers.fetch_add(1, Ordering::SeqCst);
+            let message = shared.queue.pop(shared.config.idle_timeout, || shared.find_job(id));
+            shared.idle_workers.fetch_sub(1, Ordering::SeqCst);
+
+            match message {
+                Some(Message::NewJob(job)) => {
+                    if shared.config.log_jobs {
                         println!("Worker {} got a job; executing.", id);
+                    }
 
-                        job.call_box();
-                    },
-                    Message::Terminate => {
-                        println!("Worker {} was told to terminate.", id);
+                    if !shared.run_job(id, job) && Worker::respawn(id, &shared) {
+                        // ローカルキューを引き継いだ代わりのスレッドに処理を任せて、このスレッドは終了する
+                        shared.worker_exited();
+                        return;
+                    }
+                },
+                Some(Message::Terminate) => {
+                    println!("Worker {} was told to terminate.", id);
 
-                        break;
-                    },
-                }
+                    break;
+                },
+                None => {
+                    if shared.retire_idle_worker() {
+                        println!("Worker {} was idle for {:?}; exiting.", id, shared.config.idle_timeout);
+
+                        // retire_idle_workerで終了したものとして数えたので、worker_exitedは呼ばない
+                        shared.stealers.unregister(id);
+                        steal::uninstall();
+                        return;
+                    }
+                },
             }
+        }
+
+        // 終了の指示は、ローカルキューが空になってから受け取る
+        shared.stealers.unregister(id);
+        steal::uninstall();
+        shared.worker_exited();
+    }
+
+    // パニックしたジョブを実行したスレッドの代わりに、新しいIDで新しいスレッドを立ち上げる
+    // ローカルキューに残っているジョブは、新しいスレッドに引き継ぐ
+    // スレッドを立ち上げられなかったら、falseを返して今のスレッドで処理を続ける
+    fn respawn(id: usize, shared: &Arc<Shared>) -> bool {
+        let local = match steal::uninstall() {
+            Some(local) => Arc::new(Mutex::new(Some(local))),
+            None => Default::default(),
+        };
+        shared.stealers.unregister(id);
+
+        // 終了処理がこのWorkerを見落とさないように、ロックを保持したまま立ち上げて登録する
+        let mut workers = lock(&shared.workers);
+        match shared.spawn_worker(&mut workers, &local) {
+            Ok(new_id) => {
+                println!("Worker {} panicked while executing a job; respawned as worker {}.", id, new_id);
+                true
+            },
+            Err(e) => {
+                println!("Worker {} panicked while executing a job; could not respawn: {}", id, e);
+                // ローカルキューを取り戻して、このスレッドで処理を続ける
+                let local = lock(&local).take().unwrap_or_else(steal::new_deque);
+                shared.stealers.register(id, local.stealer());
+                steal::install(shared.pool_id(), id, local);
+                false
+            },
+        }
+    }
+
+    // 終了したWorkerのスレッドをjoinして取り除く
+    fn reap(workers: &mut Vec<Worker>) {
+        workers.retain_mut(|worker| match worker.thread.take() {
+            Some(thread) if thread.is_finished() => {
+                let _ = thread.join();
+                false
+            },
+            thread => {
+                worker.thread = thread;
+                true
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execute_with_result_returns_value() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..8).map(|i| pool.execute_with_result(move || i * i)).collect();
+        let results: Vec<i32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![0, 1, 4, 9, 16, 25, 36, 49]);
+    }
+
+    #[test]
+    fn panicking_job_does_not_kill_pool() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.execute_with_result(|| -> i32 { panic!("boom") });
+        let err = handle.join().unwrap_err();
+        assert_eq!(err.panic_message(), Some("boom"));
+
+        // 結果を受け取らないジョブがパニックしても、Workerが作り直されて処理が続く
+        pool.execute(|| panic!("boom"));
+        pool.execute(|| panic!("{}", String::from("boom")));
+        assert_eq!(pool.execute_with_result(|| 42).join().unwrap(), 42);
+
+        let report = pool.shutdown_timeout(Duration::from_secs(10));
+        assert!(report.is_complete());
+    }
+
+    #[test]
+    fn shutdown_timeout_reports_unfinished_jobs() {
+        let pool = ThreadPool::new(1);
+        let slow = pool.execute_with_result(|| thread::sleep(Duration::from_secs(1)));
+        let queued = pool.execute_with_result(|| 1);
+
+        let report = pool.shutdown_timeout(Duration::from_millis(100));
+        assert_eq!(report, ShutdownReport { unfinished_jobs: 2, running_workers: 1 });
+
+        // 実行中だったジョブは終わるが、まだ始まっていなかったジョブは捨てられる
+        assert!(slow.join().is_ok());
+        assert!(matches!(queued.join(), Err(JobError::Lost)));
+    }
+
+    #[test]
+    fn join_timeout() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.execute_with_result(|| {
+            thread::sleep(Duration::from_millis(200));
+            "done"
+        });
+        assert!(matches!(handle.join_timeout(Duration::from_millis(1)), Err(JobError::Timeout)));
+        assert_eq!(handle.join_timeout(Duration::from_secs(10)).unwrap(), "done");
+        assert!(matches!(handle.join_timeout(Duration::from_secs(10)), Err(JobError::Lost)));
+    }
+
+    // 条件が成り立つまで、最大5秒待つ
+    fn wait_until<F: Fn() -> bool>(condition: F) -> bool {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while std::time::Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        condition()
+    }
+
+    #[test]
+    fn builder_rejects_invalid_sizes() {
+        assert!(matches!(Builder::new().max_workers(0).build(), Err(BuildError::NoWorkers)));
+        assert!(matches!(
+            Builder::new().min_workers(3).max_workers(2).build(),
+            Err(BuildError::MinExceedsMax { min_workers: 3, max_workers: 2 })
+        ));
+    }
+
+    #[test]
+    fn pool_grows_and_shrinks() {
+        let pool = Builder::new()
+            .min_workers(1)
+            .max_workers(4)
+            .idle_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        assert_eq!(pool.workers(), 1);
+
+        // 4つのジョブがすべて同時に動き出せば、Workerが4つに増えている
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel::<()>();
+        let release = Arc::new(Mutex::new(release));
+        for _ in 0..4 {
+            let started_sender = started_sender.clone();
+            let release = Arc::clone(&release);
+            pool.execute(move || {
+                started_sender.send(()).unwrap();
+                // すべてのジョブが動き出すまで終わらない
+                let _ = lock(&release).recv();
+            });
+        }
+        for _ in 0..4 {
+            started.recv_timeout(Duration::from_secs(5)).expect("pool did not grow");
+        }
+        assert_eq!(pool.workers(), 4);
+        drop(release_sender);
+
+        // ジョブがなくなると、min_workersまで減る
+        assert!(wait_until(|| pool.workers() == 1));
+        assert_eq!(pool.execute_with_result(|| 1).join().unwrap(), 1);
+    }
+
+    #[test]
+    fn pool_without_min_workers_starts_on_demand() {
+        let pool = Builder::new()
+            .min_workers(0)
+            .max_workers(2)
+            .idle_timeout(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        assert_eq!(pool.workers(), 0);
+        assert_eq!(pool.execute_with_result(|| 1).join().unwrap(), 1);
+        assert!(wait_until(|| pool.workers() == 0));
+        assert_eq!(pool.execute_with_result(|| 2).join().unwrap(), 2);
+    }
+
+    #[test]
+    fn worker_threads_are_named_with_unique_ids() {
+        let pool = Builder::new()
+            .min_workers(1)
+            .max_workers(1)
+            .thread_name("pool")
+            .stack_size(256 * 1024)
+            .build()
+            .unwrap();
+        let name = || thread::current().name().map(String::from);
+        assert_eq!(pool.execute_with_result(name).join().unwrap().as_deref(), Some("pool-0"));
+
+        // パニックしたWorkerの代わりのWorkerには、新しいIDが振られる
+        pool.execute(|| panic!("boom"));
+        assert_eq!(pool.execute_with_result(name).join().unwrap().as_deref(), Some("pool-1"));
+    }
+
+    // 1つしかないWorkerを、返したSenderを捨てるまで動けなくする
+    fn occupy_worker(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = release.recv();
         });
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        release_sender
+    }
 
-        Worker {
-            id,
-            thread: Some(thread),
+    fn single_worker_pool(capacity: usize, policy: OverflowPolicy) -> ThreadPool {
+        Builder::new()
+            .min_workers(1)
+            .max_workers(1)
+            .queue_capacity(capacity)
+            .overflow_policy(policy)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn reject_returns_job() {
+        let pool = single_worker_pool(1, OverflowPolicy::Reject);
+        let release = occupy_worker(&pool);
+
+        assert!(pool.submit(Priority::Normal, || {}).is_ok());
+        let (sender, receiver) = mpsc::channel();
+        let rejected = pool.submit(Priority::High, move || sender.send(42).unwrap()).unwrap_err();
+        // 拒否されたジョブを取り戻して、その場で実行する
+        rejected.into_inner()();
+        assert_eq!(receiver.recv().unwrap(), 42);
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.queue_depth, 1);
+        assert_eq!(metrics.lane_depths, [0, 1, 0]);
+        assert_eq!(metrics.rejected_jobs, 1);
+        assert_eq!(metrics.pending_jobs, 2);
+        drop(release);
+    }
+
+    #[test]
+    fn drop_oldest_drops_lowest_priority() {
+        let pool = single_worker_pool(2, OverflowPolicy::DropOldest);
+        let release = occupy_worker(&pool);
+
+        let low = pool.execute_with_result(|| "low");
+        let first = pool.execute_with_result(|| "first");
+        // 満杯なので、最も優先度の低いジョブが捨てられる
+        let second = pool.execute_with_result(|| "second");
+        // 通常の優先度のジョブしかないので、優先度の低いジョブは自分が捨てられる
+        let (sender, receiver) = mpsc::channel();
+        pool.submit(Priority::Low, move || sender.send(()).unwrap()).unwrap();
+        assert_eq!(pool.metrics().dropped_jobs, 2);
+        assert_eq!(pool.metrics().peak_queue_depth, 2);
+
+        drop(release);
+        assert!(matches!(low.join(), Err(JobError::Lost)));
+        assert_eq!(first.join().unwrap(), "first");
+        assert_eq!(second.join().unwrap(), "second");
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let pool = single_worker_pool(10, OverflowPolicy::Block);
+        let release = occupy_worker(&pool);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for &priority in &[Priority::Low, Priority::Normal, Priority::High, Priority::Normal] {
+            let order = Arc::clone(&order);
+            pool.submit(priority, move || lock(&order).push(priority)).unwrap();
         }
+        assert_eq!(pool.metrics().lane_depths, [1, 2, 1]);
+
+        drop(release);
+        let report = pool.shutdown_timeout(Duration::from_secs(5));
+        assert!(report.is_complete());
+        assert_eq!(
+            *lock(&order),
+            vec![Priority::High, Priority::Normal, Priority::Normal, Priority::Low]
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn block_waits_for_space() {
+        let pool = Arc::new(single_worker_pool(1, OverflowPolicy::Block));
+        let release = occupy_worker(&pool);
+        pool.execute(|| {});
+
+        // キューが満杯なので、空きができるまで投入したスレッドが待たされる
+        let (submitted_sender, submitted) = mpsc::channel();
+        let submitter = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                pool.execute(|| {});
+                submitted_sender.send(()).unwrap();
+            })
+        };
+        assert!(submitted.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(release);
+        submitted.recv_timeout(Duration::from_secs(5)).unwrap();
+        submitter.join().unwrap();
+        assert_eq!(pool.metrics().rejected_jobs, 0);
+    }
+
+    #[test]
+    fn jobs_spawned_from_jobs_are_stolen() {
+        let pool = Arc::new(Builder::new().min_workers(4).max_workers(4).build().unwrap());
+        let (done_sender, done) = mpsc::channel();
+        let inner_pool = Arc::clone(&pool);
+        let outer = pool.execute_with_result(move || {
+            for _ in 0..100 {
+                let done_sender = done_sender.clone();
+                inner_pool.execute(move || done_sender.send(()).unwrap());
+            }
+            // このWorkerはここで待つので、ローカルキューのジョブは他のWorkerが盗んで実行する
+            for _ in 0..100 {
+                done.recv_timeout(Duration::from_secs(5)).expect("jobs were not stolen");
+            }
+        });
+        outer.join().unwrap();
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.stolen_jobs, 100);
+        assert_eq!(metrics.local_queue_depth, 0);
+    }
+
+    #[test]
+    fn local_jobs_run_last_in_first_out() {
+        let pool = Arc::new(ThreadPool::new(1));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (inner_pool, inner_order) = (Arc::clone(&pool), Arc::clone(&order));
+        pool.execute(move || {
+            for i in 0..3 {
+                let order = Arc::clone(&inner_order);
+                inner_pool.execute(move || lock(&order).push(i));
+            }
+        });
+        assert!(wait_until(|| lock(&order).len() == 3));
+        assert_eq!(*lock(&order), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn pool_dropped_inside_job() {
+        let pool = Arc::new(ThreadPool::new(2));
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel::<()>();
+        let (dropped_sender, dropped) = mpsc::channel();
+        let last = Arc::clone(&pool);
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = release.recv();
+            // 最後の参照をジョブの中で捨てても、自分自身の終了を待って止まることはない
+            drop(last);
+            dropped_sender.send(()).unwrap();
+        });
+        started.recv().unwrap();
+        drop(pool);
+        drop(release_sender);
+        dropped.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn scoped_jobs_borrow_local_variables() {
+        let pool = ThreadPool::new(4);
+        let words = vec!["alpha", "beta", "gamma"];
+        let total = Mutex::new(0);
+        let count = pool.scope(|s| {
+            for word in &words {
+                let total = &total;
+                s.execute(move |_| *lock(total) += word.len());
+            }
+            words.len()
+        });
+        assert_eq!(count, 3);
+        assert_eq!(*lock(&total), 14);
+    }
+
+    #[test]
+    fn scoped_jobs_can_spawn_more_jobs() {
+        let pool = ThreadPool::new(2);
+        let counter = AtomicUsize::new(0);
+        pool.scope(|s| {
+            for _ in 0..4 {
+                s.execute(|s| {
+                    for _ in 0..4 {
+                        s.execute(|_| {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                        });
+                    }
+                });
+            }
+        });
+        assert_eq!(counter.load(Ordering::SeqCst), 16);
+    }
+
+    #[test]
+    fn scope_inside_job_does_not_deadlock() {
+        // Workerが1つしかなくても、スコープの終わりを待つWorkerが自分でジョブを実行する
+        let pool = Arc::new(ThreadPool::new(1));
+        let inner_pool = Arc::clone(&pool);
+        let sum = pool.execute_with_result(move || {
+            let items: Vec<u64> = (1..=100).collect();
+            inner_pool.par_map(&items, |i| i * 2).iter().sum::<u64>()
+        });
+        assert_eq!(sum.join_timeout(Duration::from_secs(5)).unwrap(), 10100);
+    }
+
+    #[test]
+    fn scope_propagates_panics_after_all_jobs_finish() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.execute(|_| panic!("boom"));
+                for _ in 0..10 {
+                    s.execute(|_| {
+                        thread::sleep(Duration::from_millis(1));
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+        assert_eq!(panic_message(result.unwrap_err().as_ref()), Some("boom"));
+        assert_eq!(finished.load(Ordering::SeqCst), 10);
+        assert_eq!(pool.execute_with_result(|| 1).join().unwrap(), 1);
+    }
+
+    #[test]
+    fn par_map_keeps_order() {
+        let pool = ThreadPool::new(3);
+        let items: Vec<usize> = (0..1000).collect();
+        let squares = pool.par_map(&items, |i| i * i);
+        assert_eq!(squares, items.iter().map(|i| i * i).collect::<Vec<_>>());
+        assert!(pool.par_map(&[] as &[usize], |i| *i).is_empty());
+
+        let sum = AtomicUsize::new(0);
+        pool.par_for_each(&items, |i| {
+            sum.fetch_add(*i, Ordering::SeqCst);
+        });
+        assert_eq!(sum.load(Ordering::SeqCst), 499500);
+    }
+
+    #[test]
+    fn scoped_jobs_rejected_by_full_queue_run_inline() {
+        let pool = single_worker_pool(1, OverflowPolicy::Reject);
+        let release = occupy_worker(&pool);
+        let ran_on = Mutex::new(Vec::new());
+        let caller = thread::current().id();
+        let (queued_sender, queued) = mpsc::channel();
+        let waiter = thread::spawn(move || {
+            queued.recv().unwrap();
+            thread::sleep(Duration::from_millis(50));
+            drop(release);
+        });
+        pool.scope(|s| {
+            s.execute(|_| lock(&ran_on).push(thread::current().id()));
+            s.execute(|_| lock(&ran_on).push(thread::current().id()));
+            queued_sender.send(()).unwrap();
+        });
+        waiter.join().unwrap();
+        let ran_on = lock(&ran_on);
+        assert_eq!(ran_on.len(), 2);
+        assert_eq!(ran_on.iter().filter(|&&id| id == caller).count(), 1);
+    }
+
+    #[test]
+    fn jobs_lists_running_and_queued_jobs() {
+        let pool = single_worker_pool(10, OverflowPolicy::Block);
+        let (started_sender, started) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel::<()>();
+        let running = pool
+            .submit(Priority::Normal, Job::new(move || {
+                started_sender.send(()).unwrap();
+                let _ = release.recv();
+            }).name("slow"))
+            .unwrap();
+        started.recv_timeout(Duration::from_secs(5)).unwrap();
+        let queued = pool.submit(Priority::High, Job::new(|| {}).name("queued")).unwrap();
+
+        let jobs = pool.jobs();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!((jobs[0].id, jobs[0].name.as_deref()), (running, Some("slow")));
+        assert!(matches!(jobs[0].state, JobState::Running { worker: 0, .. }));
+        assert_eq!((jobs[1].id, jobs[1].name.as_deref()), (queued, Some("queued")));
+        assert_eq!(jobs[1].priority, Some(Priority::High));
+        assert!(!jobs[1].is_running());
+
+        drop(release_sender);
+        assert!(wait_until(|| pool.jobs().is_empty()));
+        assert_eq!(pool.metrics().completed_jobs, 2);
+    }
+
+    #[test]
+    fn cancelled_jobs_are_skipped() {
+        let pool = single_worker_pool(10, OverflowPolicy::Block);
+        let release = occupy_worker(&pool);
+        let (sender, receiver) = mpsc::channel();
+        let id = pool.submit(Priority::Normal, move || sender.send(()).unwrap()).unwrap();
+        assert!(pool.cancel(id));
+        assert!(pool.jobs()[1].cancelled);
+
+        drop(release);
+        // 実行されずに捨てられたので、Senderも捨てられている
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_err());
+        assert_eq!(pool.metrics().cancelled_jobs, 1);
+        assert!(!pool.cancel(id));
+    }
+
+    #[test]
+    fn running_job_stops_when_cancelled() {
+        let pool = ThreadPool::new(1);
+        let (stopped_sender, stopped) = mpsc::channel();
+        let job = Job::cancellable(move |token| {
+            while !token.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            stopped_sender.send(()).unwrap();
+        });
+        let token = job.token().unwrap().clone();
+        pool.execute(job);
+        assert!(wait_until(|| pool.jobs().iter().any(JobSnapshot::is_running)));
+        token.cancel();
+        stopped.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn run_time_is_tracked() {
+        let pool = ThreadPool::new(2);
+        pool.execute_with_result(|| thread::sleep(Duration::from_millis(20))).join().unwrap();
+        pool.execute_with_result(|| {}).join().unwrap();
+        assert!(wait_until(|| pool.metrics().completed_jobs == 2));
+
+        let metrics = pool.metrics();
+        assert!(metrics.max_run_time >= Duration::from_millis(20));
+        assert!(metrics.total_run_time >= metrics.max_run_time);
+    }
+
+    #[test]
+    fn untracked_pool_only_keeps_totals() {
+        let pool = Builder::new().min_workers(1).max_workers(1).track_jobs(false).build().unwrap();
+        let release = occupy_worker(&pool);
+        let id = pool.submit(Priority::Normal, || {}).unwrap();
+        assert!(pool.jobs().is_empty());
+        assert!(!pool.cancel(id));
+
+        drop(release);
+        assert!(wait_until(|| pool.metrics().completed_jobs == 2));
+    }
+}
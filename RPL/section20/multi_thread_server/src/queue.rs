@@ -0,0 +1,305 @@
+use std::error::Error;
+use std::fmt;
+use std::iter;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crossbeam_deque::{Injector, Steal};
+
+use super::job::Task;
+use super::{lock, Message};
+
+/// ジョブの優先度。キューに溜まったジョブは、優先度の高いレーンから順に実行します。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// ヘルスチェックなど、待たせたくないジョブ
+    High,
+    #[default]
+    Normal,
+    /// 時間がかかっても構わないジョブ
+    Low,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    // レーンの添字。優先度が高いほど小さい
+    fn lane(self) -> usize {
+        self as usize
+    }
+}
+
+/// キューが満杯のときにジョブを投入したらどうするか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// 空きができるまで投入したスレッドを待たせる
+    #[default]
+    Block,
+    /// 投入したジョブを`Rejected`に入れて返す
+    Reject,
+    /// 投入したジョブと同じか低い優先度のジョブのうち、最も優先度が低く最も古いものを捨てる。
+    /// そのようなジョブがなければ、投入したジョブを捨てる
+    DropOldest,
+}
+
+/// キューが満杯で拒否されたジョブ。`into_inner`で投入したクロージャを取り戻せます。
+pub struct Rejected<F>(F);
+
+impl<F> Rejected<F> {
+    pub fn into_inner(self) -> F {
+        self.0
+    }
+}
+
+impl<F> fmt::Debug for Rejected<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Rejected { .. }")
+    }
+}
+
+impl<F> fmt::Display for Rejected<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("job queue is full")
+    }
+}
+
+impl<F> Error for Rejected<F> {}
+
+// ジョブをキューに入れた結果
+pub(crate) enum Pushed {
+    Queued,
+    // 代わりに捨てたジョブ
+    DroppedOldest(Task),
+    // 投入したジョブを捨てた
+    DroppedNew,
+}
+
+// 眠っているWorkerに渡す終了の指示
+struct Sleep {
+    // まだWorkerが受け取っていない終了の指示の数
+    terminate: usize,
+}
+
+// 優先度ごとのレーンを持つジョブキュー。容量を指定すると、満杯のときはOverflowPolicyに従う
+// ジョブの出し入れはロックを取らずに行い、Workerを眠らせたり起こしたりするときだけロックを取る
+pub(crate) struct JobQueue {
+    lanes: [Injector<Task>; 3],
+    // 溜まっているジョブの数。容量の判定に使うので、レーンに入れる前に増やす
+    len: AtomicUsize,
+    // これまでで最も多くジョブが溜まったときの数
+    peak: AtomicUsize,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    sleep: Mutex<Sleep>,
+    // ジョブか終了の指示が入ったことを、眠っているWorkerに知らせる
+    available: Condvar,
+    // 眠ろうとしているWorkerの数
+    sleepers: AtomicUsize,
+    // 起こしたWorkerがまだ起きていない。その間は、さらに起こさない
+    notified: AtomicBool,
+    // 空きができたことを、Blockで待っているスレッドに知らせる
+    space_lock: Mutex<()>,
+    space: Condvar,
+    // 空きを待っているスレッドの数
+    blocked: AtomicUsize,
+}
+
+impl JobQueue {
+    pub(crate) fn new(capacity: Option<usize>, policy: OverflowPolicy) -> JobQueue {
+        JobQueue {
+            lanes: [Injector::new(), Injector::new(), Injector::new()],
+            len: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            capacity,
+            policy,
+            sleep: Mutex::new(Sleep { terminate: 0 }),
+            available: Condvar::new(),
+            sleepers: AtomicUsize::new(0),
+            notified: AtomicBool::new(false),
+            space_lock: Mutex::new(()),
+            space: Condvar::new(),
+            blocked: AtomicUsize::new(0),
+        }
+    }
+
+    // 容量を確保できたら、jobをinto_taskでTaskにしてキューに入れる
+    pub(crate) fn push<J, C>(&self, priority: Priority, job: J, into_task: C) -> Result<Pushed, Rejected<J>>
+        where
+            C: FnOnce(J) -> Task
+    {
+        let mut pushed = Pushed::Queued;
+
+        if !self.reserve() {
+            match self.policy {
+                OverflowPolicy::Block => self.wait_for_space(),
+                OverflowPolicy::Reject => return Err(Rejected(job)),
+                OverflowPolicy::DropOldest => {
+                    // 捨てたジョブの分の空きを、投入したジョブに使う
+                    let victim = (priority.lane()..Priority::ALL.len())
+                        .rev()
+                        .find_map(|lane| steal(&self.lanes[lane]));
+                    match victim {
+                        Some(task) => pushed = Pushed::DroppedOldest(task),
+                        None => return Ok(Pushed::DroppedNew),
+                    }
+                },
+            }
+        }
+
+        self.lanes[priority.lane()].push(into_task(job));
+        self.notify_sleeper();
+        Ok(pushed)
+    }
+
+    // 容量に空きがあれば1つ確保する
+    fn reserve(&self) -> bool {
+        let reserved = match self.capacity {
+            Some(capacity) => self.len.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+                if len < capacity {
+                    Some(len + 1)
+                } else {
+                    None
+                }
+            }),
+            None => Ok(self.len.fetch_add(1, Ordering::SeqCst)),
+        };
+        match reserved {
+            Ok(len) => {
+                self.peak.fetch_max(len + 1, Ordering::SeqCst);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    // 容量に空きができるまで待って、1つ確保する
+    fn wait_for_space(&self) {
+        let mut guard = lock(&self.space_lock);
+        self.blocked.fetch_add(1, Ordering::SeqCst);
+        while !self.reserve() {
+            guard = self.space.wait(guard).unwrap_or_else(PoisonError::into_inner);
+        }
+        self.blocked.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // ジョブを取り出したので、空きを待っているスレッドがいれば起こす
+    fn release(&self, jobs: usize) {
+        self.len.fetch_sub(jobs, Ordering::SeqCst);
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            let _guard = lock(&self.space_lock);
+            self.space.notify_all();
+        }
+    }
+
+    // 眠っているWorkerがいれば1つ起こす
+    // Worker側は、眠る前に数を増やしてからジョブを探し直すので、取りこぼさない
+    // 起こしたWorkerがまだ起きていなければ、そのWorkerが起きてからジョブを探すので、起こさなくてよい
+    pub(crate) fn notify_sleeper(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 && !self.notified.swap(true, Ordering::SeqCst) {
+            // 眠ろうとしているWorkerが実際に待ち始めるまで待ってから起こす
+            // 起こされたWorkerがすぐにロックを取れるように、ロックを手放してから起こす
+            drop(lock(&self.sleep));
+            self.available.notify_one();
+        }
+    }
+
+    // 最も優先度の高いジョブを、待たずに取り出す
+    pub(crate) fn try_pop(&self) -> Option<Task> {
+        let job = self.lanes.iter().find_map(steal);
+        if job.is_some() {
+            self.release(1);
+        }
+        job
+    }
+
+    // findでジョブを探し、見つからなければ、ジョブか終了の指示が来るまで眠る
+    // findはこのキューのジョブに加えて、Workerのローカルキューや他のWorkerのローカルキューも探す
+    // 何も来ないままtimeoutが経過したらNoneを返す
+    pub(crate) fn pop<F>(&self, timeout: Duration, mut find: F) -> Option<Message>
+        where
+            F: FnMut() -> Option<Task>
+    {
+        if let Some(job) = find() {
+            return Some(Message::NewJob(job));
+        }
+
+        let deadline = Instant::now().checked_add(timeout);
+        let mut sleep = lock(&self.sleep);
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+
+        let message = loop {
+            // ジョブを、終了の指示より先に片付ける
+            if let Some(job) = find() {
+                break Some(Message::NewJob(job));
+            }
+            if sleep.terminate > 0 {
+                sleep.terminate -= 1;
+                break Some(Message::Terminate);
+            }
+            sleep = match deadline {
+                // timeoutが大きすぎて期限を表せなければ、無期限に待つ
+                None => self.available.wait(sleep).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break None;
+                    }
+                    self.available
+                        .wait_timeout(sleep, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                },
+            };
+            // 起きたので、次にジョブが入ったら別のWorkerを起こせるようにする
+            self.notified.store(false, Ordering::SeqCst);
+            atomic::fence(Ordering::SeqCst);
+        };
+
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        drop(sleep);
+
+        // 眠っていた間にジョブが溜まっていれば、次のWorkerを起こして手伝わせる
+        if let Some(Message::NewJob(_)) = message {
+            if self.len() > 0 {
+                self.notify_sleeper();
+            }
+        }
+        message
+    }
+
+    // workers個のWorkerに終了を指示する。Workerは溜まっているジョブをすべて実行してから終了する
+    pub(crate) fn terminate(&self, workers: usize) {
+        lock(&self.sleep).terminate += workers;
+        self.available.notify_all();
+    }
+
+    // 溜まっているジョブをすべて取り出して捨て、その数を返す
+    pub(crate) fn clear(&self) -> usize {
+        let cleared = iter::from_fn(|| self.lanes.iter().find_map(steal)).count();
+        self.release(cleared);
+        cleared
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    // 優先度ごとの溜まっているジョブの数と、これまでの最大値
+    pub(crate) fn depths(&self) -> ([usize; 3], usize) {
+        let mut depths = [0; 3];
+        for (depth, lane) in depths.iter_mut().zip(&self.lanes) {
+            *depth = lane.len();
+        }
+        (depths, self.peak.load(Ordering::SeqCst))
+    }
+}
+
+// レーンから先に入ったジョブを1つ取り出す。他のスレッドと取り合って失敗したら、もう一度試す
+fn steal(lane: &Injector<Task>) -> Option<Task> {
+    iter::repeat_with(|| lane.steal())
+        .find(|steal| !steal.is_retry())
+        .and_then(Steal::success)
+}
@@ -0,0 +1,239 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+use super::{lock, steal, Priority, Shared, ThreadPool};
+
+// Workerがスコープの終わりを待つ間に、他のジョブを探し直す間隔
+const HELP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// `ThreadPool::scope`の中でジョブを投入するためのスコープ。
+///
+/// スコープに投入したジョブは、`'scope`より長く生きる値を借用できます。
+pub struct Scope<'scope> {
+    shared: Arc<Shared>,
+    state: Arc<ScopeState>,
+    // 'scopeを不変にして、もっと短い借用を持つジョブを投入できないようにする
+    marker: PhantomData<fn(&'scope ()) -> &'scope ()>,
+}
+
+// 1つのスコープのジョブで共有する状態
+struct ScopeState {
+    // 投入されたが、まだ終わっていないジョブの数
+    pending: Mutex<usize>,
+    // pendingが0になったことを、スコープの終わりで待っているスレッドに知らせる
+    done: Condvar,
+    // 最初にパニックしたジョブのペイロード。スコープの終わりでパニックし直す
+    panic: Mutex<Option<Box<dyn Any + Send + 'static>>>,
+}
+
+impl ScopeState {
+    fn record_panic(&self, payload: Box<dyn Any + Send + 'static>) {
+        lock(&self.panic).get_or_insert(payload);
+    }
+
+    fn finish(&self) {
+        let mut pending = lock(&self.pending);
+        *pending -= 1;
+        if *pending == 0 {
+            self.done.notify_all();
+        }
+    }
+}
+
+impl<'scope> Scope<'scope> {
+    fn new(shared: Arc<Shared>, state: Arc<ScopeState>) -> Scope<'scope> {
+        Scope {
+            shared,
+            state,
+            marker: PhantomData,
+        }
+    }
+
+    /// スコープの中でジョブを投入する。
+    ///
+    /// ジョブには同じスコープが渡されるので、ジョブの中からさらにジョブを投入できます。
+    /// キューが満杯で`OverflowPolicy::Reject`に拒否されたジョブは、投入したスレッドでその場で実行します。
+    pub fn execute<F>(&self, f: F)
+        where
+            F: FnOnce(&Scope<'scope>) + Send + 'scope
+    {
+        *lock(&self.state.pending) += 1;
+
+        let job = ScopedJob {
+            f: Some(f),
+            scope: Scope::new(Arc::clone(&self.shared), Arc::clone(&self.state)),
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || job.run());
+        // スコープはすべてのジョブが終わるまで戻らないので、ジョブが借用している値はジョブより長く生きる
+        // ジョブが実行されずに捨てられても、ScopedJobのDropで終わったものとして数える
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(job) };
+
+        if let Err(rejected) = self.shared.submit(Priority::Normal, job) {
+            rejected.into_inner()();
+        }
+    }
+
+    // 投入したジョブがすべて終わるまで待つ
+    fn wait(&self) {
+        let id = match steal::current_worker(self.shared.pool_id()) {
+            Some(id) => id,
+            None => {
+                let pending = lock(&self.state.pending);
+                let _guard = self.state.done
+                    .wait_while(pending, |pending| *pending > 0)
+                    .unwrap_or_else(PoisonError::into_inner);
+                return;
+            },
+        };
+
+        // ジョブの中から呼ばれたときは、このWorkerのローカルキューにスコープのジョブが入っている
+        // 待つだけだと誰も実行しないことがあるので、待つ間もプールのジョブを実行する
+        loop {
+            if *lock(&self.state.pending) == 0 {
+                return;
+            }
+            match self.shared.find_job(id) {
+                // パニックはジョブの中で捕まえたので、このWorkerはそのまま待ち続ける
+                Some(job) => {
+                    self.shared.run_job(id, job);
+                },
+                None => {
+                    let pending = lock(&self.state.pending);
+                    if *pending > 0 {
+                        let _ = self.state.done.wait_timeout(pending, HELP_INTERVAL);
+                    }
+                },
+            }
+        }
+    }
+}
+
+// スコープに投入したジョブ。実行されたか捨てられたら、スコープのジョブが1つ終わったものとして数える
+struct ScopedJob<'scope, F> {
+    f: Option<F>,
+    scope: Scope<'scope>,
+}
+
+impl<'scope, F> ScopedJob<'scope, F>
+    where
+        F: FnOnce(&Scope<'scope>)
+{
+    fn run(mut self) {
+        if let Some(f) = self.f.take() {
+            let scope = &self.scope;
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(scope))) {
+                self.scope.state.record_panic(payload);
+            }
+        }
+    }
+}
+
+impl<'scope, F> Drop for ScopedJob<'scope, F> {
+    fn drop(&mut self) {
+        // 借用を持つクロージャは、スコープが戻る前に捨てる
+        let lost = self.f.take().is_some();
+        if lost {
+            // キューが満杯でOverflowPolicy::DropOldestに捨てられたか、実行前にキャンセルされた
+            self.scope.state.record_panic(Box::new("scoped job was dropped before it ran"));
+        }
+        self.scope.state.finish();
+    }
+}
+
+impl ThreadPool {
+    /// ローカル変数を借用できるジョブを投入するためのスコープを作る。
+    ///
+    /// fの中で`Scope::execute`に投入したジョブがすべて終わるまで、`scope`は戻りません。
+    /// ジョブがパニックしたら、すべてのジョブが終わってから、そのパニックを呼び出し元で起こし直します。
+    ///
+    /// ```
+    /// use multi_thread_server::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let mut counts = vec![0; 4];
+    /// let lines = ["a b", "c", "d e f", ""];
+    /// pool.scope(|s| {
+    ///     for (count, line) in counts.iter_mut().zip(&lines) {
+    ///         s.execute(move |_| *count = line.split_whitespace().count());
+    ///     }
+    /// });
+    /// assert_eq!(counts, vec![2, 1, 3, 0]);
+    /// ```
+    pub fn scope<'scope, F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&Scope<'scope>) -> R
+    {
+        let scope = Scope::new(
+            Arc::clone(&self.shared),
+            Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+                panic: Mutex::new(None),
+            }),
+        );
+
+        // fがパニックしても、投入済みのジョブが借用している値を捨てる前に、ジョブの終わりを待つ
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+
+        let result = match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        if let Some(payload) = lock(&scope.state.panic).take() {
+            panic::resume_unwind(payload);
+        }
+        result
+    }
+
+    /// スライスの要素を分けてプールで並列に処理し、すべて終わるまで待つ。
+    pub fn par_for_each<T, F>(&self, items: &[T], f: F)
+        where
+            T: Sync,
+            F: Fn(&T) + Sync
+    {
+        let f = &f;
+        self.scope(|s| {
+            for chunk in items.chunks(self.chunk_len(items.len())) {
+                s.execute(move |_| chunk.iter().for_each(f));
+            }
+        });
+    }
+
+    /// スライスの要素をプールで並列に変換し、元の順序で結果を返す。
+    pub fn par_map<T, U, F>(&self, items: &[T], f: F) -> Vec<U>
+        where
+            T: Sync,
+            U: Send,
+            F: Fn(&T) -> U + Sync
+    {
+        let mut results: Vec<Option<U>> = Vec::with_capacity(items.len());
+        results.resize_with(items.len(), || None);
+
+        let f = &f;
+        let chunk_len = self.chunk_len(items.len());
+        self.scope(|s| {
+            for (chunk, slots) in items.chunks(chunk_len).zip(results.chunks_mut(chunk_len)) {
+                s.execute(move |_| {
+                    for (item, slot) in chunk.iter().zip(slots) {
+                        *slot = Some(f(item));
+                    }
+                });
+            }
+        });
+
+        // scopeがパニックせずに戻ったなら、すべてのジョブが結果を書き込んでいる
+        results.into_iter().map(|result| result.expect("scoped job did not run")).collect()
+    }
+
+    // 1つのジョブで処理する要素の数
+    // Workerの数より多めに分けて、先に終わったWorkerが残りを手伝えるようにする
+    fn chunk_len(&self, len: usize) -> usize {
+        let chunks = self.shared.config.max_workers * 4;
+        len.div_ceil(chunks).max(1)
+    }
+}
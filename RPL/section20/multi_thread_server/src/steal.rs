@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::iter;
+use std::sync::{PoisonError, RwLock};
+
+use crossbeam_deque::{Steal, Stealer, Worker as Deque};
+
+use super::job::Task;
+
+// Workerスレッドが持つローカルキュー
+// ジョブの中から投入したジョブはここに入り、そのWorkerが後入れ先出しで取り出す
+// 他のWorkerは手が空いたら、先に入ったものから盗んで実行する
+struct LocalQueue {
+    // どのプールのWorkerか。Sharedのアドレスで区別する
+    pool: usize,
+    // このスレッドのWorkerのID
+    id: usize,
+    deque: Deque<Task>,
+}
+
+thread_local! {
+    static LOCAL: RefCell<Option<LocalQueue>> = const { RefCell::new(None) };
+}
+
+// 新しいローカルキューを作る
+pub(crate) fn new_deque() -> Deque<Task> {
+    Deque::new_lifo()
+}
+
+// 今のスレッドを、poolのWorkerとしてローカルキューを持つスレッドにする
+pub(crate) fn install(pool: usize, id: usize, deque: Deque<Task>) {
+    LOCAL.with(|local| *local.borrow_mut() = Some(LocalQueue { pool, id, deque }));
+}
+
+// 今のスレッドからローカルキューを取り外す。Workerを作り直すときは、新しいスレッドに引き継ぐ
+pub(crate) fn uninstall() -> Option<Deque<Task>> {
+    LOCAL.with(|local| local.borrow_mut().take().map(|local| local.deque))
+}
+
+// 今のスレッドがpoolのWorkerなら、jobをinto_taskでTaskにしてそのローカルキューに入れる
+// そうでなければジョブをそのまま返す
+pub(crate) fn push<J, C>(pool: usize, job: J, into_task: C) -> Result<(), J>
+    where
+        C: FnOnce(J) -> Task
+{
+    LOCAL.with(|local| match &*local.borrow() {
+        Some(local) if local.pool == pool => {
+            local.deque.push(into_task(job));
+            Ok(())
+        },
+        _ => Err(job),
+    })
+}
+
+// 今のスレッドがpoolのWorkerか
+pub(crate) fn is_worker_of(pool: usize) -> bool {
+    LOCAL.with(|local| matches!(&*local.borrow(), Some(local) if local.pool == pool))
+}
+
+// 今のスレッドがpoolのWorkerなら、そのWorkerのID
+pub(crate) fn current_worker(pool: usize) -> Option<usize> {
+    LOCAL.with(|local| match &*local.borrow() {
+        Some(local) if local.pool == pool => Some(local.id),
+        _ => None,
+    })
+}
+
+// 今のスレッドのローカルキューからジョブを取り出す
+pub(crate) fn pop() -> Option<Task> {
+    LOCAL.with(|local| local.borrow().as_ref().and_then(|local| local.deque.pop()))
+}
+
+// 他のWorkerのローカルキューから盗むためのStealerの一覧
+pub(crate) struct Stealers {
+    // WorkerのIDとStealerの組。Workerが生成・終了したときだけ書き換える
+    entries: RwLock<Vec<(usize, Stealer<Task>)>>,
+}
+
+impl Stealers {
+    pub(crate) fn new() -> Stealers {
+        Stealers {
+            entries: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn register(&self, id: usize, stealer: Stealer<Task>) {
+        self.entries.write().unwrap_or_else(PoisonError::into_inner).push((id, stealer));
+    }
+
+    pub(crate) fn unregister(&self, id: usize) {
+        self.entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(entry_id, _)| *entry_id != id);
+    }
+
+    // idのWorker以外のローカルキューから1つ盗む
+    // 同じWorkerばかりが狙われないように、一覧の中でidの次のWorkerから順に試す
+    pub(crate) fn steal(&self, id: usize) -> Option<Task> {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        let start = entries.iter().position(|(entry_id, _)| *entry_id == id).map_or(0, |i| i + 1);
+        let (after, before) = entries.split_at(start.min(entries.len()));
+        let victims = after.iter().chain(before).filter(|(entry_id, _)| *entry_id != id);
+
+        // 他のスレッドと取り合って失敗したら（Steal::Retry）、もう一度試す
+        iter::repeat_with(|| {
+            victims
+                .clone()
+                .map(|(_, stealer)| stealer.steal())
+                .collect::<Steal<Task>>()
+        })
+        .find(|steal| !steal.is_retry())
+        .and_then(Steal::success)
+    }
+
+    // すべてのローカルキューに溜まっているジョブの数
+    pub(crate) fn len(&self) -> usize {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        entries.iter().map(|(_, stealer)| stealer.len()).sum()
+    }
+
+    // すべてのローカルキューからジョブを取り出して捨て、その数を返す
+    pub(crate) fn clear(&self) -> usize {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        let mut cleared = 0;
+        for (_, stealer) in entries.iter() {
+            loop {
+                match stealer.steal() {
+                    Steal::Success(job) => {
+                        drop(job);
+                        cleared += 1;
+                    },
+                    Steal::Retry => continue,
+                    Steal::Empty => break,
+                }
+            }
+        }
+        cleared
+    }
+}
@@ -1,9 +1,11 @@
-use num_cpus;
 use bitonic_sorter::SortOrder;
 // 第3段階のsort関数をseq_sortという別名で使用する
 use bitonic_sorter::third::sort as seq_sort;
 // 第4段階のsort関数をpar_sortという別名で使用する
 use bitonic_sorter::fourth::sort as par_sort;
+use bitonic_sorter::fourth::{sort_with_config, SortConfig};
+use bitonic_sorter::counter::Counters;
+use bitonic_sorter::sorter::{find_u32_sorter, u32_sorters, Sorter};
 use bitonic_sorter::utils::{is_sorted_ascending, new_u32_vec};
 use std::{env, f64};
 use std::str::FromStr;
@@ -17,17 +19,39 @@ fn main() {
         let bits = u32::from_str(&n).expect("error parsing argument");
         // 順次ソートと並列ソートを実行する
         run_sorts(bits);
+
+        // 2つめ以降の引数でオプションの計測を指定する
+        let mut args = env::args().skip(2);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // 並列ソートの設定を変えながら計測する
+                "--sweep" => run_sweep(bits),
+                // 名前で指定したバックエンド（カンマ区切り、またはall）を計測する
+                "--backends" => {
+                    let names = args.next().unwrap_or_else(|| exit_with_usage());
+                    run_backends(bits, &names);
+                }
+                _ => exit_with_usage(),
+            }
+        }
     } else {
-        // コマンドライン引数が指定されていなかったらヘルプメッセージを表示し
-        // ステータスコード1で終了する
-        eprintln!(
-            "Usage {} <number of elements in bits>",
-            env::args().nth(0).unwrap()
-        );
-        std::process::exit(1);
+        exit_with_usage();
     }
 }
 
+fn exit_with_usage() -> ! {
+    // コマンドライン引数が正しく指定されていなかったらヘルプメッセージを表示し
+    // ステータスコード1で終了する
+    let names: Vec<&str> = u32_sorters().iter().map(|sorter| sorter.name()).collect();
+    eprintln!(
+        "Usage {} <number of elements in bits> [--sweep] [--backends <all|name,...>]\n\
+         backends: {}",
+        env::args().next().unwrap(),
+        names.join(", ")
+    );
+    std::process::exit(1);
+}
+
 fn run_sorts(bits: u32) {
     // 指定されたビット数からデータの要素数を求める
     // 例：
@@ -59,21 +83,88 @@ fn run_sorts(bits: u32) {
     println!("speed up: {:.2}x", seq_duration / par_duration);
 }
 
+// 並列処理の閾値とスレッド数の組み合わせごとに並列ソートの時間を計測し、表にして表示する
+fn run_sweep(bits: u32) {
+    let len = 2.0_f64.powi(bits as i32) as usize;
+    let thresholds = [256, 1024, 4096, 16384, 65536];
+
+    // スレッド数は1から論理コア数まで2倍ずつ増やし、最後に論理コア数そのものを加える
+    let max_threads = num_cpus::get();
+    let mut thread_counts: Vec<usize> = (0..)
+        .map(|i| 1 << i)
+        .take_while(|&n| n < max_threads)
+        .collect();
+    thread_counts.push(max_threads);
+
+    println!();
+    println!("par_sort sweep: seconds to sort {} integers", len);
+    print!("{:>10}", "threshold");
+    for threads in &thread_counts {
+        print!(" {:>10}", format!("{} thr", threads));
+    }
+    println!();
+
+    for &threshold in &thresholds {
+        print!("{:>10}", threshold);
+        for &threads in &thread_counts {
+            let config = SortConfig::new()
+                .threshold(threshold)
+                .num_threads(threads)
+                .expect("Failed to build a thread pool: ");
+            let sorter = |x: &mut [u32], order: &SortOrder| sort_with_config(x, order, &config);
+            let nano_secs = measure_sort(&sorter, len);
+            print!(" {:>10.4}", nano_secs / 1e9);
+        }
+        println!();
+    }
+}
+
+// バックエンドごとに、比較と交換の回数と実行時間を計測して表にする
+fn run_backends(bits: u32, names: &str) {
+    let len = 2.0_f64.powi(bits as i32) as usize;
+    let sorters: Vec<Box<dyn Sorter<u32>>> = if names == "all" {
+        u32_sorters()
+    } else {
+        names
+            .split(',')
+            .map(|name| {
+                find_u32_sorter(name).unwrap_or_else(|| {
+                    eprintln!("unknown backend: {}", name);
+                    exit_with_usage()
+                })
+            })
+            .collect()
+    };
+
+    println!();
+    println!("backends: sorting {} integers", len);
+    println!("{:<16} {:>14} {:>14} {:>12}", "backend", "comparisons", "swaps", "seconds");
+    for sorter in &sorters {
+        // 実行時間は計測なしのsortで測り、回数はsort_countedで別に数える
+        let sort = |x: &mut [u32], order: &SortOrder| sorter.sort(x, order);
+        let nano_secs = measure_sort(&sort, len);
+
+        let counters = Counters::new();
+        let sort_counted = |x: &mut [u32], order: &SortOrder| sorter.sort_counted(x, order, &counters);
+        measure_sort(&sort_counted, len);
+
+        println!(
+            "{:<16} {:>14} {:>14} {:>12.4}",
+            sorter.name(),
+            counters.comparisons(),
+            counters.swaps(),
+            nano_secs / 1e9
+        );
+    }
+}
+
 fn timed_sort<F>(sorter: &F, len: usize, name: &str) -> f64
 where
     F: Fn(&mut [u32], &SortOrder) -> Result<(), String>,
 {
-    // 要素数lenのu32型ベクタを生成する
-    let mut x = new_u32_vec(len);
-
-    // sorter関数を呼び出すことで、ソートを実行する
-    // かかった時間（dur）を記録する
-    let start = Instant::now();
-    sorter(&mut x[..], &SortOrder::Ascending).expect("Failed to sort: ");
-    let dur = start.elapsed();
+    let nano_secs = measure_sort(sorter, len);
 
     // ソートした要素数とかかった時間（秒）を表示する
-    let nano_secs = dur.subsec_nanos() as f64 + dur.as_secs() as f64 * 1e9_f64;
     println!(
         "{}: sorted {} integers in {} seconds",
         name,
@@ -81,8 +172,25 @@ where
         nano_secs / 1e9
     );
 
+    nano_secs
+}
+
+// sorterで要素数lenのデータをソートし、かかった時間（ナノ秒）を返す
+fn measure_sort<F>(sorter: &F, len: usize) -> f64
+where
+    F: Fn(&mut [u32], &SortOrder) -> Result<(), String>,
+{
+    // 要素数lenのu32型ベクタを生成する
+    let mut x = new_u32_vec(len);
+
+    // sorter関数を呼び出すことで、ソートを実行する
+    // かかった時間（dur）を記録する
+    let start = Instant::now();
+    sorter(&mut x[..], &SortOrder::Ascending).expect("Failed to sort: ");
+    let dur = start.elapsed();
+
     // ソート結果が正しいか検証する
     assert!(is_sorted_ascending(&x[..]));
 
-    nano_secs
+    dur.subsec_nanos() as f64 + dur.as_secs() as f64 * 1e9_f64
 }
\ No newline at end of file
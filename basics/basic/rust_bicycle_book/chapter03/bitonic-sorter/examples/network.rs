@@ -0,0 +1,53 @@
+use bitonic_sorter::fourth::SortConfig;
+use bitonic_sorter::network::{fourth_network, odd_even_network, third_network, Network};
+use std::env;
+use std::str::FromStr;
+
+fn main() {
+    // 1つめのコマンドライン引数でソートする要素数を受け取る
+    let mut args = env::args().skip(1);
+    let len = match args.next().map(|n| usize::from_str(&n)) {
+        Some(Ok(len)) => len,
+        _ => exit_with_usage(),
+    };
+
+    // 2つめ以降の引数で出力形式とソートの種類を指定する
+    let mut svg = false;
+    let mut backend = String::from("third");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--svg" => svg = true,
+            "--backend" => backend = args.next().unwrap_or_else(|| exit_with_usage()),
+            _ => exit_with_usage(),
+        }
+    }
+
+    let network: Network = match backend.as_str() {
+        "third" => third_network(len),
+        // 並列に実行されるように、しきい値を小さくする
+        "fourth" => fourth_network(len, &SortConfig::new().threshold(2)),
+        "odd-even" => odd_even_network(len),
+        _ => exit_with_usage(),
+    };
+
+    if svg {
+        print!("{}", network.to_svg());
+    } else {
+        println!(
+            "{}: {} wires, {} comparators, depth {}",
+            backend,
+            network.wires(),
+            network.comparators().len(),
+            network.depth()
+        );
+        print!("{}", network.to_text());
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!(
+        "Usage {} <number of elements> [--svg] [--backend third|fourth|odd-even]",
+        env::args().next().unwrap()
+    );
+    std::process::exit(1);
+}
@@ -0,0 +1,96 @@
+use bitonic_sorter::external::{is_sorted_stream, sort_file, ExternalSortConfig, RecordFormat};
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+const USAGE: &str = "Usage:
+  extsort [--memory <size>] [--record-size <bytes>] [--temp-dir <dir>] <input> <output>
+  extsort --check [--record-size <bytes>] <file>
+
+Options:
+  --memory <size>        memory budget per chunk (e.g. 512K, 64M, 1G; default 64M)
+  --record-size <bytes>  sort fixed-size binary records instead of lines
+  --temp-dir <dir>       directory for temporary run files (default: system temp dir)
+  --check                verify that <file> is sorted in ascending order";
+
+fn main() {
+    let mut config = ExternalSortConfig::default();
+    let mut check = false;
+    let mut paths = Vec::new();
+
+    // コマンドライン引数を先頭から順に解釈する
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--memory" => config.memory_budget = parse_size(&expect_value(&mut args, &arg)),
+            "--record-size" => {
+                let size = parse_size(&expect_value(&mut args, &arg));
+                config.format = RecordFormat::Fixed(size);
+            }
+            "--temp-dir" => config.temp_dir = PathBuf::from(expect_value(&mut args, &arg)),
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => exit_with_usage(&format!("unknown option: {}", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    match (check, paths.as_slice()) {
+        (true, [file]) => {
+            // 出力ファイルを少しずつ読み出して、昇順に並んでいるか確認する
+            let reader = BufReader::new(File::open(file).unwrap_or_else(|e| exit_with_error(&e)));
+            if is_sorted_stream(reader, config.format, 4096).unwrap_or_else(|e| exit_with_error(&e)) {
+                println!("{}: sorted", file.display());
+            } else {
+                println!("{}: NOT sorted", file.display());
+                process::exit(1);
+            }
+        }
+        (false, [input, output]) => {
+            let stats = sort_file(input, output, &config).unwrap_or_else(|e| exit_with_error(&e));
+            eprintln!("sorted {} records using {} runs", stats.records, stats.runs);
+        }
+        _ => exit_with_usage("wrong number of arguments"),
+    }
+}
+
+fn expect_value(args: &mut impl Iterator<Item = String>, option: &str) -> String {
+    args.next()
+        .unwrap_or_else(|| exit_with_usage(&format!("{} requires a value", option)))
+}
+
+// 512K、64M、1Gのような単位付きのサイズをバイト数に変換する
+fn parse_size(s: &str) -> usize {
+    let (digits, unit) = match s.char_indices().find(|&(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        _ => exit_with_usage(&format!("invalid size: {}", s)),
+    };
+    // 単位を掛けてusizeに収まらなければ、大きすぎる値として断る
+    match usize::from_str(digits).ok().filter(|&n| n > 0).and_then(|n| n.checked_mul(multiplier)) {
+        Some(size) => size,
+        None => exit_with_usage(&format!("invalid size: {}", s)),
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn exit_with_error(error: &std::io::Error) -> ! {
+    eprintln!("error: {}", error);
+    process::exit(2);
+}
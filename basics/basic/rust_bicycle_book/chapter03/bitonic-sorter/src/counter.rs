@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// ソート中の比較と交換を数えるためのトレイト
+// 並列ソートのスレッド間で共有するので、Syncを要求する
+pub trait Counter: Sync {
+    // 要素をn回比較した
+    fn count_comparisons(&self, _n: u64) {}
+    // 要素をn回交換した（マージソートや基数ソートでは、要素を移動した回数）
+    fn count_swaps(&self, _n: u64) {}
+    // ソーティングネットワークで位置iとjの要素を比較交換した
+    // 比較交換のあと、ソート順でiの要素がjの要素の前に来る（i > jのこともある）
+    fn record_compare_exchange(&self, _i: usize, _j: usize) {}
+}
+
+// 何も数えないカウンタ。通常のソートではこれを使う
+// メソッドが空なので、最適化によって計測のコードは取り除かれる
+impl Counter for () {}
+
+// 比較と交換の回数を数えるカウンタ
+#[derive(Debug, Default)]
+pub struct Counters {
+    comparisons: AtomicU64,
+    swaps: AtomicU64,
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn comparisons(&self) -> u64 {
+        self.comparisons.load(Ordering::Relaxed)
+    }
+
+    pub fn swaps(&self) -> u64 {
+        self.swaps.load(Ordering::Relaxed)
+    }
+}
+
+impl Counter for Counters {
+    fn count_comparisons(&self, n: u64) {
+        // 回数を数えるだけなので、他のメモリ操作との順序は保証しなくてよい
+        self.comparisons.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn count_swaps(&self, n: u64) {
+        self.swaps.fetch_add(n, Ordering::Relaxed);
+    }
+}
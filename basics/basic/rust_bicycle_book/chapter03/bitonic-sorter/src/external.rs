@@ -0,0 +1,337 @@
+use super::fourth;
+use super::utils::is_sorted_ascending;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+// ファイルからレコードを読み出す単位
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    Lines,          // 改行（\n）で区切られた行。出力では各行の末尾に\nを付ける
+    Fixed(usize),   // 指定したバイト数の固定長レコード
+}
+
+// 外部ソートの設定
+#[derive(Debug, Clone)]
+pub struct ExternalSortConfig {
+    // 1つのチャンク（メモリ上でソートする単位）に読み込むレコードのおおよその上限（バイト）
+    pub memory_budget: usize,
+    pub format: RecordFormat,
+    // ソート済みのチャンク（ラン）を書き出す一時ファイルの置き場所
+    pub temp_dir: PathBuf,
+    // 一度にマージするランの数の上限。これを超えるときは、何回かに分けてマージする
+    pub merge_fan_in: usize,
+}
+
+impl Default for ExternalSortConfig {
+    fn default() -> Self {
+        Self {
+            memory_budget: 64 * 1024 * 1024,
+            format: RecordFormat::Lines,
+            temp_dir: std::env::temp_dir(),
+            merge_fan_in: 64,
+        }
+    }
+}
+
+// ソート結果の統計
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortStats {
+    pub records: u64,   // ソートしたレコード数
+    pub runs: usize,    // 一時ファイルに書き出したランの数（1チャンクに収まったときは0）
+}
+
+// 1レコードあたりの管理領域（Vec<u8>自体の大きさ）もメモリ使用量として数える
+const RECORD_OVERHEAD: usize = mem::size_of::<Vec<u8>>();
+
+// ファイルinputのレコードをバイト列として昇順にソートし、ファイルoutputに書き出す
+pub fn sort_file(input: &Path, output: &Path, config: &ExternalSortConfig) -> io::Result<SortStats> {
+    let reader = BufReader::new(File::open(input)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+    let stats = sort_stream(reader, &mut writer, config)?;
+    writer.flush()?;
+    Ok(stats)
+}
+
+// inputから読み出したレコードを昇順にソートし、outputに書き出す
+// メモリにはmemory_budget程度のレコードしか保持しない
+pub fn sort_stream<R, W>(mut input: R, output: &mut W, config: &ExternalSortConfig) -> io::Result<SortStats>
+    where R: BufRead, W: Write
+{
+    if let RecordFormat::Fixed(0) = config.format {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "record size must be greater than 0"));
+    }
+
+    let mut runs = Vec::new();
+    let mut records = 0;
+    loop {
+        let mut chunk = read_chunk(&mut input, config)?;
+        if chunk.is_empty() {
+            break;
+        }
+        records += chunk.len() as u64;
+        // チャンク内のレコードを並列バイトニックソートでソートする
+        fourth::sort_by(&mut chunk, &|a: &Vec<u8>, b: &Vec<u8>| a.cmp(b))
+            .map_err(io::Error::other)?;
+
+        // 最初のチャンクで入力を読み切ったなら、一時ファイルを使わずにそのまま出力する
+        if runs.is_empty() && input.fill_buf()?.is_empty() {
+            for record in &chunk {
+                write_record(output, record, config.format)?;
+            }
+            return Ok(SortStats { records, runs: 0 });
+        }
+        runs.push(spill_run(&chunk, config)?);
+    }
+
+    let run_count = runs.len();
+    merge_runs(runs, output, config)?;
+    Ok(SortStats { records, runs: run_count })
+}
+
+// ストリームのレコードが昇順に並んでいるか検証する
+// 一度にbatch_records個ずつ読み出し、utils::is_sorted_ascendingで確認する
+pub fn is_sorted_stream<R: BufRead>(mut input: R, format: RecordFormat, batch_records: usize) -> io::Result<bool> {
+    // バッチの境目も検証できるよう、前のバッチの最後のレコードを次のバッチの先頭に残しておく
+    let mut batch: Vec<Vec<u8>> = Vec::new();
+    loop {
+        let carried = batch.len();
+        while batch.len() < carried + batch_records.max(1) {
+            match read_record(&mut input, format)? {
+                Some(record) => batch.push(record),
+                None => break,
+            }
+        }
+        if !is_sorted_ascending(&batch) {
+            return Ok(false);
+        }
+        if batch.len() == carried {
+            return Ok(true);
+        }
+        let last = batch.pop();
+        batch.clear();
+        batch.extend(last);
+    }
+}
+
+// memory_budgetに達するまでレコードを読み出す
+fn read_chunk<R: BufRead>(input: &mut R, config: &ExternalSortConfig) -> io::Result<Vec<Vec<u8>>> {
+    let mut chunk = Vec::new();
+    let mut used = 0;
+    // 予算が極端に小さくても、少なくとも1レコードは読み出す
+    while chunk.is_empty() || used < config.memory_budget {
+        match read_record(input, config.format)? {
+            Some(record) => {
+                used += record.len() + RECORD_OVERHEAD;
+                chunk.push(record);
+            }
+            None => break,
+        }
+    }
+    Ok(chunk)
+}
+
+// 1レコードを読み出す。入力の終わりに達したらNoneを返す
+fn read_record<R: BufRead>(input: &mut R, format: RecordFormat) -> io::Result<Option<Vec<u8>>> {
+    let mut record = Vec::new();
+    match format {
+        RecordFormat::Lines => {
+            if input.read_until(b'\n', &mut record)? == 0 {
+                return Ok(None);
+            }
+            // 行末の\nはレコードに含めない（最終行に\nがなくてもよい）
+            if record.last() == Some(&b'\n') {
+                record.pop();
+            }
+        }
+        RecordFormat::Fixed(size) => {
+            let read = input.by_ref().take(size as u64).read_to_end(&mut record)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            if read < size {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("incomplete record: expected {} bytes, got {}", size, read),
+                ));
+            }
+        }
+    }
+    Ok(Some(record))
+}
+
+fn write_record<W: Write>(output: &mut W, record: &[u8], format: RecordFormat) -> io::Result<()> {
+    output.write_all(record)?;
+    if format == RecordFormat::Lines {
+        output.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+// ソート済みのチャンクを一時ファイルに書き出す
+// tempfile_inで作ったファイルは、閉じると自動的に削除される
+fn spill_run(chunk: &[Vec<u8>], config: &ExternalSortConfig) -> io::Result<File> {
+    let mut writer = BufWriter::new(tempfile::tempfile_in(&config.temp_dir)?);
+    for record in chunk {
+        write_record(&mut writer, record, config.format)?;
+    }
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+// ランをoutputへマージする。ランの数がmerge_fan_inを超える間は、
+// merge_fan_in個ずつ中間のランにマージして数を減らす
+fn merge_runs<W: Write>(mut runs: Vec<File>, output: &mut W, config: &ExternalSortConfig) -> io::Result<()> {
+    let fan_in = config.merge_fan_in.max(2);
+    while runs.len() > fan_in {
+        let mut merged = Vec::new();
+        let mut rest = runs.into_iter();
+        loop {
+            let group: Vec<File> = rest.by_ref().take(fan_in).collect();
+            if group.is_empty() {
+                break;
+            }
+            let mut writer = BufWriter::new(tempfile::tempfile_in(&config.temp_dir)?);
+            merge(group, &mut writer, config.format)?;
+            let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.seek(SeekFrom::Start(0))?;
+            merged.push(file);
+        }
+        runs = merged;
+    }
+    merge(runs, output, config.format)
+}
+
+// k個のソート済みランを、二分ヒープを使って1つのソート済みの列にマージする（k-wayマージ）
+fn merge<W: Write>(runs: Vec<File>, output: &mut W, format: RecordFormat) -> io::Result<()> {
+    let mut readers: Vec<BufReader<File>> = runs.into_iter().map(BufReader::new).collect();
+
+    // BinaryHeapは最大値を取り出すので、Reverseで包んで最小のレコードから取り出す
+    // 同じレコードはランの番号順に取り出される
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some(record) = read_record(reader, format)? {
+            heap.push(Reverse((record, i)));
+        }
+    }
+
+    while let Some(Reverse((record, i))) = heap.pop() {
+        write_record(output, &record, format)?;
+        // 取り出したレコードのランから、次のレコードを補充する
+        if let Some(next) = read_record(&mut readers[i], format)? {
+            heap.push(Reverse((next, i)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::new_u32_vec;
+    use std::io::Cursor;
+
+    // 整数を文字列にして1行ずつ並べた入力を作る
+    fn numbers_as_lines(n: usize) -> Vec<u8> {
+        new_u32_vec(n)
+            .iter()
+            .map(|x| format!("{}\n", x % 100_000))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    fn config(memory_budget: usize, format: RecordFormat) -> ExternalSortConfig {
+        ExternalSortConfig {
+            memory_budget,
+            format,
+            merge_fan_in: 4,
+            ..ExternalSortConfig::default()
+        }
+    }
+
+    #[test]
+    fn sort_lines_in_memory() {
+        let input = b"pear\napple\nfig\nbanana".to_vec();
+        let mut output = Vec::new();
+        let stats = sort_stream(Cursor::new(input), &mut output, &config(1024, RecordFormat::Lines)).unwrap();
+        assert_eq!(stats, SortStats { records: 4, runs: 0 });
+        assert_eq!(output, b"apple\nbanana\nfig\npear\n".to_vec());
+    }
+
+    #[test]
+    fn sort_lines_with_runs() {
+        let input = numbers_as_lines(10_000);
+        let mut expected: Vec<&[u8]> = input.split(|&b| b == b'\n').filter(|l| !l.is_empty()).collect();
+        expected.sort();
+
+        // 予算を小さくして、多数のランと複数回のマージを発生させる
+        let mut output = Vec::new();
+        let stats = sort_stream(Cursor::new(&input), &mut output, &config(4096, RecordFormat::Lines)).unwrap();
+        assert_eq!(stats.records, 10_000);
+        assert!(stats.runs > 4, "runs: {}", stats.runs);
+
+        let actual: Vec<&[u8]> = output.split(|&b| b == b'\n').filter(|l| !l.is_empty()).collect();
+        assert_eq!(actual, expected);
+        assert!(is_sorted_stream(Cursor::new(&output), RecordFormat::Lines, 100).unwrap());
+    }
+
+    #[test]
+    fn sort_fixed_size_records() {
+        // u32をビッグエンディアンの4バイトで表すと、バイト列の順序と数値の順序が一致する
+        let numbers = new_u32_vec(5_000);
+        let input: Vec<u8> = numbers.iter().flat_map(|n| n.to_be_bytes().to_vec()).collect();
+
+        let mut output = Vec::new();
+        let stats = sort_stream(Cursor::new(input), &mut output, &config(1024, RecordFormat::Fixed(4))).unwrap();
+        assert_eq!(stats.records, 5_000);
+
+        let actual: Vec<u32> = output
+            .chunks(4)
+            .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        let mut expected = numbers;
+        expected.sort();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn sort_incomplete_fixed_size_record() {
+        let input = vec![0u8; 10];
+        let mut output = Vec::new();
+        let result = sort_stream(Cursor::new(input), &mut output, &config(1024, RecordFormat::Fixed(4)));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn sort_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.txt");
+        let output = dir.path().join("output.txt");
+        std::fs::write(&input, numbers_as_lines(3_000)).unwrap();
+
+        let config = ExternalSortConfig {
+            temp_dir: dir.path().to_path_buf(),
+            ..config(2048, RecordFormat::Lines)
+        };
+        let stats = sort_file(&input, &output, &config).unwrap();
+        assert_eq!(stats.records, 3_000);
+
+        let reader = BufReader::new(File::open(&output).unwrap());
+        assert!(is_sorted_stream(reader, RecordFormat::Lines, 64).unwrap());
+        // 一時ファイルは残らない
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn detect_unsorted_stream() {
+        // バッチの境目（2件目と3件目の間）で順序が崩れている
+        let input = b"a\nb\na\nc\n".to_vec();
+        assert!(!is_sorted_stream(Cursor::new(&input), RecordFormat::Lines, 2).unwrap());
+        assert!(is_sorted_stream(Cursor::new(b"a\nb\nb\nc\n"), RecordFormat::Lines, 2).unwrap());
+        assert!(is_sorted_stream(Cursor::new(b""), RecordFormat::Lines, 2).unwrap());
+    }
+}
@@ -1,27 +1,36 @@
+use super::counter::Counter;
+
 pub fn sort(x: &mut [u32], up: bool) {
+    sort_counted(x, up, &());
+}
+
+// sortと同じだが、比較と交換の回数をcounterで数える
+pub fn sort_counted<C: Counter>(x: &mut [u32], up: bool, counter: &C) {
     if x.len() > 1 {
         let mid_point = x.len() / 2;
-        sort(&mut x[..mid_point], true);
-        sort(&mut x[mid_point..], false);
-        sub_sort(x, up);
+        sort_counted(&mut x[..mid_point], true, counter);
+        sort_counted(&mut x[mid_point..], false, counter);
+        sub_sort(x, up, counter);
     }
 }
 
-fn sub_sort(x: &mut [u32], up: bool) {
+fn sub_sort<C: Counter>(x: &mut [u32], up: bool, counter: &C) {
     if x.len() > 1 {
-        compare_and_swap(x, up);
+        compare_and_swap(x, up, counter);
         let mid_point = x.len() / 2;
-        sub_sort(&mut x[..mid_point], up);
-        sub_sort(&mut x[mid_point..], up);
+        sub_sort(&mut x[..mid_point], up, counter);
+        sub_sort(&mut x[mid_point..], up, counter);
     }
 }
 
-fn compare_and_swap(x: &mut [u32], up: bool) {
+fn compare_and_swap<C: Counter>(x: &mut [u32], up: bool, counter: &C) {
     let mid_point = x.len() / 2;
+    counter.count_comparisons(mid_point as u64);
     for i in 0..mid_point {
         if (x[i] > x[mid_point + i]) == up {
             // 要素を交換する
             x.swap(i, mid_point + i);
+            counter.count_swaps(1);
         }
     }
 }
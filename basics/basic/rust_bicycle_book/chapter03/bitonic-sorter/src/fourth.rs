@@ -1,70 +1,157 @@
 use super::SortOrder;
-use rayon;
+use super::counter::Counter;
+use rayon::{self, ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
 use std::cmp::Ordering;
+use std::sync::Arc;
 
-// 並列処理をするか決定する閾値
-const PARALLEL_THRESHOLD: usize = 4096;
+// 並列処理をするか決定する閾値（SortConfigのデフォルト値）
+pub const PARALLEL_THRESHOLD: usize = 4096;
 
-pub fn sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
-    // do_sortを呼ぶ代わりに、sort_by を呼ぶようにする
-    // is_power_of_twoはsort_byが呼ぶので、sort関数から削除
-        match *order {
-            SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
-            SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+// 並列処理をどのスレッドで行うか
+#[derive(Debug, Clone)]
+pub enum Parallelism {
+    Global,                  // rayonのグローバルスレッドプール
+    Pool(Arc<ThreadPool>),   // 専用のスレッドプール
+    Sequential,              // 並列処理をせず、呼び出し元のスレッドだけでソートする
+}
+
+// 並列ソートの設定。sort_with_config、sort_by_with_configに渡す
+#[derive(Debug, Clone)]
+pub struct SortConfig {
+    // 分割後の要素数がこの値以上なら並列処理する
+    pub parallel_threshold: usize,
+    pub parallelism: Parallelism,
+}
+
+impl Default for SortConfig {
+    fn default() -> Self {
+        // sort、sort_byと同じ設定
+        Self {
+            parallel_threshold: PARALLEL_THRESHOLD,
+            parallelism: Parallelism::Global,
         }
+    }
+}
+
+impl SortConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threshold(mut self, parallel_threshold: usize) -> Self {
+        self.parallel_threshold = parallel_threshold;
+        self
+    }
+
+    pub fn thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.parallelism = Parallelism::Pool(pool);
+        self
+    }
+
+    // 指定したスレッド数の専用スレッドプールを作って使う
+    // スレッドの生成に失敗した場合はエラーを返す
+    pub fn num_threads(self, num_threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new().num_threads(num_threads).build()?;
+        Ok(self.thread_pool(Arc::new(pool)))
+    }
+
+    pub fn sequential(mut self) -> Self {
+        self.parallelism = Parallelism::Sequential;
+        self
+    }
+}
+
+pub fn sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+    sort_with_config(x, order, &SortConfig::default())
 }
 
+// 要素数が2のべき乗でなくてもソートできるので、Errを返すことはない
 pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
     where T: Send, F:Sync + Fn(&T, &T) -> Ordering
 {
-    if x.len().is_power_of_two() {
-        do_sort(x, true, comparator);
-        Ok(())
-    } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
+    sort_by_with_config(x, comparator, &SortConfig::default())
+}
+
+pub fn sort_with_config<T: Ord + Send>(x: &mut [T], order: &SortOrder, config: &SortConfig)
+    -> Result<(), String>
+{
+    // do_sortを呼ぶ代わりに、sort_by_with_config を呼ぶようにする
+    match *order {
+        SortOrder::Ascending => sort_by_with_config(x, &|a, b| a.cmp(b), config),
+        SortOrder::Descending => sort_by_with_config(x, &|a, b| b.cmp(a), config),
     }
 }
 
-fn do_sort<T, F>(x: &mut [T], forward: bool, comparator: &F) 
+pub fn sort_by_with_config<T, F>(x: &mut [T], comparator: &F, config: &SortConfig)
+    -> Result<(), String>
     where T: Send, F:Sync + Fn(&T, &T) -> Ordering
+{
+    sort_by_counted(x, comparator, config, &())
+}
+
+// sort_by_with_configと同じだが、比較と交換の回数をcounterで数える
+pub fn sort_by_counted<T, F, C>(x: &mut [T], comparator: &F, config: &SortConfig, counter: &C)
+    -> Result<(), String>
+    where T: Send, F:Sync + Fn(&T, &T) -> Ordering, C: Counter
+{
+    match config.parallelism {
+        Parallelism::Global => do_sort(x, 0, true, comparator, config.parallel_threshold, counter),
+        // installの中で呼んだrayon::joinは、そのスレッドプールで実行される
+        Parallelism::Pool(ref pool) => {
+            pool.install(|| do_sort(x, 0, true, comparator, config.parallel_threshold, counter))
+        }
+        // 閾値を最大値にすれば、並列処理に切り替わることはない
+        Parallelism::Sequential => do_sort(x, 0, true, comparator, usize::MAX, counter),
+    }
+    Ok(())
+}
+
+// offsetはxの先頭がソート対象全体のどの位置にあたるかを表す。比較交換の記録に使う
+fn do_sort<T, F, C>(x: &mut [T], offset: usize, forward: bool, comparator: &F, threshold: usize, counter: &C)
+    where T: Send, F:Sync + Fn(&T, &T) -> Ordering, C: Counter
 {
     if x.len() > 1 {
+        // 要素数が奇数のときは後半の方が1つ多くなる
         let mid_point = x.len() / 2;
         // xをmid_pointを境にした2つの可変の借用に分割し、firstとsecondに束縛する
         let (first, second) = x.split_at_mut(mid_point);
-        // xの分割後の要素数を閾値(PARALLEL_THRESHOLD)と比較する
-        if mid_point >= PARALLEL_THRESHOLD {
+        // 前半はforwardと逆の順序、後半はforwardの順序でソートして、バイトニック列を作る
+        // xの分割後の要素数を閾値(threshold)と比較する
+        if mid_point >= threshold {
             // 閾値以上なら並列処理する
-            rayon::join(|| do_sort(first, true, comparator),
-                        || do_sort(second, false, comparator));
+            rayon::join(|| do_sort(first, offset, !forward, comparator, threshold, counter),
+                        || do_sort(second, offset + mid_point, forward, comparator, threshold, counter));
         } else {
             // 閾値未満なら順次処理をする
-            do_sort(first, true, comparator);
-            do_sort(second, false, comparator);
+            do_sort(first, offset, !forward, comparator, threshold, counter);
+            do_sort(second, offset + mid_point, forward, comparator, threshold, counter);
         }
-        sub_sort(x, forward, comparator);
+        sub_sort(x, offset, forward, comparator, threshold, counter);
     }
 }
 
-fn sub_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
-    where T: Send, F:Sync + Fn(&T, &T) -> Ordering
+fn sub_sort<T, F, C>(x: &mut [T], offset: usize, forward: bool, comparator: &F, threshold: usize, counter: &C)
+    where T: Send, F:Sync + Fn(&T, &T) -> Ordering, C: Counter
 {
     if x.len() > 1 {
-        compare_and_swap(x, forward, comparator);
-        let mid_point = x.len() / 2;
+        compare_and_swap(x, offset, forward, comparator, counter);
+        // x.len()未満で最大の2のべき乗を境に分割する（2のべき乗なら半分の位置）
+        let mid_point = x.len().next_power_of_two() / 2;
         let (first, second) = x.split_at_mut(mid_point);
-        if mid_point >= PARALLEL_THRESHOLD {
-            rayon::join(|| do_sort(first, forward, comparator),
-                        || do_sort(second, forward, comparator));
+        if mid_point >= threshold {
+            // 順次処理と同じくsub_sortを呼ぶ。ここでdo_sortを呼ぶとソート済みの列を
+            // もう一度ソートし直すことになり、ネットワークも順次処理と食い違う
+            rayon::join(|| sub_sort(first, offset, forward, comparator, threshold, counter),
+                        || sub_sort(second, offset + mid_point, forward, comparator, threshold, counter));
         } else {
-            sub_sort(first, forward, comparator);
-            sub_sort(second, forward, comparator);
+            sub_sort(first, offset, forward, comparator, threshold, counter);
+            sub_sort(second, offset + mid_point, forward, comparator, threshold, counter);
         }
     }
 }
 
-fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F) 
-    where F: Fn(&T, &T) -> Ordering
+fn compare_and_swap<T, F, C>(x: &mut [T], offset: usize, forward: bool, comparator: &F, counter: &C)
+    where F: Fn(&T, &T) -> Ordering, C: Counter
 {
     // 比較前にforwardをOrdering値に変換しておく
     let swap_condition = if forward {
@@ -73,13 +160,19 @@ fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
         Ordering::Less
     };
 
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
+    let mid_point = x.len().next_power_of_two() / 2;
+    counter.count_comparisons((x.len() - mid_point) as u64);
+    // 比較交換のあと、comparatorの順序で前に来る要素の位置をlo、後ろに来る要素の位置をhiとする
+    let (lo, hi) = if forward { (0, mid_point) } else { (mid_point, 0) };
+    // 比較相手（mid_point + i）が存在する範囲だけを比較する
+    for i in 0..(x.len() - mid_point) {
         // closureで2要素を比較し、返されたOrderingのバリアント(値)が
         // swap_conditionと等しいなら要素を交換する
+        counter.record_compare_exchange(offset + lo + i, offset + hi + i);
         if comparator(&x[i], &x[mid_point + i]) == swap_condition {
             // 要素を交換する
             x.swap(i, mid_point + i);
+            counter.count_swaps(1);
         }
     }
 }
@@ -87,8 +180,10 @@ fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
 #[cfg(test)]
 mod tests {
     // 親モジュール（first）のsort関数を使用する
-    use super::{sort, sort_by};
+    use super::{sort, sort_by, sort_by_with_config, sort_with_config, SortConfig, PARALLEL_THRESHOLD};
     use crate::SortOrder::*;
+    use crate::third;
+    use std::cmp::Ordering;
     use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
 
     // 構造体Studentを定義する
@@ -179,11 +274,47 @@ mod tests {
     }
 
     #[test]
-    fn sort_to_fail() {
-        // x.len()が2のべき乗になっていない
+    fn sort_not_power_of_two() {
+        // x.len()が2のべき乗になっていなくてもソートできる
         let mut x = vec![10, 30, 11];
-        // 戻り値はエラー
-        assert!(sort(&mut x, &Ascending).is_err());
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![10, 11, 30]);
+
+        let mut x = vec![10, 30, 11, 20, 4, 330];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 30, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_every_length() {
+        // 要素数0から3,000までのすべての長さで、標準ライブラリのソート結果と比較する
+        for len in 0..=3000 {
+            // 値の範囲を狭めて、重複する要素も含まれるようにする
+            let data: Vec<u32> = new_u32_vec(len).into_iter().map(|n| n % 1000).collect();
+
+            let mut expected = data.clone();
+            expected.sort();
+            let mut x = data.clone();
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+
+            expected.reverse();
+            let mut x = data;
+            assert_eq!(sort(&mut x, &Descending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn sort_large_not_power_of_two() {
+        // 並列処理の閾値を超える、2のべき乗でない要素数でもソートできる
+        for &len in &[8191, 8193, 10000, 65537] {
+            let mut x = new_u32_vec(len);
+            let mut expected = x.clone();
+            expected.sort();
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+        }
     }
 
     #[test]
@@ -229,4 +360,79 @@ mod tests {
         );
         assert_eq!(x, expected);
     }
-}
\ No newline at end of file
+
+    // テストで使う比較関数。名前はアサーション失敗時のメッセージに表示する
+    type NamedComparator = (&'static str, fn(&u32, &u32) -> Ordering);
+
+    #[test]
+    fn sort_matches_sequential_around_threshold() {
+        // 並列処理に切り替わる境目（分割後の要素数が閾値以上）の前後と、それより大きい要素数
+        let lens = [
+            PARALLEL_THRESHOLD - 1,
+            PARALLEL_THRESHOLD,
+            PARALLEL_THRESHOLD * 2 - 1,
+            PARALLEL_THRESHOLD * 2,
+            PARALLEL_THRESHOLD * 2 + 1,
+            PARALLEL_THRESHOLD * 3 + 7,
+            PARALLEL_THRESHOLD * 4,
+            PARALLEL_THRESHOLD * 16 + 1,
+        ];
+
+        // 同じ値が多数ある比較関数も含める。バイトニックソートは安定ではないので、
+        // 等しい要素の並びまで一致すれば、並列版と順次版が同じネットワークを辿ったと分かる
+        let comparators: [NamedComparator; 4] = [
+            ("ascending", |a, b| a.cmp(b)),
+            ("descending", |a, b| b.cmp(a)),
+            ("mod 100 ascending", |a, b| (a % 100).cmp(&(b % 100))),
+            ("mod 100 descending", |a, b| (b % 100).cmp(&(a % 100))),
+        ];
+
+        for &len in &lens {
+            let data = new_u32_vec(len);
+            for &(name, comparator) in &comparators {
+                let mut expected = data.clone();
+                assert_eq!(third::sort_by(&mut expected, &comparator), Ok(()));
+
+                let mut x = data.clone();
+                assert_eq!(sort_by(&mut x, &comparator), Ok(()));
+                assert!(x == expected, "len: {}, comparator: {}", len, name);
+            }
+        }
+    }
+
+    #[test]
+    fn sort_with_custom_config() {
+        let data = new_u32_vec(PARALLEL_THRESHOLD * 4 + 3);
+        let mut expected = data.clone();
+        expected.sort();
+
+        let configs = vec![
+            // 閾値を小さくして、細かく並列処理させる
+            SortConfig::new().threshold(16),
+            SortConfig::new().threshold(1).num_threads(2).unwrap(),
+            SortConfig::new().num_threads(1).unwrap(),
+            SortConfig::new().sequential(),
+        ];
+
+        for config in &configs {
+            let mut x = data.clone();
+            assert_eq!(sort_with_config(&mut x, &Ascending, config), Ok(()));
+            assert!(x == expected, "config: {:?}", config);
+        }
+    }
+
+    #[test]
+    fn sort_by_with_config_matches_sequential() {
+        // 設定を変えてもネットワークは変わらないので、等しい要素の並びまで一致する
+        let data = new_u32_vec(PARALLEL_THRESHOLD * 2 + 1);
+        let comparator = |a: &u32, b: &u32| (b % 100).cmp(&(a % 100));
+
+        let mut expected = data.clone();
+        assert_eq!(third::sort_by(&mut expected, &comparator), Ok(()));
+
+        let config = SortConfig::new().threshold(64).num_threads(3).unwrap();
+        let mut x = data;
+        assert_eq!(sort_by_with_config(&mut x, &comparator, &config), Ok(()));
+        assert!(x == expected);
+    }
+}
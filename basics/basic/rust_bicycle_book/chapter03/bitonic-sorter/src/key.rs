@@ -0,0 +1,307 @@
+use super::SortOrder;
+use super::fourth;
+use std::cmp::Ordering;
+
+// 要素からキーを取り出し、キーの順序でソートする
+// キーは比較のたびに計算されるので、計算が軽いキー（フィールドのコピーなど）に向いている
+pub fn sort_by_key<T, K, F>(x: &mut [T], key: &F, order: &SortOrder) -> Result<(), String>
+    where T: Send, K: Ord, F: Sync + Fn(&T) -> K
+{
+    match *order {
+        SortOrder::Ascending => fourth::sort_by(x, &|a, b| key(a).cmp(&key(b))),
+        SortOrder::Descending => fourth::sort_by(x, &|a, b| key(b).cmp(&key(a))),
+    }
+}
+
+// sort_by_keyと同じだが、キーは要素ごとに1回だけ計算する
+// キーの計算が重い（文字列の生成など）ときに使う。等しいキーの要素の順序は保たれる
+pub fn sort_by_cached_key<T, K, F>(x: &mut [T], key: &F, order: &SortOrder) -> Result<(), String>
+    where K: Ord + Send, F: Fn(&T) -> K
+{
+    // (キー, 元の位置)の組をソートする。元の位置も比較するので、結果は安定になる
+    let mut keys: Vec<(K, usize)> = x.iter().map(key).zip(0..).collect();
+    match *order {
+        SortOrder::Ascending => fourth::sort_by(&mut keys, &|a, b| a.cmp(b))?,
+        SortOrder::Descending => {
+            fourth::sort_by(&mut keys, &|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)))?
+        }
+    }
+    let mut indices: Vec<usize> = keys.into_iter().map(|(_, i)| i).collect();
+    apply_permutation(x, &mut indices);
+    Ok(())
+}
+
+// 安定ソート。comparatorが等しいと判定した要素は、元の順序のまま並ぶ
+// バイトニックソートは安定ではないので、各要素の元の位置を第2のキーとして比較する
+pub fn stable_sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
+    where T: Sync, F: Sync + Fn(&T, &T) -> Ordering
+{
+    // 要素そのものではなく、元の位置（インデックス）の列をソートする
+    let mut indices: Vec<usize> = (0..x.len()).collect();
+    {
+        let x: &[T] = x;
+        fourth::sort_by(&mut indices, &|&i, &j| comparator(&x[i], &x[j]).then(i.cmp(&j)))?;
+    }
+    apply_permutation(x, &mut indices);
+    Ok(())
+}
+
+pub fn stable_sort_by_key<T, K, F>(x: &mut [T], key: &F, order: &SortOrder) -> Result<(), String>
+    where T: Sync, K: Ord, F: Sync + Fn(&T) -> K
+{
+    match *order {
+        SortOrder::Ascending => stable_sort_by(x, &|a, b| key(a).cmp(&key(b))),
+        SortOrder::Descending => stable_sort_by(x, &|a, b| key(b).cmp(&key(a))),
+    }
+}
+
+// Comparatorで表した順序でソートする
+pub fn sort_by_comparator<T: Send>(x: &mut [T], comparator: &Comparator<T>) -> Result<(), String> {
+    fourth::sort_by(x, &|a, b| comparator.compare(a, b))
+}
+
+pub fn stable_sort_by_comparator<T: Sync>(x: &mut [T], comparator: &Comparator<T>) -> Result<(), String> {
+    stable_sort_by(x, &|a, b| comparator.compare(a, b))
+}
+
+// ソート後の位置kに、元の位置indices[k]の要素が来るようにxを並べ替える
+// 要素をコピーせず、交換だけで並べ替える（標準ライブラリのsort_by_cached_keyと同じ方法）
+fn apply_permutation<T>(x: &mut [T], indices: &mut [usize]) {
+    for i in 0..x.len() {
+        // 元の位置の要素がすでに交換で移動していたら、移動先をたどる
+        let mut index = indices[i];
+        while index < i {
+            index = indices[index];
+        }
+        indices[i] = index;
+        x.swap(i, index);
+    }
+}
+
+// 複数のキーを組み合わせた比較関数を組み立てる
+// 例：by(|s: &Student| &s.last_name).then_desc(|s| &s.age)
+//     → last_nameの昇順、last_nameが等しければageの降順
+pub struct Comparator<'a, T> {
+    compare: BoxedCompare<'a, T>,
+}
+
+// 並列ソートのスレッド間で共有できる比較関数
+type BoxedCompare<'a, T> = Box<dyn Fn(&T, &T) -> Ordering + Send + Sync + 'a>;
+
+// 最初のキーで昇順に比較するComparatorを作る
+pub fn by<'a, T: 'a, K, F>(key: F) -> Comparator<'a, T>
+    where K: Ord + ?Sized, F: Fn(&T) -> &K + Send + Sync + 'a
+{
+    Comparator::by_order(key, &SortOrder::Ascending)
+}
+
+// 最初のキーで降順に比較するComparatorを作る
+pub fn by_desc<'a, T: 'a, K, F>(key: F) -> Comparator<'a, T>
+    where K: Ord + ?Sized, F: Fn(&T) -> &K + Send + Sync + 'a
+{
+    Comparator::by_order(key, &SortOrder::Descending)
+}
+
+impl<'a, T: 'a> Comparator<'a, T> {
+    // キーと順序（SortOrder）を指定してComparatorを作る
+    pub fn by_order<K, F>(key: F, order: &SortOrder) -> Self
+        where K: Ord + ?Sized, F: Fn(&T) -> &K + Send + Sync + 'a
+    {
+        match *order {
+            SortOrder::Ascending => Self::with(move |a, b| key(a).cmp(key(b))),
+            SortOrder::Descending => Self::with(move |a, b| key(b).cmp(key(a))),
+        }
+    }
+
+    // 比較関数そのものからComparatorを作る
+    pub fn with<F>(compare: F) -> Self
+        where F: Fn(&T, &T) -> Ordering + Send + Sync + 'a
+    {
+        Self { compare: Box::new(compare) }
+    }
+
+    // これまでのキーで等しい場合に、keyの昇順で比較する
+    pub fn then<K, F>(self, key: F) -> Self
+        where K: Ord + ?Sized, F: Fn(&T) -> &K + Send + Sync + 'a
+    {
+        self.then_order(key, &SortOrder::Ascending)
+    }
+
+    // これまでのキーで等しい場合に、keyの降順で比較する
+    pub fn then_desc<K, F>(self, key: F) -> Self
+        where K: Ord + ?Sized, F: Fn(&T) -> &K + Send + Sync + 'a
+    {
+        self.then_order(key, &SortOrder::Descending)
+    }
+
+    pub fn then_order<K, F>(self, key: F, order: &SortOrder) -> Self
+        where K: Ord + ?Sized, F: Fn(&T) -> &K + Send + Sync + 'a
+    {
+        self.then_comparator(Self::by_order(key, order))
+    }
+
+    // これまでのキーで等しい場合に、比較関数compareで比較する
+    // 計算で求めるキーなど、要素への参照として取り出せないキーに使う
+    pub fn then_with<F>(self, compare: F) -> Self
+        where F: Fn(&T, &T) -> Ordering + Send + Sync + 'a
+    {
+        self.then_comparator(Self::with(compare))
+    }
+
+    // 順序全体を逆にする
+    pub fn reverse(self) -> Self {
+        let compare = self.compare;
+        Self::with(move |a, b| compare(b, a))
+    }
+
+    pub fn compare(&self, a: &T, b: &T) -> Ordering {
+        (self.compare)(a, b)
+    }
+
+    fn then_comparator(self, next: Self) -> Self {
+        let (first, second) = (self.compare, next.compare);
+        // firstで等しい（Equal）ときだけsecondで比較する
+        Self::with(move |a, b| first(a, b).then_with(|| second(a, b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SortOrder::*;
+    use crate::utils::new_u32_vec;
+
+    // deriveアトリビュートを使い、DebugトレイトとPartialEqトレイトの実装を自動導出する
+    #[derive(Debug, PartialEq)]
+    struct Student {
+        first_name: String,
+        last_name: String,
+        age: u8,
+    }
+
+    impl Student {
+        fn new(first_name: &str, last_name: &str, age: u8) -> Self {
+            Self {
+                first_name: first_name.to_string(),
+                last_name: last_name.to_string(),
+                age,
+            }
+        }
+    }
+
+    #[test]
+    fn sort_student_by_key() {
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "Yamada", 14);
+        let kyoko = Student::new("Kyoko", "Ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        assert_eq!(sort_by_key(&mut x, &|s| s.age, &Ascending), Ok(()));
+        assert_eq!(x, vec![&hanako, &kyoko, &taro, &ryosuke]);
+
+        assert_eq!(sort_by_key(&mut x, &|s| s.age, &Descending), Ok(()));
+        assert_eq!(x, vec![&ryosuke, &taro, &kyoko, &hanako]);
+    }
+
+    #[test]
+    fn sort_student_by_cached_key() {
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "Yamada", 14);
+        let kyoko = Student::new("Kyoko", "Ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+
+        // 姓名を連結した文字列をキーにする。キーの生成は要素ごとに1回だけ
+        let full_name = |s: &&Student| format!("{} {}", s.last_name, s.first_name);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        assert_eq!(sort_by_cached_key(&mut x, &full_name, &Ascending), Ok(()));
+        assert_eq!(x, vec![&ryosuke, &kyoko, &hanako, &taro]);
+
+        assert_eq!(sort_by_cached_key(&mut x, &full_name, &Descending), Ok(()));
+        assert_eq!(x, vec![&taro, &hanako, &kyoko, &ryosuke]);
+    }
+
+    #[test]
+    fn sort_student_by_multiple_keys() {
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "Yamada", 14);
+        let jiro = Student::new("Jiro", "Yamada", 16);
+        let kyoko = Student::new("Kyoko", "Ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+
+        // last_nameの昇順、ageの降順、first_nameの昇順
+        let comparator = by(|s: &&Student| &s.last_name)
+            .then_desc(|s| &s.age)
+            .then(|s| s.first_name.as_str());
+
+        let mut x = vec![&taro, &hanako, &jiro, &kyoko, &ryosuke];
+        assert_eq!(sort_by_comparator(&mut x, &comparator), Ok(()));
+        assert_eq!(x, vec![&ryosuke, &kyoko, &jiro, &taro, &hanako]);
+
+        // 全体を逆順にする
+        let comparator = comparator.reverse();
+        assert_eq!(sort_by_comparator(&mut x, &comparator), Ok(()));
+        assert_eq!(x, vec![&hanako, &taro, &jiro, &kyoko, &ryosuke]);
+    }
+
+    #[test]
+    fn sort_by_comparator_with_sort_order() {
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "Yamada", 14);
+        let kyoko = Student::new("Kyoko", "Ito", 15);
+
+        // 順序をSortOrderの値で切り替える。計算で求めるキーはthen_withで比較する
+        let order = Descending;
+        let comparator = Comparator::by_order(|s: &&Student| &s.last_name, &order)
+            .then_with(|a, b| (a.age / 2).cmp(&(b.age / 2)))
+            .then_order(|s| &s.first_name, &Ascending);
+
+        let mut x = vec![&kyoko, &taro, &hanako];
+        assert_eq!(sort_by_comparator(&mut x, &comparator), Ok(()));
+        assert_eq!(x, vec![&hanako, &taro, &kyoko]);
+    }
+
+    #[test]
+    fn stable_sort_keeps_original_order() {
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "Yamada", 14);
+        let kyoko = Student::new("Kyoko", "Ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+        let jiro = Student::new("Jiro", "Ito", 16);
+
+        // last_nameが等しい生徒は、元の順序のまま並ぶ
+        let mut x = vec![&taro, &kyoko, &hanako, &ryosuke, &jiro];
+        assert_eq!(stable_sort_by_key(&mut x, &|s| s.last_name.clone(), &Ascending), Ok(()));
+        assert_eq!(x, vec![&ryosuke, &kyoko, &jiro, &taro, &hanako]);
+
+        let mut x = vec![&taro, &kyoko, &hanako, &ryosuke, &jiro];
+        let comparator = by_desc(|s: &&Student| &s.last_name);
+        assert_eq!(stable_sort_by_comparator(&mut x, &comparator), Ok(()));
+        assert_eq!(x, vec![&taro, &hanako, &kyoko, &jiro, &ryosuke]);
+    }
+
+    #[test]
+    fn stable_sort_matches_std() {
+        // 標準ライブラリのsort_by_key（安定ソート）と結果を比較する
+        for &len in &[0, 1, 2, 3, 100, 1000, 4097, 10000] {
+            // (キー, 元の位置)の組。キーの値の範囲を狭めて重複させる
+            let data: Vec<(u32, usize)> = new_u32_vec(len)
+                .into_iter()
+                .map(|n| n % 10)
+                .zip(0..)
+                .collect();
+
+            let mut expected = data.clone();
+            expected.sort_by_key(|&(k, _)| k);
+
+            let mut x = data.clone();
+            assert_eq!(stable_sort_by(&mut x, &|a, b| a.0.cmp(&b.0)), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+
+            let mut x = data;
+            assert_eq!(sort_by_cached_key(&mut x, &|&(k, _)| k, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+        }
+    }
+}
@@ -1,5 +1,8 @@
 pub mod utils;
 
+// ソート中の比較と交換の回数を数える
+pub mod counter;
+
 // 第1段階：初歩的な実装。u32型の値のソートのみに対応
 pub mod first;
 
@@ -12,6 +15,23 @@ pub mod third;
 // 最終形：並列ソート
 pub mod fourth;
 
+// 最終形の並列ソートを使った、キーによるソート、複数キーの比較、安定ソート
+pub mod key;
+
+// 最終形の並列ソートを使った、メモリに収まらないファイルの外部ソート
+pub mod external;
+
+// バイトニックソート以外のソートアルゴリズム
+pub mod odd_even;   // 奇偶マージソート（ソーティングネットワーク）
+pub mod merge;      // 並列マージソート
+pub mod radix;      // LSD基数ソート
+
+// 各ソートを共通の方法で呼び出すためのSorterトレイト
+pub mod sorter;
+
+// ソートが辿ったソーティングネットワークの記録と図示
+pub mod network;
+
 pub enum SortOrder {
     Ascending,   // 昇順
     Descending,  // 降順
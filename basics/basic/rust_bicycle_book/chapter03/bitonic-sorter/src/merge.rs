@@ -0,0 +1,120 @@
+use super::SortOrder;
+use super::counter::Counter;
+use rayon;
+use std::cmp::Ordering;
+
+// rayonで並列化したマージソート。安定ソートで、要素数と同じ大きさの作業領域を使う
+
+// 並列処理をするか決定する閾値
+const PARALLEL_THRESHOLD: usize = 4096;
+
+pub fn sort<T: Ord + Send + Clone>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
+    where T: Send + Clone, F: Sync + Fn(&T, &T) -> Ordering
+{
+    sort_by_counted(x, comparator, &())
+}
+
+// sort_byと同じだが、比較の回数と要素を移動した回数（交換の回数として）をcounterで数える
+pub fn sort_by_counted<T, F, C>(x: &mut [T], comparator: &F, counter: &C) -> Result<(), String>
+    where T: Send + Clone, F: Sync + Fn(&T, &T) -> Ordering, C: Counter
+{
+    // マージ先の作業領域。中身はマージのたびに上書きされる
+    let mut buf = x.to_vec();
+    do_sort(x, &mut buf, comparator, counter);
+    Ok(())
+}
+
+fn do_sort<T, F, C>(x: &mut [T], buf: &mut [T], comparator: &F, counter: &C)
+    where T: Send + Clone, F: Sync + Fn(&T, &T) -> Ordering, C: Counter
+{
+    if x.len() > 1 {
+        let mid_point = x.len() / 2;
+        {
+            let (first, second) = x.split_at_mut(mid_point);
+            let (first_buf, second_buf) = buf.split_at_mut(mid_point);
+            if mid_point >= PARALLEL_THRESHOLD {
+                rayon::join(|| do_sort(first, first_buf, comparator, counter),
+                            || do_sort(second, second_buf, comparator, counter));
+            } else {
+                do_sort(first, first_buf, comparator, counter);
+                do_sort(second, second_buf, comparator, counter);
+            }
+        }
+        merge(x, mid_point, buf, comparator, counter);
+        // マージした結果をxに書き戻す
+        x.clone_from_slice(buf);
+    }
+}
+
+// ソート済みのx[..mid_point]とx[mid_point..]をマージしてbufに書き込む
+fn merge<T, F, C>(x: &[T], mid_point: usize, buf: &mut [T], comparator: &F, counter: &C)
+    where T: Clone, F: Fn(&T, &T) -> Ordering, C: Counter
+{
+    let (first, second) = x.split_at(mid_point);
+    let (mut i, mut j) = (0, 0);
+    let mut comparisons = 0;
+    for slot in buf.iter_mut() {
+        // 後半の要素が厳密に小さいときだけ後半から取るので、等しい要素の順序は保たれる
+        let take_second = if i == first.len() {
+            true
+        } else if j == second.len() {
+            false
+        } else {
+            comparisons += 1;
+            comparator(&second[j], &first[i]) == Ordering::Less
+        };
+        if take_second {
+            slot.clone_from(&second[j]);
+            j += 1;
+        } else {
+            slot.clone_from(&first[i]);
+            i += 1;
+        }
+    }
+    counter.count_comparisons(comparisons);
+    counter.count_swaps(buf.len() as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sort, sort_by, PARALLEL_THRESHOLD};
+    use crate::SortOrder::*;
+    use crate::utils::new_u32_vec;
+
+    #[test]
+    fn sort_u32_ascending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![4, 10, 11, 20, 21, 30, 110, 330]);
+    }
+
+    #[test]
+    fn sort_u32_descending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_is_stable() {
+        // 並列処理の閾値を超える要素数で、(キー, 元の位置)の組をキーだけで比較してソートする
+        let data: Vec<(u32, usize)> = new_u32_vec(PARALLEL_THRESHOLD * 3 + 5)
+            .into_iter()
+            .map(|n| n % 10)
+            .zip(0..)
+            .collect();
+        let mut expected = data.clone();
+        expected.sort_by_key(|&(k, _)| k);
+
+        let mut x = data;
+        assert_eq!(sort_by(&mut x, &|a, b| a.0.cmp(&b.0)), Ok(()));
+        assert_eq!(x, expected);
+    }
+}
@@ -0,0 +1,346 @@
+use super::counter::{Counter, Counters};
+use super::fourth::{self, SortConfig};
+use super::{odd_even, third};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// 比較と交換の回数を数え、さらに比較交換した位置の組を順に記録するカウンタ
+// sort_by_countedに渡すと、ソートが辿ったソーティングネットワークを取り出せる
+#[derive(Debug, Default)]
+pub struct Recorder {
+    counters: Counters,
+    pairs: Mutex<Vec<(usize, usize)>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn comparisons(&self) -> u64 {
+        self.counters.comparisons()
+    }
+
+    pub fn swaps(&self) -> u64 {
+        self.counters.swaps()
+    }
+
+    // 記録した比較交換の列を、wires本のワイヤ（要素数）のネットワークとして取り出す
+    pub fn into_network(self, wires: usize) -> Network {
+        // ロック中にパニックしたスレッドがあっても、記録済みの組はそのまま使う
+        let pairs = self.pairs.into_inner().unwrap_or_else(|e| e.into_inner());
+        Network::new(wires, pairs)
+    }
+}
+
+impl Counter for Recorder {
+    fn count_comparisons(&self, n: u64) {
+        self.counters.count_comparisons(n);
+    }
+
+    fn count_swaps(&self, n: u64) {
+        self.counters.count_swaps(n);
+    }
+
+    fn record_compare_exchange(&self, i: usize, j: usize) {
+        self.pairs.lock().unwrap_or_else(|e| e.into_inner()).push((i, j));
+    }
+}
+
+// ソーティングネットワーク
+// 比較交換(i, j)は、位置iとjの要素を比べ、小さい方（ソート順で前に来る方）をiに置く
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    wires: usize,
+    comparators: Vec<(usize, usize)>,
+}
+
+impl Network {
+    pub fn new(wires: usize, comparators: Vec<(usize, usize)>) -> Self {
+        Self { wires, comparators }
+    }
+
+    // ワイヤの本数（ソートする要素数）
+    pub fn wires(&self) -> usize {
+        self.wires
+    }
+
+    pub fn comparators(&self) -> &[(usize, usize)] {
+        &self.comparators
+    }
+
+    // ネットワークの比較交換をxに順に適用する
+    pub fn apply<T: Ord>(&self, x: &mut [T]) {
+        for &(i, j) in &self.comparators {
+            if x[i] > x[j] {
+                x.swap(i, j);
+            }
+        }
+    }
+
+    // 比較交換を、同時に実行できるもの同士の層に分ける
+    // 各比較交換は、2本のワイヤのどちらかで直前に使われた層の次の層に置く
+    // 並列ソートで記録の順序が入れ替わっても、ワイヤごとの順序が同じなら同じ層になる
+    pub fn layers(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut next_layer = vec![0; self.wires];
+        let mut layers: Vec<Vec<(usize, usize)>> = Vec::new();
+        for &(i, j) in &self.comparators {
+            let layer = next_layer[i].max(next_layer[j]);
+            if layer == layers.len() {
+                layers.push(Vec::new());
+            }
+            layers[layer].push((i, j));
+            next_layer[i] = layer + 1;
+            next_layer[j] = layer + 1;
+        }
+        layers
+    }
+
+    // ネットワークの深さ（層の数）
+    pub fn depth(&self) -> usize {
+        self.layers().len()
+    }
+
+    // テキストで図を描く。ワイヤを横線で、比較交換を縦線で表す
+    // 小さい要素が来る側をo、大きい要素が来る側を矢印（vまたは^）で示す
+    //
+    //   0 --o--o-----
+    //   1 --v--|--o--
+    //   2 -----v--v--
+    pub fn to_text(&self) -> String {
+        let columns = self.columns();
+        let label_width = self.wires.saturating_sub(1).to_string().len();
+        let mut text = String::new();
+        for wire in 0..self.wires {
+            write!(text, "{:>width$} -", wire, width = label_width).unwrap();
+            for column in &columns {
+                let symbol = column
+                    .iter()
+                    .find_map(|&(i, j)| symbol_at(wire, i, j))
+                    .unwrap_or('-');
+                write!(text, "-{}-", symbol).unwrap();
+            }
+            text.push_str("-\n");
+        }
+        text
+    }
+
+    // SVGで図を描く。小さい要素が来る側を白丸、大きい要素が来る側を黒丸で示す
+    pub fn to_svg(&self) -> String {
+        const SPACING: usize = 20;
+        const MARGIN: usize = 20;
+        // 層の境目は少し間隔を空ける
+        const LAYER_GAP: usize = 10;
+
+        let columns = self.columns_with_layer_breaks();
+        let breaks = columns.iter().filter(|(new_layer, _)| *new_layer).count();
+        let width = MARGIN * 2 + columns.len() * SPACING + breaks * LAYER_GAP;
+        let height = MARGIN * 2 + self.wires.saturating_sub(1) * SPACING;
+        let y = |wire: usize| MARGIN + wire * SPACING;
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = width,
+            h = height
+        )
+        .unwrap();
+        writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+        for wire in 0..self.wires {
+            writeln!(
+                svg,
+                r#"<line x1="{}" y1="{y}" x2="{}" y2="{y}" stroke="gray"/>"#,
+                MARGIN / 2,
+                width - MARGIN / 2,
+                y = y(wire)
+            )
+            .unwrap();
+        }
+
+        let mut x = MARGIN;
+        for (new_layer, column) in &columns {
+            if *new_layer {
+                x += LAYER_GAP;
+            }
+            x += SPACING / 2;
+            for &(i, j) in column {
+                writeln!(
+                    svg,
+                    r#"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="black"/>"#,
+                    y(i),
+                    y(j),
+                    x = x
+                )
+                .unwrap();
+                writeln!(svg, r#"<circle cx="{}" cy="{}" r="3" fill="white" stroke="black"/>"#, x, y(i)).unwrap();
+                writeln!(svg, r#"<circle cx="{}" cy="{}" r="3" fill="black"/>"#, x, y(j)).unwrap();
+            }
+            x += SPACING / 2;
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    // 図の列。同じ層の比較交換のうち、縦線が重ならないものは同じ列に描く
+    fn columns(&self) -> Vec<Vec<(usize, usize)>> {
+        self.columns_with_layer_breaks()
+            .into_iter()
+            .map(|(_, column)| column)
+            .collect()
+    }
+
+    // 図の列と、その列が新しい層の最初の列かどうかの組
+    fn columns_with_layer_breaks(&self) -> Vec<(bool, Vec<(usize, usize)>)> {
+        let mut columns = Vec::new();
+        for (n, layer) in self.layers().into_iter().enumerate() {
+            let mut layer_columns: Vec<Vec<(usize, usize)>> = Vec::new();
+            for (i, j) in layer {
+                let overlaps = |column: &Vec<(usize, usize)>| {
+                    column.iter().any(|&(a, b)| a.min(b) <= i.max(j) && i.min(j) <= a.max(b))
+                };
+                match layer_columns.iter_mut().find(|column| !overlaps(column)) {
+                    Some(column) => column.push((i, j)),
+                    None => layer_columns.push(vec![(i, j)]),
+                }
+            }
+            for (k, column) in layer_columns.into_iter().enumerate() {
+                columns.push((n > 0 && k == 0, column));
+            }
+        }
+        columns
+    }
+}
+
+// 比較交換(i, j)を描く列で、ワイヤwireの位置に置く記号
+fn symbol_at(wire: usize, i: usize, j: usize) -> Option<char> {
+    if wire == i {
+        Some('o')
+    } else if wire == j {
+        Some(if j > i { 'v' } else { '^' })
+    } else if i.min(j) < wire && wire < i.max(j) {
+        Some('|')
+    } else {
+        None
+    }
+}
+
+// 第3段階のバイトニックソートが要素数lenのときに辿るネットワーク
+// ソーティングネットワークの比較交換の列はデータによらないので、すべて同じ値の列をソートして記録する
+pub fn third_network(len: usize) -> Network {
+    let recorder = Recorder::new();
+    let mut x = vec![0u32; len];
+    third::sort_by_counted(&mut x, &|a, b| a.cmp(b), &recorder).unwrap();
+    recorder.into_network(len)
+}
+
+// 最終形（並列）のバイトニックソートが辿るネットワーク
+pub fn fourth_network(len: usize, config: &SortConfig) -> Network {
+    let recorder = Recorder::new();
+    let mut x = vec![0u32; len];
+    fourth::sort_by_counted(&mut x, &|a, b| a.cmp(b), config, &recorder).unwrap();
+    recorder.into_network(len)
+}
+
+// 奇偶マージソートが辿るネットワーク
+pub fn odd_even_network(len: usize) -> Network {
+    let recorder = Recorder::new();
+    let mut x = vec![0u32; len];
+    odd_even::sort_by_counted(&mut x, &|a, b| a.cmp(b), &recorder).unwrap();
+    recorder.into_network(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{is_sorted_ascending, new_u32_vec};
+
+    // 0と1だけからなるすべての入力をソートできれば、任意の入力をソートできる（0-1原理）
+    fn sorts_all_zero_one_inputs(network: &Network) -> bool {
+        let n = network.wires();
+        (0..1u32 << n).all(|bits| {
+            let mut x: Vec<u32> = (0..n).map(|i| (bits >> i) & 1).collect();
+            network.apply(&mut x);
+            is_sorted_ascending(&x)
+        })
+    }
+
+    // 層ごとに比較交換を並べ替えて、記録の順序に左右されない形にする
+    fn normalized_layers(network: &Network) -> Vec<Vec<(usize, usize)>> {
+        network
+            .layers()
+            .into_iter()
+            .map(|mut layer| {
+                layer.sort();
+                layer
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bitonic_network_of_four() {
+        // 前半を逆順、後半を正順にソートしてからマージする、教科書どおりの4要素のネットワーク
+        let network = third_network(4);
+        assert_eq!(network.comparators(), &[(1, 0), (2, 3), (0, 2), (1, 3), (0, 1), (2, 3)]);
+        assert_eq!(network.depth(), 3);
+        assert_eq!(network.to_text(), "\
+0 --^--o-----o--
+1 --o--|--o--v--
+2 --o--v--|--o--
+3 --v-----v--v--
+");
+    }
+
+    #[test]
+    fn bitonic_network_of_eight() {
+        // 8要素のバイトニックソートは、4つの比較交換からなる層が6つ
+        let recorder = Recorder::new();
+        let mut x = new_u32_vec(8);
+        third::sort_by_counted(&mut x, &|a, b| a.cmp(b), &recorder).unwrap();
+        assert_eq!(recorder.comparisons(), 24);
+        assert!(recorder.swaps() <= 24);
+
+        let network = recorder.into_network(8);
+        assert_eq!(network, third_network(8));
+        assert!(network.layers().iter().all(|layer| layer.len() == 4));
+        assert_eq!(network.depth(), 6);
+    }
+
+    #[test]
+    fn networks_sort_zero_one_inputs() {
+        for len in 0..=12 {
+            assert!(sorts_all_zero_one_inputs(&third_network(len)), "third, len: {}", len);
+            assert!(sorts_all_zero_one_inputs(&odd_even_network(len)), "odd-even, len: {}", len);
+        }
+    }
+
+    #[test]
+    fn fourth_network_matches_third() {
+        // 並列ソートでも、同時に実行できる比較交換の層は順次ソートと同じになる
+        let config = SortConfig::new().threshold(2).num_threads(4).unwrap();
+        for &len in &[8, 13, 64, 100] {
+            let third = third_network(len);
+            let fourth = fourth_network(len, &config);
+            assert_eq!(fourth.comparators().len(), third.comparators().len());
+            assert_eq!(normalized_layers(&fourth), normalized_layers(&third), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn odd_even_network_of_eight() {
+        let network = odd_even_network(8);
+        assert_eq!(network.comparators().len(), 19);
+        assert_eq!(network.depth(), 6);
+    }
+
+    #[test]
+    fn render_svg() {
+        let network = third_network(4);
+        let svg = network.to_svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        // ワイヤ4本と比較交換6個の線、比較交換ごとに2つの丸
+        assert_eq!(svg.matches("<line ").count(), 4 + 6);
+        assert_eq!(svg.matches("<circle ").count(), 6 * 2);
+    }
+}
@@ -0,0 +1,96 @@
+use super::SortOrder;
+use super::counter::Counter;
+use std::cmp::Ordering;
+
+// バッチャーの奇偶マージソート（odd-even merge sort）によるソーティングネットワーク
+// バイトニックソートより比較の回数が少ない。順次処理のみ
+
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
+    where F: Fn(&T, &T) -> Ordering
+{
+    sort_by_counted(x, comparator, &())
+}
+
+// sort_byと同じだが、比較と交換の回数をcounterで数える
+pub fn sort_by_counted<T, F, C>(x: &mut [T], comparator: &F, counter: &C) -> Result<(), String>
+    where F: Fn(&T, &T) -> Ordering, C: Counter
+{
+    let n = x.len();
+    // 要素数を2のべき乗に切り上げ、末尾を最大値で埋めたものとして考える
+    // 最大値との比較では交換が起きないので、範囲外（n以上）の位置との比較は省略できる
+    //
+    // pは長さpのソート済みの列を2つずつマージして、長さ2pの列を作る段階を表す
+    let mut p = 1;
+    while p < n {
+        // kは比較する2要素の距離
+        let mut k = p;
+        while k >= 1 {
+            let mut j = k % p;
+            while j + k < n {
+                for i in 0..k.min(n - j - k) {
+                    // 同じ長さ2pのブロックに属する2要素だけを比較する
+                    if (i + j) / (p * 2) == (i + j + k) / (p * 2) {
+                        compare_and_swap(x, i + j, i + j + k, comparator, counter);
+                    }
+                }
+                j += k * 2;
+            }
+            k /= 2;
+        }
+        p *= 2;
+    }
+    Ok(())
+}
+
+fn compare_and_swap<T, F, C>(x: &mut [T], i: usize, j: usize, comparator: &F, counter: &C)
+    where F: Fn(&T, &T) -> Ordering, C: Counter
+{
+    counter.count_comparisons(1);
+    counter.record_compare_exchange(i, j);
+    // i < jなので、x[i]の方が大きければ交換する
+    if comparator(&x[i], &x[j]) == Ordering::Greater {
+        x.swap(i, j);
+        counter.count_swaps(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sort, sort_by};
+    use crate::SortOrder::*;
+    use crate::utils::new_u32_vec;
+
+    #[test]
+    fn sort_u32_ascending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![4, 10, 11, 20, 21, 30, 110, 330]);
+    }
+
+    #[test]
+    fn sort_u32_descending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_every_length() {
+        // 要素数0から1,000までのすべての長さで、標準ライブラリのソート結果と比較する
+        for len in 0..=1000 {
+            let data: Vec<u32> = new_u32_vec(len).into_iter().map(|n| n % 1000).collect();
+            let mut expected = data.clone();
+            expected.sort();
+            let mut x = data;
+            assert_eq!(sort_by(&mut x, &|a, b| a.cmp(b)), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use super::SortOrder;
+use super::counter::Counter;
+
+// LSD（最下位桁から処理する）基数ソート。整数のキーにだけ使える
+// 要素を比較せず、1バイトずつ桁ごとに振り分ける
+
+// 基数ソートのキーとして使える型
+// keyはキーの大小関係を保ったまま、符号なし64ビット整数に変換した値
+pub trait RadixKey: Copy {
+    const BYTES: usize;
+    fn key(self) -> u64;
+}
+
+macro_rules! impl_radix_key_unsigned {
+    ($($t:ty),*) => {
+        $(impl RadixKey for $t {
+            const BYTES: usize = std::mem::size_of::<$t>();
+            fn key(self) -> u64 {
+                self as u64
+            }
+        })*
+    };
+}
+
+// 符号付き整数は符号ビットを反転させると、符号なし整数として比較したときも大小関係が保たれる
+macro_rules! impl_radix_key_signed {
+    ($($t:ty => $u:ty),*) => {
+        $(impl RadixKey for $t {
+            const BYTES: usize = std::mem::size_of::<$t>();
+            fn key(self) -> u64 {
+                ((self as $u) ^ (1 << (Self::BYTES * 8 - 1))) as u64
+            }
+        })*
+    };
+}
+
+impl_radix_key_unsigned!(u8, u16, u32, u64, usize);
+impl_radix_key_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, isize => usize);
+
+pub fn sort<T: RadixKey>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+    sort_counted(x, order, &())
+}
+
+// sortと同じだが、要素を移動した回数を交換の回数としてcounterで数える（比較は行わない）
+pub fn sort_counted<T: RadixKey, C: Counter>(x: &mut [T], order: &SortOrder, counter: &C) -> Result<(), String> {
+    // 降順のときはキーのビットを反転させて、昇順にソートする
+    let flip = match *order {
+        SortOrder::Ascending => 0,
+        SortOrder::Descending => u64::MAX,
+    };
+    let digit = |value: T, shift: usize| (((value.key() ^ flip) >> shift) & 0xff) as usize;
+
+    let mut buf = x.to_vec();
+    // 振り分け元と振り分け先を桁ごとに入れ替える。trueならbufに最新の結果が入っている
+    let mut in_buf = false;
+    for byte in 0..T::BYTES {
+        let shift = byte * 8;
+        let (src, dst): (&[T], &mut [T]) = if in_buf {
+            (&buf, &mut *x)
+        } else {
+            (&*x, &mut buf)
+        };
+
+        // 各桁の値（0〜255）の出現回数を数える
+        let mut counts = [0usize; 256];
+        for &value in src.iter() {
+            counts[digit(value, shift)] += 1;
+        }
+        // すべての要素の桁が同じなら、振り分けても順序は変わらないので飛ばす
+        if counts.contains(&src.len()) {
+            continue;
+        }
+
+        // 出現回数の累積和から、各桁の値の書き込み開始位置を求める
+        let mut offsets = [0usize; 256];
+        for d in 1..256 {
+            offsets[d] = offsets[d - 1] + counts[d - 1];
+        }
+        // 元の順序を保ったまま振り分ける（安定）
+        for &value in src.iter() {
+            let d = digit(value, shift);
+            dst[offsets[d]] = value;
+            offsets[d] += 1;
+        }
+        counter.count_swaps(src.len() as u64);
+        in_buf = !in_buf;
+    }
+
+    if in_buf {
+        x.copy_from_slice(&buf);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sort;
+    use crate::SortOrder::*;
+    use crate::utils::new_u32_vec;
+
+    #[test]
+    fn sort_u32() {
+        let data = new_u32_vec(10000);
+        let mut expected = data.clone();
+        expected.sort();
+
+        let mut x = data.clone();
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, expected);
+
+        expected.reverse();
+        let mut x = data;
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn sort_signed_integers() {
+        let mut x: Vec<i32> = vec![10, -30, 11, 0, i32::MIN, 330, -1, i32::MAX];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![i32::MIN, -30, -1, 0, 10, 11, 330, i32::MAX]);
+
+        let mut x: Vec<i8> = vec![3, -128, 127, -1, 0];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![127, 3, 0, -1, -128]);
+    }
+
+    #[test]
+    fn sort_u64_with_shared_digits() {
+        // 上位の桁がすべて同じなので、その桁の振り分けは飛ばされる
+        let mut x: Vec<u64> = vec![0x0100_0000_0000_0003, 0x0100_0000_0000_0001, 0x0100_0000_0000_0002];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![0x0100_0000_0000_0001, 0x0100_0000_0000_0002, 0x0100_0000_0000_0003]);
+    }
+}
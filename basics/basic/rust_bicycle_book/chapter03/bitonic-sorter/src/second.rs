@@ -1,41 +1,51 @@
 use super::SortOrder;
+use super::counter::Counter;
 
+// 要素数が2のべき乗でなくてもソートできるので、Errを返すことはない
 pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
-    if x.len().is_power_of_two() {
-        match *order {
-            SortOrder::Ascending => do_sort(x, true),
-            SortOrder::Descending => do_sort(x, false),
-        };
-        Ok(())
-    } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
-    }
+    sort_counted(x, order, &())
+}
+
+// sortと同じだが、比較と交換の回数をcounterで数える
+pub fn sort_counted<T: Ord, C: Counter>(x: &mut [T], order: &SortOrder, counter: &C) -> Result<(), String> {
+    match *order {
+        SortOrder::Ascending => do_sort(x, true, counter),
+        SortOrder::Descending => do_sort(x, false, counter),
+    };
+    Ok(())
 }
 
-fn do_sort<T: Ord>(x: &mut [T], up: bool) {
+fn do_sort<T: Ord, C: Counter>(x: &mut [T], up: bool, counter: &C) {
     if x.len() > 1 {
+        // 要素数が奇数のときは後半の方が1つ多くなる
         let mid_point = x.len() / 2;
-        do_sort(&mut x[..mid_point], true);
-        do_sort(&mut x[mid_point..], false);
-        sub_sort(x, up);
+        // 前半をupと逆順、後半をupの順にソートする
+        // 要素数が2のべき乗でない場合でも、こうすると前半と後半を合わせてバイトニック列にできる
+        do_sort(&mut x[..mid_point], !up, counter);
+        do_sort(&mut x[mid_point..], up, counter);
+        sub_sort(x, up, counter);
     }
 }
 
-fn sub_sort<T: Ord>(x: &mut [T], up: bool) {
+fn sub_sort<T: Ord, C: Counter>(x: &mut [T], up: bool, counter: &C) {
     if x.len() > 1 {
-        compare_and_swap(x, up);
-        let mid_point = x.len() / 2;
-        sub_sort(&mut x[..mid_point], up);
-        sub_sort(&mut x[mid_point..], up);
+        compare_and_swap(x, up, counter);
+        // x.len()未満で最大の2のべき乗を境に分割する（2のべき乗なら半分の位置）
+        let mid_point = x.len().next_power_of_two() / 2;
+        sub_sort(&mut x[..mid_point], up, counter);
+        sub_sort(&mut x[mid_point..], up, counter);
     }
 }
 
-fn compare_and_swap<T: Ord>(x: &mut [T], up: bool) {
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
+fn compare_and_swap<T: Ord, C: Counter>(x: &mut [T], up: bool, counter: &C) {
+    let mid_point = x.len().next_power_of_two() / 2;
+    counter.count_comparisons((x.len() - mid_point) as u64);
+    // 比較相手（mid_point + i）が存在する範囲だけを比較する
+    for i in 0..(x.len() - mid_point) {
         if (x[i] > x[mid_point + i]) == up {
             // 要素を交換する
             x.swap(i, mid_point + i);
+            counter.count_swaps(1);
         }
     }
 }
@@ -45,6 +55,7 @@ mod tests {
     // 親モジュール（first）のsort関数を使用する
     use super::sort;
     use crate::SortOrder::*;
+    use crate::utils::new_u32_vec;
 
     // #[test]の付いた関数はcargo testとしたときに実行される
     #[test]
@@ -85,10 +96,34 @@ mod tests {
     }
 
     #[test]
-    fn sort_to_fail() {
-        // x.len()が2のべき乗になっていない
+    fn sort_not_power_of_two() {
+        // x.len()が2のべき乗になっていなくてもソートできる
         let mut x = vec![10, 30, 11];
-        // 戻り値はエラー
-        assert!(sort(&mut x, &Ascending).is_err());
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![10, 11, 30]);
+
+        let mut x = vec![10, 30, 11, 20, 4, 330];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 30, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_every_length() {
+        // 要素数0から3,000までのすべての長さで、標準ライブラリのソート結果と比較する
+        for len in 0..=3000 {
+            // 値の範囲を狭めて、重複する要素も含まれるようにする
+            let data: Vec<u32> = new_u32_vec(len).into_iter().map(|n| n % 1000).collect();
+
+            let mut expected = data.clone();
+            expected.sort();
+            let mut x = data.clone();
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+
+            expected.reverse();
+            let mut x = data;
+            assert_eq!(sort(&mut x, &Descending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,248 @@
+use super::SortOrder;
+use super::counter::Counters;
+use super::fourth::SortConfig;
+use super::radix::RadixKey;
+use super::{first, fourth, merge, odd_even, radix, second, third};
+
+// ソートの実装（バックエンド）を共通の方法で呼び出すためのトレイト
+// Box<dyn Sorter<T>>として扱えるので、実行時に名前でバックエンドを選べる
+pub trait Sorter<T> {
+    // バックエンドの名前。ベンチマークなどで選択や表示に使う
+    fn name(&self) -> &'static str;
+
+    fn sort(&self, x: &mut [T], order: &SortOrder) -> Result<(), String>;
+
+    // sortと同じだが、比較と交換の回数をcountersで数える
+    // 計測のぶん遅くなるので、実行時間の計測にはsortを使う
+    fn sort_counted(&self, x: &mut [T], order: &SortOrder, counters: &Counters) -> Result<(), String>;
+}
+
+// 第1段階のバイトニックソート。u32型で、要素数が2のべき乗のときだけソートできる
+pub struct BitonicFirst;
+
+// 第2段階のバイトニックソート
+pub struct BitonicSecond;
+
+// 第3段階のバイトニックソート（順次処理）
+pub struct BitonicThird;
+
+// 最終形のバイトニックソート（並列処理）
+#[derive(Default)]
+pub struct BitonicFourth {
+    pub config: SortConfig,
+}
+
+// バッチャーの奇偶マージソート（順次処理）
+pub struct OddEvenMergeSort;
+
+// 並列マージソート
+pub struct ParallelMergeSort;
+
+// LSD基数ソート。整数型だけに使える
+pub struct RadixSort;
+
+impl Sorter<u32> for BitonicFirst {
+    fn name(&self) -> &'static str {
+        "bitonic-first"
+    }
+
+    fn sort(&self, x: &mut [u32], order: &SortOrder) -> Result<(), String> {
+        self.sort_with(x, order, first::sort)
+    }
+
+    fn sort_counted(&self, x: &mut [u32], order: &SortOrder, counters: &Counters) -> Result<(), String> {
+        self.sort_with(x, order, |x, up| first::sort_counted(x, up, counters))
+    }
+}
+
+impl BitonicFirst {
+    // 第1段階のsortは要素数を検査しないので、ここで検査する
+    fn sort_with<F>(&self, x: &mut [u32], order: &SortOrder, sorter: F) -> Result<(), String>
+        where F: FnOnce(&mut [u32], bool)
+    {
+        if x.len() > 1 && !x.len().is_power_of_two() {
+            return Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()));
+        }
+        match *order {
+            SortOrder::Ascending => sorter(x, true),
+            SortOrder::Descending => sorter(x, false),
+        }
+        Ok(())
+    }
+}
+
+impl<T: Ord> Sorter<T> for BitonicSecond {
+    fn name(&self) -> &'static str {
+        "bitonic-second"
+    }
+
+    fn sort(&self, x: &mut [T], order: &SortOrder) -> Result<(), String> {
+        second::sort(x, order)
+    }
+
+    fn sort_counted(&self, x: &mut [T], order: &SortOrder, counters: &Counters) -> Result<(), String> {
+        second::sort_counted(x, order, counters)
+    }
+}
+
+impl<T: Ord> Sorter<T> for BitonicThird {
+    fn name(&self) -> &'static str {
+        "bitonic-third"
+    }
+
+    fn sort(&self, x: &mut [T], order: &SortOrder) -> Result<(), String> {
+        third::sort(x, order)
+    }
+
+    fn sort_counted(&self, x: &mut [T], order: &SortOrder, counters: &Counters) -> Result<(), String> {
+        match *order {
+            SortOrder::Ascending => third::sort_by_counted(x, &|a, b| a.cmp(b), counters),
+            SortOrder::Descending => third::sort_by_counted(x, &|a, b| b.cmp(a), counters),
+        }
+    }
+}
+
+impl<T: Ord + Send> Sorter<T> for BitonicFourth {
+    fn name(&self) -> &'static str {
+        "bitonic-fourth"
+    }
+
+    fn sort(&self, x: &mut [T], order: &SortOrder) -> Result<(), String> {
+        fourth::sort_with_config(x, order, &self.config)
+    }
+
+    fn sort_counted(&self, x: &mut [T], order: &SortOrder, counters: &Counters) -> Result<(), String> {
+        match *order {
+            SortOrder::Ascending => fourth::sort_by_counted(x, &|a, b| a.cmp(b), &self.config, counters),
+            SortOrder::Descending => fourth::sort_by_counted(x, &|a, b| b.cmp(a), &self.config, counters),
+        }
+    }
+}
+
+impl<T: Ord> Sorter<T> for OddEvenMergeSort {
+    fn name(&self) -> &'static str {
+        "odd-even-merge"
+    }
+
+    fn sort(&self, x: &mut [T], order: &SortOrder) -> Result<(), String> {
+        odd_even::sort(x, order)
+    }
+
+    fn sort_counted(&self, x: &mut [T], order: &SortOrder, counters: &Counters) -> Result<(), String> {
+        match *order {
+            SortOrder::Ascending => odd_even::sort_by_counted(x, &|a, b| a.cmp(b), counters),
+            SortOrder::Descending => odd_even::sort_by_counted(x, &|a, b| b.cmp(a), counters),
+        }
+    }
+}
+
+impl<T: Ord + Send + Clone> Sorter<T> for ParallelMergeSort {
+    fn name(&self) -> &'static str {
+        "merge"
+    }
+
+    fn sort(&self, x: &mut [T], order: &SortOrder) -> Result<(), String> {
+        merge::sort(x, order)
+    }
+
+    fn sort_counted(&self, x: &mut [T], order: &SortOrder, counters: &Counters) -> Result<(), String> {
+        match *order {
+            SortOrder::Ascending => merge::sort_by_counted(x, &|a, b| a.cmp(b), counters),
+            SortOrder::Descending => merge::sort_by_counted(x, &|a, b| b.cmp(a), counters),
+        }
+    }
+}
+
+impl<T: RadixKey> Sorter<T> for RadixSort {
+    fn name(&self) -> &'static str {
+        "radix"
+    }
+
+    fn sort(&self, x: &mut [T], order: &SortOrder) -> Result<(), String> {
+        radix::sort(x, order)
+    }
+
+    fn sort_counted(&self, x: &mut [T], order: &SortOrder, counters: &Counters) -> Result<(), String> {
+        radix::sort_counted(x, order, counters)
+    }
+}
+
+// u32型をソートできるすべてのバックエンド
+pub fn u32_sorters() -> Vec<Box<dyn Sorter<u32>>> {
+    vec![
+        Box::new(BitonicFirst),
+        Box::new(BitonicSecond),
+        Box::new(BitonicThird),
+        Box::new(BitonicFourth::default()),
+        Box::new(OddEvenMergeSort),
+        Box::new(ParallelMergeSort),
+        Box::new(RadixSort),
+    ]
+}
+
+// 名前でu32型のバックエンドを探す
+pub fn find_u32_sorter(name: &str) -> Option<Box<dyn Sorter<u32>>> {
+    u32_sorters().into_iter().find(|sorter| sorter.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SortOrder::*;
+    use crate::utils::new_u32_vec;
+
+    #[test]
+    fn every_sorter_sorts_u32() {
+        for sorter in u32_sorters() {
+            for &len in &[0usize, 1, 2, 8, 1024, 1000, 5000] {
+                // 第1段階は要素数が2のべき乗のときだけソートできる
+                if sorter.name() == "bitonic-first" && len > 1 && !len.is_power_of_two() {
+                    let mut x = new_u32_vec(len);
+                    assert!(sorter.sort(&mut x, &Ascending).is_err());
+                    continue;
+                }
+
+                let data = new_u32_vec(len);
+                let mut expected = data.clone();
+                expected.sort();
+
+                let mut x = data.clone();
+                assert_eq!(sorter.sort(&mut x, &Ascending), Ok(()));
+                assert!(x == expected, "{}: len {}", sorter.name(), len);
+
+                expected.reverse();
+                let mut x = data;
+                let counters = Counters::new();
+                assert_eq!(sorter.sort_counted(&mut x, &Descending, &counters), Ok(()));
+                assert!(x == expected, "{}: len {}", sorter.name(), len);
+            }
+        }
+    }
+
+    #[test]
+    fn network_comparison_counts() {
+        // ソーティングネットワークの比較回数はデータによらず、要素数だけで決まる
+        // 8要素のとき、バイトニックソートは24回、奇偶マージソートは19回
+        let expected = [
+            ("bitonic-first", 24),
+            ("bitonic-second", 24),
+            ("bitonic-third", 24),
+            ("bitonic-fourth", 24),
+            ("odd-even-merge", 19),
+            ("radix", 0),
+        ];
+        for &(name, comparisons) in &expected {
+            let sorter = find_u32_sorter(name).unwrap();
+            let counters = Counters::new();
+            let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+            assert_eq!(sorter.sort_counted(&mut x, &Ascending, &counters), Ok(()));
+            assert_eq!(counters.comparisons(), comparisons, "{}", name);
+            assert!(counters.swaps() > 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn find_unknown_sorter() {
+        assert!(find_u32_sorter("bogo").is_none());
+    }
+}
@@ -1,52 +1,60 @@
 use super::SortOrder;
+use super::counter::Counter;
 use std::cmp::Ordering;
 
 pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
     // do_sortを呼ぶ代わりに、sort_by を呼ぶようにする
-    // is_power_of_twoはsort_byが呼ぶので、sort関数から削除
         match *order {
             SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
             SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a))
         }
 }
 
+// 要素数が2のべき乗でなくてもソートできるので、Errを返すことはない
 pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
     where F: Fn(&T, &T) -> Ordering 
 {
-    if x.len().is_power_of_two() {
-        do_sort(x, true, comparator);
-        Ok(())
-    } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
-    }
+    sort_by_counted(x, comparator, &())
+}
+
+// sort_byと同じだが、比較と交換の回数をcounterで数える
+pub fn sort_by_counted<T, F, C>(x: &mut [T], comparator: &F, counter: &C) -> Result<(), String>
+    where F: Fn(&T, &T) -> Ordering, C: Counter
+{
+    do_sort(x, 0, true, comparator, counter);
+    Ok(())
 }
 
-fn do_sort<T, F>(x: &mut [T], forward: bool, comparator: &F) 
-    where F: Fn(&T, &T) -> Ordering
+// offsetはxの先頭がソート対象全体のどの位置にあたるかを表す。比較交換の記録に使う
+fn do_sort<T, F, C>(x: &mut [T], offset: usize, forward: bool, comparator: &F, counter: &C)
+    where F: Fn(&T, &T) -> Ordering, C: Counter
 {
     if x.len() > 1 {
+        // 要素数が奇数のときは後半の方が1つ多くなる
         let mid_point = x.len() / 2;
-        // 第2引数がtrueのとき、comparatorで示される順序でソート
-        do_sort(&mut x[..mid_point], true, comparator);
-        // 第2引数がfalseのとき、comparatorと逆順で順序でソート
-        do_sort(&mut x[mid_point..], false, comparator);
-        sub_sort(x, forward, comparator);
+        // 前半はforwardと逆の順序でソート
+        // 要素数が2のべき乗でない場合でも、こうすると前半と後半を合わせてバイトニック列にできる
+        do_sort(&mut x[..mid_point], offset, !forward, comparator, counter);
+        // 後半はforwardで示される順序でソート
+        do_sort(&mut x[mid_point..], offset + mid_point, forward, comparator, counter);
+        sub_sort(x, offset, forward, comparator, counter);
     }
 }
 
-fn sub_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
-    where F: Fn(&T, &T) -> Ordering
+fn sub_sort<T, F, C>(x: &mut [T], offset: usize, forward: bool, comparator: &F, counter: &C)
+    where F: Fn(&T, &T) -> Ordering, C: Counter
 {
     if x.len() > 1 {
-        compare_and_swap(x, forward, comparator);
-        let mid_point = x.len() / 2;
-        sub_sort(&mut x[..mid_point], forward, comparator);
-        sub_sort(&mut x[mid_point..], forward, comparator);
+        compare_and_swap(x, offset, forward, comparator, counter);
+        // x.len()未満で最大の2のべき乗を境に分割する（2のべき乗なら半分の位置）
+        let mid_point = x.len().next_power_of_two() / 2;
+        sub_sort(&mut x[..mid_point], offset, forward, comparator, counter);
+        sub_sort(&mut x[mid_point..], offset + mid_point, forward, comparator, counter);
     }
 }
 
-fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F) 
-    where F: Fn(&T, &T) -> Ordering
+fn compare_and_swap<T, F, C>(x: &mut [T], offset: usize, forward: bool, comparator: &F, counter: &C)
+    where F: Fn(&T, &T) -> Ordering, C: Counter
 {
     // 比較前にforwardをOrdering値に変換しておく
     let swap_condition = if forward {
@@ -55,13 +63,19 @@ fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
         Ordering::Less
     };
 
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
+    let mid_point = x.len().next_power_of_two() / 2;
+    counter.count_comparisons((x.len() - mid_point) as u64);
+    // 比較交換のあと、comparatorの順序で前に来る要素の位置をlo、後ろに来る要素の位置をhiとする
+    let (lo, hi) = if forward { (0, mid_point) } else { (mid_point, 0) };
+    // 比較相手（mid_point + i）が存在する範囲だけを比較する
+    for i in 0..(x.len() - mid_point) {
         // closureで2要素を比較し、返されたOrderingのバリアント(値)が
         // swap_conditionと等しいなら要素を交換する
+        counter.record_compare_exchange(offset + lo + i, offset + hi + i);
         if comparator(&x[i], &x[mid_point + i]) == swap_condition {
             // 要素を交換する
             x.swap(i, mid_point + i);
+            counter.count_swaps(1);
         }
     }
 }
@@ -161,11 +175,35 @@ mod tests {
     }
 
     #[test]
-    fn sort_to_fail() {
-        // x.len()が2のべき乗になっていない
+    fn sort_not_power_of_two() {
+        // x.len()が2のべき乗になっていなくてもソートできる
         let mut x = vec![10, 30, 11];
-        // 戻り値はエラー
-        assert!(sort(&mut x, &Ascending).is_err());
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![10, 11, 30]);
+
+        let mut x = vec![10, 30, 11, 20, 4, 330];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 30, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_every_length() {
+        // 要素数0から3,000までのすべての長さで、標準ライブラリのソート結果と比較する
+        for len in 0..=3000 {
+            // 値の範囲を狭めて、重複する要素も含まれるようにする
+            let data: Vec<u32> = new_u32_vec(len).into_iter().map(|n| n % 1000).collect();
+
+            let mut expected = data.clone();
+            expected.sort();
+            let mut x = data.clone();
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+
+            expected.reverse();
+            let mut x = data;
+            assert_eq!(sort(&mut x, &Descending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+        }
     }
 
     #[test]
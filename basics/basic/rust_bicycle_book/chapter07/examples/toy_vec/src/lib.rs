@@ -1,4 +1,7 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 pub struct ToyVec<T> {
     elements: Box<[T]>,  // T型の要素を格納する領域。各要素はヒープ領域に置かれる
@@ -155,6 +158,92 @@ impl<T: fmt::Debug> fmt::Debug for ToyVec<T> {
     }
 }
 
+// PartialEqの比較が反射律を満たす（T: Eq）なら、ToyVec<T>もEqとして扱える
+impl<T: Eq> Eq for ToyVec<T> {}
+
+// スライス[T]やVec<T>とも直接比較できるようにする
+impl<T: PartialEq> PartialEq<[T]> for ToyVec<T> {
+    fn eq(&self, other: &[T]) -> bool {
+        self.elements[..self.len] == *other
+    }
+}
+
+impl<T: PartialEq> PartialEq<Vec<T>> for ToyVec<T> {
+    fn eq(&self, other: &Vec<T>) -> bool {
+        self.elements[..self.len] == other[..]
+    }
+}
+
+// Vec<T>と同じく辞書式順序で比較する。キャパシティの違いは結果に影響しない
+impl<T: PartialOrd> PartialOrd for ToyVec<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.elements[..self.len].partial_cmp(&other.elements[..other.len])
+    }
+}
+
+impl<T: Ord> Ord for ToyVec<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.elements[..self.len].cmp(&other.elements[..other.len])
+    }
+}
+
+// Eqと整合させるため、有効な要素（len個）だけをハッシュする
+// スライスと同じハッシュ値になるので、HashMapのキーにしても[T]で検索できる
+impl<T: Hash> Hash for ToyVec<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.elements[..self.len].hash(state)
+    }
+}
+
+impl<T> AsRef<[T]> for ToyVec<T> {
+    fn as_ref(&self) -> &[T] {
+        &self.elements[..self.len]
+    }
+}
+
+// Hash・Eq・Ordがスライスと一致しているので、Borrowを実装してよい
+impl<T> Borrow<[T]> for ToyVec<T> {
+    fn borrow(&self) -> &[T] {
+        &self.elements[..self.len]
+    }
+}
+
+// Vec<T>の領域をそのままelementsとして引き継ぐ。キャパシティは要素数と同じになる
+impl<T> From<Vec<T>> for ToyVec<T> {
+    fn from(vec: Vec<T>) -> Self {
+        let len = vec.len();
+        Self {
+            elements: vec.into_boxed_slice(),
+            len,
+        }
+    }
+}
+
+// Fromを実装するとInto<Vec<T>>も自動的に実装される
+impl<T> From<ToyVec<T>> for Vec<T> {
+    fn from(toy_vec: ToyVec<T>) -> Self {
+        let mut vec = toy_vec.elements.into_vec();
+        vec.truncate(toy_vec.len);  // len以降の領域にはデフォルト値が入っているので取り除く
+        vec
+    }
+}
+
+// serdeフィーチャーが有効なときだけ、シーケンスとしてシリアライズ／デシリアライズできるようにする
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for ToyVec<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.elements[..self.len])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for ToyVec<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // いったんVec<T>として読み込んでからToyVec<T>に変換する
+        Vec::deserialize(deserializer).map(Self::from)
+    }
+}
+
 // IntoIteratorトレイトを実装するとfor式での繰り返しができるようになる
 impl<'vec, T: Default> IntoIterator for &'vec ToyVec<T> {
     type Item = &'vec T;            // イテレータがイテレートする値の型
@@ -484,4 +573,80 @@ mod tests {
         assert_eq!(sum, [1, 1, 2, 3, 5].iter().sum());
     }
 
+    #[test]
+    fn test_eq_and_ord() {
+        let mut v1 = ToyVec::new();
+        v1.push(1);
+        v1.push(2);
+        let mut v2 = ToyVec::with_capacity(8);  // キャパシティが違っても比較には影響しない
+        v2.push(1);
+        v2.push(2);
+        assert_eq!(v1, v2);
+        assert_eq!(v1, vec![1, 2]);
+        assert_eq!(v1, [1, 2][..]);
+
+        v2.push(0);
+        assert!(v1 < v2);  // 短い方が前（Vecと同じ辞書式順序）
+        v1.pop();
+        v1.push(3);
+        assert!(v1 > v2);
+
+        let mut sorted = vec![v1.clone(), v2.clone(), ToyVec::new()];
+        sorted.sort();
+        assert_eq!(sorted, vec![ToyVec::new(), v2, v1]);
+    }
+
+    #[test]
+    fn test_hash() {
+        use std::collections::HashMap;
+
+        let mut key = ToyVec::with_capacity(4);
+        key.push("alfalfa");
+        key.push("broccoli");
+
+        let mut map = HashMap::new();
+        map.insert(key.clone(), 1);
+        assert_eq!(map.get(&ToyVec::from(vec!["alfalfa", "broccoli"])), Some(&1));
+        assert_eq!(map.get(&["alfalfa", "broccoli"][..]), Some(&1));
+
+        key.pop();
+        assert_eq!(map.get(&key), None);
+    }
+
+    #[test]
+    fn test_vec_conversion() {
+        let mut v: ToyVec<_> = vec!['a', 'b', 'c'].into();
+        assert_eq!(v.len(), 3);
+        assert_eq!(v.capacity(), 3);
+        assert_eq!(v.as_ref(), &['a', 'b', 'c']);
+
+        v.push('d');
+        v.pop();
+        assert_eq!(v.capacity(), 6);
+
+        // len以降の未使用領域は含まれない
+        let vec: Vec<char> = v.into();
+        assert_eq!(vec, vec!['a', 'b', 'c']);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde_test::{assert_tokens, Token};
+
+        let mut v = ToyVec::with_capacity(4);
+        v.push(1);
+        v.push(2);
+        v.push(3);
+
+        // キャパシティに関係なく、len個の要素を持つシーケンスとして扱われる
+        assert_tokens(&v, &[
+            Token::Seq { len: Some(3) },
+            Token::I32(1),
+            Token::I32(2),
+            Token::I32(3),
+            Token::SeqEnd,
+        ]);
+    }
+
 }
\ No newline at end of file
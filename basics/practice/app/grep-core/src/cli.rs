@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+
+// 大文字と小文字の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    // クエリに大文字が含まれていなければ、大文字と小文字を区別しない
+    Smart,
+}
+
+impl CaseMode {
+    pub fn is_sensitive(self, query: &str) -> bool {
+        match self {
+            CaseMode::Sensitive => true,
+            CaseMode::Insensitive => false,
+            CaseMode::Smart => query.chars().any(char::is_uppercase),
+        }
+    }
+}
+
+// 色を付けるかどうか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMode {
+    // 標準出力が端末で、NO_COLORが設定されていなければ付ける
+    Auto,
+    Always,
+    Never,
+}
+
+// minigrepとminigrep2に共通するオプション
+// 各プログラムの引数の定義に`#[command(flatten)]`で埋め込み、クエリやパスとそのプログラムだけのオプションは向こうで足す
+// 指定されなかったオプションは、設定ファイルや環境変数で決めるので、Optionか対になるフラグで持つ
+#[derive(Debug, Args)]
+pub struct SearchArgs {
+    /// Match case exactly (overrides CASE_INSENSITIVE)
+    #[arg(short = 's', long, overrides_with_all = ["ignore_case", "smart_case"])]
+    pub case_sensitive: bool,
+
+    /// Ignore case
+    #[arg(short = 'i', long, overrides_with_all = ["case_sensitive", "smart_case"])]
+    pub ignore_case: bool,
+
+    /// Ignore case unless the query contains an uppercase letter
+    #[arg(short = 'S', long, overrides_with_all = ["case_sensitive", "ignore_case"])]
+    pub smart_case: bool,
+
+    /// Treat the query as a regular expression
+    #[arg(short = 'E', long, overrides_with = "fixed_strings")]
+    pub regex: bool,
+
+    /// Treat the query as a literal string
+    #[arg(short = 'F', long, overrides_with = "regex")]
+    pub fixed_strings: bool,
+
+    /// Select non-matching lines
+    #[arg(short = 'v', long)]
+    pub invert_match: bool,
+
+    /// Prefix each line with its line number
+    #[arg(short = 'n', long, overrides_with = "no_line_number")]
+    pub line_number: bool,
+
+    /// Do not print line numbers
+    #[arg(short = 'N', long, overrides_with = "line_number")]
+    pub no_line_number: bool,
+
+    /// Print only the number of selected lines
+    #[arg(short = 'c', long)]
+    pub count: bool,
+
+    /// Print only the matched parts of each line
+    #[arg(short = 'o', long)]
+    pub only_matching: bool,
+
+    /// Lines to print after each match
+    #[arg(short = 'A', long, value_name = "NUM")]
+    pub after_context: Option<usize>,
+
+    /// Lines to print before each match
+    #[arg(short = 'B', long, value_name = "NUM")]
+    pub before_context: Option<usize>,
+
+    /// Lines to print before and after each match
+    #[arg(short = 'C', long, value_name = "NUM")]
+    pub context: Option<usize>,
+
+    /// When to highlight matches
+    #[arg(long, value_enum, value_name = "WHEN")]
+    pub color: Option<ColorMode>,
+
+    /// Read defaults from this file instead of the usual config file
+    #[arg(long, value_name = "PATH", conflicts_with = "no_config")]
+    pub config: Option<PathBuf>,
+
+    /// Do not read any config file
+    #[arg(long)]
+    pub no_config: bool,
+}
+
+impl SearchArgs {
+    pub fn case(&self) -> Option<CaseMode> {
+        if self.case_sensitive {
+            Some(CaseMode::Sensitive)
+        } else if self.ignore_case {
+            Some(CaseMode::Insensitive)
+        } else if self.smart_case {
+            Some(CaseMode::Smart)
+        } else {
+            None
+        }
+    }
+}
+
+// 対になるフラグのどちらかが指定されていれば、その値
+pub fn flag(yes: bool, no: bool) -> Option<bool> {
+    match (yes, no) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
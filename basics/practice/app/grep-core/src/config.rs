@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::io::{self, IsTerminal};
+
+use serde::de::DeserializeOwned;
+
+use crate::cli::{self, CaseMode, ColorMode, SearchArgs};
+use crate::config_file::{self, FileConfig};
+use crate::error::ConfigError;
+use crate::matcher::{new_matcher, Matcher};
+use crate::output::OutputOptions;
+use crate::search::SearchOptions;
+
+// 何をどう探して、どう表示するか。SearchArgsと環境変数、設定ファイルから決める
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchConfig {
+    pub query: String,
+    pub case_sensitive: bool,
+    // queryを正規表現として扱う（-E）
+    pub regex: bool,
+    // 一致しなかった行を表示する（-v）
+    pub invert: bool,
+    // 行番号を付ける（-n）
+    pub line_numbers: bool,
+    // 行ではなく、一致した行の数を表示する（-c）
+    pub count: bool,
+    // 一致した部分だけを表示する（-o）
+    pub only_matching: bool,
+    // 一致した行の前と後に表示する行数（-B、-A、-Cは両方）
+    pub before_context: usize,
+    pub after_context: usize,
+    // 一致した部分に色を付ける（--color）
+    pub color: bool,
+}
+
+impl SearchConfig {
+    // nameのプログラムの設定ファイルを読んで、設定を決める
+    // 戻り値の2つ目は、設定ファイルに書かれた、そのプログラムだけのキーの値
+    // 環境変数はenvで読むので、テストでは実際の環境を変えずに済む
+    //
+    // それぞれの値は、コマンドライン引数、環境変数、設定ファイル、既定値の順に優先する
+    pub fn load<X, E>(name: &str, query: String, args: &SearchArgs, env: &E) -> Result<(SearchConfig, X), ConfigError>
+    where
+        X: DeserializeOwned + Default,
+        E: Fn(&str) -> Option<String>,
+    {
+        let (file, extra) = if args.no_config {
+            (FileConfig::default(), X::default())
+        } else {
+            match config_file::locate(name, args.config.as_deref(), env) {
+                Some((path, true)) => FileConfig::load(&path)?,
+                Some((path, false)) if path.is_file() => FileConfig::load(&path)?,
+                _ => (FileConfig::default(), X::default()),
+            }
+        };
+        Ok((SearchConfig::resolve(query, args, &file, env), extra))
+    }
+
+    fn resolve<E>(query: String, args: &SearchArgs, file: &FileConfig, env: &E) -> SearchConfig
+    where
+        E: Fn(&str) -> Option<String>,
+    {
+        // `CASE_INSENSITIVE`は値ではなく、設定されているかどうかだけを見る
+        // -s、-i、-Sを指定すれば、環境変数に関係なくそれに従う
+        let env_case = env("CASE_INSENSITIVE").map(|_| CaseMode::Insensitive);
+        let case = args.case().or(env_case).or(file.case).unwrap_or(CaseMode::Sensitive);
+        let case_sensitive = case.is_sensitive(&query);
+
+        // -Aと-Bは-Cより優先する
+        let context = args.context.or(file.context);
+        let before_context = args.before_context.or(file.before_context).or(context).unwrap_or(0);
+        let after_context = args.after_context.or(file.after_context).or(context).unwrap_or(0);
+
+        // autoのときは、端末に出力していて、NO_COLORが設定されていなければ色を付ける
+        let color = match args.color.or(file.color).unwrap_or(ColorMode::Auto) {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => env("NO_COLOR").is_none() && io::stdout().is_terminal(),
+        };
+
+        SearchConfig {
+            regex: cli::flag(args.regex, args.fixed_strings).or(file.regex).unwrap_or(false),
+            line_numbers: cli::flag(args.line_number, args.no_line_number).or(file.line_number).unwrap_or(false),
+            invert: args.invert_match,
+            count: args.count,
+            only_matching: args.only_matching,
+            query,
+            case_sensitive,
+            before_context,
+            after_context,
+            color,
+        }
+    }
+
+    // 固定文字列か正規表現か、大文字小文字を区別するかどうかに合わせてMatcherを選ぶ
+    // 正規表現が不正ならエラーを返す
+    pub fn matcher(&self) -> Result<Box<dyn Matcher>, Box<dyn Error>> {
+        new_matcher(&self.query, self.regex, self.case_sensitive)
+    }
+
+    pub fn search_options(&self) -> SearchOptions {
+        SearchOptions {
+            invert: self.invert,
+            before: self.before_context,
+            after: self.after_context,
+        }
+    }
+
+    pub fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            line_numbers: self.line_numbers,
+            only_matching: self.only_matching,
+            count: self.count,
+            color: self.color,
+            group_separator: self.before_context > 0 || self.after_context > 0,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::cli::{CaseMode, ColorMode};
+use crate::error::ConfigError;
+
+// 設定ファイル（TOML）に書ける、minigrepとminigrep2に共通する既定値。どれも省略できる
+//
+//     case = "smart"
+//     color = "never"
+//     line_number = true
+//     context = 2
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub case: Option<CaseMode>,
+    pub color: Option<ColorMode>,
+    pub regex: Option<bool>,
+    pub line_number: Option<bool>,
+    pub context: Option<usize>,
+    pub before_context: Option<usize>,
+    pub after_context: Option<usize>,
+}
+
+// FileConfigのキー。これ以外のキーは、各プログラムの設定として読む
+const KEYS: [&str; 7] = ["case", "color", "regex", "line_number", "context", "before_context", "after_context"];
+
+// そのプログラムだけのキーがないときに使う。どんなキーも受け付けない
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoExtraKeys {}
+
+impl FileConfig {
+    // 共通のキーをFileConfigに、残りのキーをXに読む
+    // どちらも知らないキーはXが受け付けずに、エラーになる
+    pub fn load<X: DeserializeOwned>(path: &Path) -> Result<(FileConfig, X), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::ReadConfig {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let invalid = |source| ConfigError::ParseConfig {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut extra: toml::Table = toml::from_str(&text).map_err(invalid)?;
+        let shared: toml::Table = KEYS.iter().filter_map(|key| extra.remove(*key).map(|value| (key.to_string(), value))).collect();
+        let shared = toml::Value::Table(shared).try_into().map_err(invalid)?;
+        let extra = toml::Value::Table(extra).try_into().map_err(invalid)?;
+        Ok((shared, extra))
+    }
+}
+
+// 読む設定ファイルを決める。nameはプログラム名
+// `--config`か`NAME_CONFIG`（minigrepなら`MINIGREP_CONFIG`）で指定したファイルは、なければエラーにする
+// ホームディレクトリの`.name.toml`は、あるときだけ読む
+pub fn locate<E>(name: &str, explicit: Option<&Path>, env: &E) -> Option<(PathBuf, bool)>
+where
+    E: Fn(&str) -> Option<String>,
+{
+    if let Some(path) = explicit {
+        return Some((path.to_path_buf(), true));
+    }
+    if let Some(path) = env(&format!("{}_CONFIG", name.to_uppercase())) {
+        return Some((PathBuf::from(path), true));
+    }
+    env("HOME").map(|home| (Path::new(&home).join(format!(".{}.toml", name)), false))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    struct Extra {
+        hidden: Option<bool>,
+    }
+
+    #[test]
+    fn locates_files_by_program_name() {
+        let env = |name: &str| match name {
+            "HOME" => Some(String::from("/home/ferris")),
+            "MINIGREP2_CONFIG" => Some(String::from("/etc/minigrep2.toml")),
+            _ => None,
+        };
+        assert_eq!(locate("minigrep", None, &env), Some((PathBuf::from("/home/ferris/.minigrep.toml"), false)));
+        assert_eq!(locate("minigrep2", None, &env), Some((PathBuf::from("/etc/minigrep2.toml"), true)));
+        assert_eq!(locate("minigrep", Some(Path::new("a.toml")), &env), Some((PathBuf::from("a.toml"), true)));
+    }
+
+    #[test]
+    fn splits_shared_and_program_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "color = \"never\"\nhidden = true\ncontext = 1\n").unwrap();
+        let (shared, extra) = FileConfig::load::<Extra>(&path).unwrap();
+        assert_eq!(shared, FileConfig { color: Some(ColorMode::Never), context: Some(1), ..FileConfig::default() });
+        assert_eq!(extra, Extra { hidden: Some(true) });
+
+        // そのプログラムが知らないキーはエラーにする
+        match FileConfig::load::<NoExtraKeys>(&path) {
+            Err(e @ ConfigError::ParseConfig { .. }) => assert!(e.to_string().contains("hidden"), "{}", e),
+            _ => panic!("unknown keys should be rejected"),
+        }
+        fs::write(&path, "context = \"two\"\n").unwrap();
+        assert!(FileConfig::load::<Extra>(&path).is_err());
+    }
+}
@@ -0,0 +1,19 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+// 設定を組み立てるときのエラー
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    // 引数の誤りと、--helpや--versionの表示
+    // `clap::Error::exit`で、それぞれに合った出力先と終了コードで終わらせる
+    #[error(transparent)]
+    Args(#[from] clap::Error),
+
+    #[error("could not read config file {}: {source}", path.display())]
+    ReadConfig { path: PathBuf, source: io::Error },
+
+    #[error("invalid config file {}: {source}", path.display())]
+    ParseConfig { path: PathBuf, source: toml::de::Error },
+}
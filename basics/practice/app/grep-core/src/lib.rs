@@ -0,0 +1,94 @@
+// minigrep、minigrep2、grep-rsで共有する検索の部品
+// 1行ずつ調べて、選んだ行とその前後の行を決め、表示する形に整える
+use std::io::{self, Read, Write};
+
+use stream_search::LineReader;
+
+// 共通のコマンドライン引数（cliフィーチャー）
+#[cfg(feature = "cli")]
+pub mod cli;
+// 引数、環境変数、設定ファイルから決める検索の設定（cliフィーチャー）
+#[cfg(feature = "cli")]
+pub mod config;
+// 既定値を書いておく設定ファイル（cliフィーチャー）
+#[cfg(feature = "cli")]
+pub mod config_file;
+// 設定を組み立てるときのエラー（cliフィーチャー）
+#[cfg(feature = "cli")]
+pub mod error;
+// 固定文字列と正規表現の検索
+pub mod matcher;
+// 表示する行の整形
+pub mod output;
+// 行ごとの検索と、前後の行の選び方
+pub mod search;
+
+#[cfg(feature = "cli")]
+pub use cli::{CaseMode, ColorMode, SearchArgs};
+#[cfg(feature = "cli")]
+pub use config::SearchConfig;
+#[cfg(feature = "cli")]
+pub use config_file::{FileConfig, NoExtraKeys};
+#[cfg(feature = "cli")]
+pub use error::ConfigError;
+pub use matcher::{new_matcher, LiteralMatcher, Matcher, RegexMatcher};
+pub use output::{format_count, format_file_lines, format_lines, Formatter, OutputOptions};
+pub use search::{count_matches, search_lines, Line, LineKind, LineSearcher, SearchOptions};
+
+// linesを最後まで検索し、表示する行をoutに書く。戻り値は条件に合った行の数
+// pathがあれば、各行の前にパスを付ける
+// UTF-8として正しくないバイトは置換文字(U+FFFD)にして、検索を止めないようにする
+pub fn write_matches<R: Read, W: Write>(
+    lines: &mut LineReader<R>,
+    path: Option<&str>,
+    matcher: &dyn Matcher,
+    search: &SearchOptions,
+    output: &OutputOptions,
+    out: &mut W,
+) -> io::Result<usize> {
+    let mut searcher = LineSearcher::new(matcher, search);
+    let mut formatter = Formatter::new(path, output);
+    while let Some((number, bytes)) = lines.next_line()? {
+        let text = String::from_utf8_lossy(bytes);
+        searcher.feed(number as usize, &text, |line| -> io::Result<()> {
+            if output.count {
+                return Ok(());
+            }
+            for output in formatter.format(&line) {
+                writeln!(out, "{}", output)?;
+            }
+            Ok(())
+        })?;
+    }
+    if output.count {
+        writeln!(out, "{}", format_count(path, searcher.matched(), output))?;
+    }
+    Ok(searcher.matched())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(input: &[u8], path: Option<&str>, search: &SearchOptions, output: &OutputOptions) -> (usize, String) {
+        let matcher = LiteralMatcher::new("cat");
+        let mut out = Vec::new();
+        let matched = write_matches(&mut LineReader::new(input), path, &matcher, search, output, &mut out).unwrap();
+        (matched, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn writes_lines_or_counts() {
+        let input = b"a cat\r\ndog\ncaf\xe9 cat\n";
+        let output = OutputOptions { line_numbers: true, ..OutputOptions::default() };
+        assert_eq!(
+            write(input, Some("pets.txt"), &SearchOptions::default(), &output),
+            (2, String::from("pets.txt:1:a cat\npets.txt:3:caf\u{fffd} cat\n"))
+        );
+
+        let output = OutputOptions { count: true, ..OutputOptions::default() };
+        let search = SearchOptions { invert: true, ..SearchOptions::default() };
+        assert_eq!(write(input, None, &search, &output), (1, String::from("1\n")));
+    }
+}
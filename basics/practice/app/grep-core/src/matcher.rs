@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+// 行の中からクエリに一致する場所を探すもの
+// 固定文字列と正規表現のどちらで探すかを、検索する側が気にしなくて済むようにトレイトにする
+pub trait Matcher {
+    // lineのstartバイト目以降で、最初に一致した範囲を返す
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>>;
+
+    fn is_match(&self, line: &str) -> bool {
+        self.find_at(line, 0).is_some()
+    }
+
+    // 重ならないように、一致した範囲をすべて返す
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        while start <= line.len() {
+            let range = match self.find_at(line, start) {
+                Some(range) => range,
+                None => break,
+            };
+            // 空の一致が続いて止まらなくならないように、次の文字から探す
+            start = if range.is_empty() {
+                range.end + line[range.end..].chars().next().map_or(1, char::len_utf8)
+            } else {
+                range.end
+            };
+            ranges.push(range);
+        }
+        ranges
+    }
+}
+
+// 大文字と小文字を区別して、固定文字列を探す
+pub struct LiteralMatcher {
+    query: String,
+}
+
+impl LiteralMatcher {
+    pub fn new(query: &str) -> LiteralMatcher {
+        LiteralMatcher { query: query.to_string() }
+    }
+}
+
+impl Matcher for LiteralMatcher {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        // 空のクエリはすべての行に一致する
+        line[start..].find(&self.query).map(|i| start + i..start + i + self.query.len())
+    }
+}
+
+// 正規表現で探す
+pub struct RegexMatcher {
+    regex: Regex,
+}
+
+impl RegexMatcher {
+    pub fn new(pattern: &str, case_sensitive: bool) -> Result<RegexMatcher, regex::Error> {
+        let regex = RegexBuilder::new(pattern).case_insensitive(!case_sensitive).build()?;
+        Ok(RegexMatcher { regex })
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        self.regex.find_at(line, start).map(|m| m.range())
+    }
+}
+
+// 設定に合ったMatcherを作る
+// 大文字と小文字を区別しない固定文字列は、小文字にすると長さが変わる文字があって元の行での位置がずれるので、
+// エスケープした正規表現で探す
+pub fn new_matcher(query: &str, regex: bool, case_sensitive: bool) -> Result<Box<dyn Matcher>, Box<dyn Error>> {
+    if regex {
+        Ok(Box::new(RegexMatcher::new(query, case_sensitive)?))
+    } else if case_sensitive {
+        Ok(Box::new(LiteralMatcher::new(query)))
+    } else {
+        Ok(Box::new(RegexMatcher::new(&regex::escape(query), false)?))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn literal_finds_every_occurrence() {
+        let matcher = LiteralMatcher::new("ab");
+        assert_eq!(matcher.find_all("abcab ab"), vec![0..2, 3..5, 6..8]);
+        assert!(!matcher.is_match("a b"));
+    }
+
+    #[test]
+    fn case_insensitive_literal_keeps_original_positions() {
+        let matcher = new_matcher("straße.", false, false).unwrap();
+        // "."は正規表現としてではなく、文字として探す
+        assert_eq!(matcher.find_all("ÄÖ STRASSE. Straße. straßex"), vec![14..22]);
+    }
+
+    #[test]
+    fn regex_and_empty_matches() {
+        let matcher = new_matcher(r"\d+", true, true).unwrap();
+        assert_eq!(matcher.find_all("a1b22c333"), vec![1..2, 3..5, 6..9]);
+
+        let matcher = new_matcher("x*", true, true).unwrap();
+        assert_eq!(matcher.find_all("aé"), vec![0..0, 1..1, 3..3]);
+
+        assert!(new_matcher("(", true, true).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::search::{Line, LineKind};
+
+// 端末に色を付けるためのエスケープシーケンス
+const MATCH_COLOR: &str = "\x1b[1;31m";
+const NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const PATH_COLOR: &str = "\x1b[35m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputOptions {
+    // 行番号を付ける（-n）
+    pub line_numbers: bool,
+    // 一致した部分だけを1行ずつ表示する（-o）
+    pub only_matching: bool,
+    // 行ではなく、条件に合った行の数だけを表示する（-c）
+    pub count: bool,
+    // 一致した部分に色を付ける
+    pub color: bool,
+    // 前後の行を表示しているとき、続いていない行の間に"--"を入れる
+    pub group_separator: bool,
+}
+
+// 検索結果を表示する行にする
+pub fn format_lines(lines: &[Line], options: &OutputOptions) -> Vec<String> {
+    format_file_lines(None, lines, options)
+}
+
+// 複数のファイルを検索しているときは、各行の前にファイルのパスを付ける
+pub fn format_file_lines(path: Option<&str>, lines: &[Line], options: &OutputOptions) -> Vec<String> {
+    let mut formatter = Formatter::new(path, options);
+    lines.iter().flat_map(|line| formatter.format(line)).collect()
+}
+
+// 検索結果を1行ずつ表示する行にするもの
+// 続いていない行の間に"--"を入れるために、前の行の番号を覚えておく
+pub struct Formatter<'o> {
+    path: Option<&'o str>,
+    options: &'o OutputOptions,
+    last: Option<usize>,
+}
+
+impl<'o> Formatter<'o> {
+    pub fn new(path: Option<&'o str>, options: &'o OutputOptions) -> Formatter<'o> {
+        Formatter { path, options, last: None }
+    }
+
+    // 1行の結果から、表示する行を作る。-oでは一致した数だけ、前後の行では1つもないこともある
+    pub fn format(&mut self, line: &Line) -> Vec<String> {
+        let (path, options) = (self.path, self.options);
+        let mut output = Vec::new();
+        if options.group_separator && self.last.is_some_and(|last| line.number > last + 1) {
+            output.push(paint("--", SEPARATOR_COLOR, options.color));
+        }
+        self.last = Some(line.number);
+
+        if options.only_matching {
+            // 前後の行には一致した部分がないので、何も表示しない
+            for range in &line.matches {
+                let text = paint(&line.text[range.clone()], MATCH_COLOR, options.color);
+                output.push(prefix(path, line, options) + &text);
+            }
+        } else {
+            output.push(prefix(path, line, options) + &highlight(line, options.color));
+        }
+        output
+    }
+}
+
+// パスと行番号の後ろには、条件に合った行なら":"、前後の行なら"-"を付ける
+// 例えば"src/lib.rs:12:"や"src/lib.rs-13-"になる
+fn prefix(path: Option<&str>, line: &Line, options: &OutputOptions) -> String {
+    let separator = match line.kind {
+        LineKind::Match => ':',
+        LineKind::Context => '-',
+    };
+    let mut prefix = String::new();
+    if let Some(path) = path {
+        prefix.push_str(&paint(path, PATH_COLOR, options.color));
+        prefix.push(separator);
+    }
+    if options.line_numbers {
+        prefix.push_str(&paint(&line.number.to_string(), NUMBER_COLOR, options.color));
+        prefix.push(separator);
+    }
+    prefix
+}
+
+// -cのときの1ファイル分の行
+pub fn format_count(path: Option<&str>, count: usize, options: &OutputOptions) -> String {
+    match path {
+        Some(path) => format!("{}:{}", paint(path, PATH_COLOR, options.color), count),
+        None => count.to_string(),
+    }
+}
+
+// 一致した範囲に色を付ける
+fn highlight(line: &Line, color: bool) -> String {
+    if !color || line.matches.is_empty() {
+        return line.text.to_string();
+    }
+    let mut highlighted = String::new();
+    let mut end = 0;
+    for range in &line.matches {
+        highlighted.push_str(&line.text[end..range.start]);
+        highlighted.push_str(&paint(&line.text[range.clone()], MATCH_COLOR, true));
+        end = range.end;
+    }
+    highlighted.push_str(&line.text[end..]);
+    highlighted
+}
+
+fn paint(text: &str, color: &str, enabled: bool) -> String {
+    if enabled && !text.is_empty() {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+
+#[cfg(test)]
+// 一致した範囲は1つでもVecで持つ
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::*;
+
+    fn lines() -> Vec<Line<'static>> {
+        vec![
+            Line { number: 1, text: "a cat", kind: LineKind::Context, matches: vec![] },
+            Line { number: 2, text: "cat and cat", kind: LineKind::Match, matches: vec![0..3, 8..11] },
+            Line { number: 5, text: "the cat", kind: LineKind::Match, matches: vec![4..7] },
+        ]
+    }
+
+    #[test]
+    fn numbers_and_separators() {
+        let options = OutputOptions { line_numbers: true, group_separator: true, ..OutputOptions::default() };
+        assert_eq!(format_lines(&lines(), &options), vec!["1-a cat", "2:cat and cat", "--", "5:the cat"]);
+
+        // 前後の行を表示していなければ"--"は入れない
+        assert_eq!(format_lines(&lines()[1..], &OutputOptions::default()), vec!["cat and cat", "the cat"]);
+    }
+
+    #[test]
+    fn only_matching_parts() {
+        let options = OutputOptions { only_matching: true, line_numbers: true, ..OutputOptions::default() };
+        assert_eq!(format_lines(&lines(), &options), vec!["2:cat", "2:cat", "5:cat"]);
+    }
+
+    #[test]
+    fn path_prefixes() {
+        let options = OutputOptions { line_numbers: true, ..OutputOptions::default() };
+        assert_eq!(
+            format_file_lines(Some("src/cat.txt"), &lines()[..2], &options),
+            vec!["src/cat.txt-1-a cat", "src/cat.txt:2:cat and cat"]
+        );
+        assert_eq!(format_count(Some("src/cat.txt"), 2, &options), "src/cat.txt:2");
+        assert_eq!(format_count(None, 2, &options), "2");
+    }
+
+    #[test]
+    fn highlights_match_spans() {
+        let options = OutputOptions { color: true, ..OutputOptions::default() };
+        assert_eq!(
+            format_lines(&lines()[1..2], &options),
+            vec!["\x1b[1;31mcat\x1b[0m and \x1b[1;31mcat\x1b[0m"]
+        );
+    }
+}
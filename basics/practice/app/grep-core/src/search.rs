@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::ops::Range;
+
+use crate::matcher::Matcher;
+
+// 結果の行が、条件に合った行なのか、その前後に表示するだけの行なのか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Match,
+    Context,
+}
+
+// 検索結果の1行
+// 行番号と一致した範囲を持っているので、表示の仕方を変えても検索の結果はそのままテストできる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line<'a> {
+    // 1から数えた行番号
+    pub number: usize,
+    pub text: &'a str,
+    pub kind: LineKind,
+    // textの中で一致した範囲（バイト単位）。前後の行と、-vで選んだ行では空
+    pub matches: Vec<Range<usize>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    // 一致しなかった行を選ぶ（-v）
+    pub invert: bool,
+    // 選んだ行の前に表示する行数（-B）
+    pub before: usize,
+    // 選んだ行の後に表示する行数（-A）
+    pub after: usize,
+}
+
+// 1行ずつ渡された行をmatcherで調べ、選んだ行とその前後の行を順に渡すもの
+// 覚えておくのは前に表示するかもしれないoptions.before行だけなので、入力全体を持たなくてよい
+pub struct LineSearcher<'m> {
+    matcher: &'m dyn Matcher,
+    options: SearchOptions,
+    // 次に選ぶ行の前に表示するかもしれない行。options.before行だけ覚えておく
+    before: VecDeque<(usize, String)>,
+    // あと何行、後ろの行として表示するか
+    after: usize,
+    // これまでに選んだ行の数
+    matched: usize,
+}
+
+impl<'m> LineSearcher<'m> {
+    pub fn new(matcher: &'m dyn Matcher, options: &SearchOptions) -> LineSearcher<'m> {
+        LineSearcher {
+            matcher,
+            options: options.clone(),
+            before: VecDeque::with_capacity(options.before),
+            after: 0,
+            matched: 0,
+        }
+    }
+
+    // number行目のtextを調べ、表示する行があればemitに順に渡す
+    // emitがエラーを返したら、そこで止めてそのエラーを返す
+    pub fn feed<F, E>(&mut self, number: usize, text: &str, mut emit: F) -> Result<(), E>
+    where
+        F: FnMut(Line) -> Result<(), E>,
+    {
+        let matches = self.matcher.find_all(text);
+        if matches.is_empty() == self.options.invert {
+            for (number, text) in self.before.drain(..) {
+                emit(Line { number, text: &text, kind: LineKind::Context, matches: Vec::new() })?;
+            }
+            let matches = if self.options.invert { Vec::new() } else { matches };
+            self.matched += 1;
+            self.after = self.options.after;
+            emit(Line { number, text, kind: LineKind::Match, matches })
+        } else if self.after > 0 {
+            self.after -= 1;
+            emit(Line { number, text, kind: LineKind::Context, matches: Vec::new() })
+        } else {
+            if self.options.before > 0 {
+                // あふれた一番古い行のStringを使い回す
+                let mut kept = if self.before.len() == self.options.before {
+                    self.before.pop_front().map(|(_, text)| text).unwrap_or_default()
+                } else {
+                    String::new()
+                };
+                kept.clear();
+                kept.push_str(text);
+                self.before.push_back((number, kept));
+            }
+            Ok(())
+        }
+    }
+
+    // これまでに選んだ行の数（-c）
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+}
+
+// contentsの各行をmatcherで調べ、選んだ行とその前後の行を順に返す
+// 戻り値の行はcontentsを参照するので、contentsと同じライフタイムを持つ
+pub fn search_lines<'a>(matcher: &dyn Matcher, contents: &'a str, options: &SearchOptions) -> Vec<Line<'a>> {
+    let texts: Vec<&'a str> = contents.lines().collect();
+    let mut results = Vec::new();
+    let mut searcher = LineSearcher::new(matcher, options);
+    for (i, text) in texts.iter().enumerate() {
+        // 前の行はsearcherの中にコピーされているので、contentsの中の同じ行を指し直す
+        let Ok(()) = searcher.feed(i + 1, text, |line| {
+            results.push(Line { number: line.number, text: texts[line.number - 1], kind: line.kind, matches: line.matches });
+            Ok::<(), Infallible>(())
+        });
+    }
+    results
+}
+
+// 条件に合った行の数（-c）
+pub fn count_matches(lines: &[Line]) -> usize {
+    lines.iter().filter(|line| line.kind == LineKind::Match).count()
+}
+
+
+#[cfg(test)]
+// 一致した範囲は1つでもVecで持つ
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::*;
+    use crate::matcher::LiteralMatcher;
+
+    const CONTENTS: &str = "\
+one
+two match
+three
+four
+five
+six match
+seven match
+eight
+nine";
+
+    fn numbers(lines: &[Line]) -> Vec<(usize, LineKind)> {
+        lines.iter().map(|line| (line.number, line.kind)).collect()
+    }
+
+    #[test]
+    fn reports_line_numbers_and_ranges() {
+        let lines = search_lines(&LiteralMatcher::new("match"), CONTENTS, &SearchOptions::default());
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], Line { number: 2, text: "two match", kind: LineKind::Match, matches: vec![4..9] });
+        assert_eq!(count_matches(&lines), 3);
+    }
+
+    #[test]
+    fn context_lines_do_not_repeat() {
+        let options = SearchOptions { invert: false, before: 1, after: 1 };
+        let lines = search_lines(&LiteralMatcher::new("match"), CONTENTS, &options);
+        use self::LineKind::*;
+        assert_eq!(
+            numbers(&lines),
+            vec![(1, Context), (2, Match), (3, Context), (5, Context), (6, Match), (7, Match), (8, Context)]
+        );
+        assert_eq!(count_matches(&lines), 3);
+    }
+
+    #[test]
+    fn searcher_takes_one_line_at_a_time() {
+        let matcher = LiteralMatcher::new("match");
+        let mut searcher = LineSearcher::new(&matcher, &SearchOptions { invert: false, before: 2, after: 0 });
+        let mut emitted = Vec::new();
+        for (i, text) in CONTENTS.lines().enumerate() {
+            // 渡した行は呼び出しごとに捨てても、前の行として覚えた分は残る
+            let text = text.to_string();
+            searcher
+                .feed(i + 1, &text, |line| {
+                    emitted.push((line.number, line.text.to_string(), line.kind));
+                    Ok::<(), ()>(())
+                })
+                .unwrap();
+        }
+        assert_eq!(searcher.matched(), 3);
+        let numbers: Vec<usize> = emitted.iter().map(|line| line.0).collect();
+        assert_eq!(numbers, vec![1, 2, 4, 5, 6, 7]);
+        assert_eq!(emitted[2], (4, String::from("four"), LineKind::Context));
+
+        // emitのエラーはそのまま返る
+        assert_eq!(searcher.feed(8, "last match", |_| Err("closed")), Err("closed"));
+    }
+
+    #[test]
+    fn invert_selects_other_lines() {
+        let options = SearchOptions { invert: true, ..SearchOptions::default() };
+        let lines = search_lines(&LiteralMatcher::new("e"), CONTENTS, &options);
+        assert_eq!(lines.iter().map(|line| line.text).collect::<Vec<_>>(), vec!["two match", "four", "six match"]);
+        assert!(lines.iter().all(|line| line.matches.is_empty()));
+    }
+}
@@ -0,0 +1,486 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use grep_core::{Formatter, LineSearcher, Matcher, OutputOptions, SearchOptions};
+use stream_search::LineReader;
+
+// 順番が来ていないファイルの出力を、メモリに置いておく大きさ。超えた分は一時ファイルに移す
+const MEMORY_PER_FILE: usize = 64 * 1024;
+// 順番が来ているファイルの出力は、これだけ溜まるか、次の行を待つ前に書く側に渡す
+const BATCH_SIZE: usize = 8 * 1024;
+// 書き終わっていない一番前のファイルから、スレッドの数の何倍先まで検索を始めてよいか
+const AHEAD_PER_THREAD: usize = 4;
+
+// すべてのファイルを検索し終えたときの状態
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    // どれかのファイルで一致した
+    pub matched: bool,
+    // 開けなかったり、読めなかったりしたファイルの数
+    pub errors: usize,
+}
+
+impl Status {
+    // grepと同じ終了コード。エラーがあれば2、一致した行があれば0、なければ1
+    pub fn exit_code(&self) -> i32 {
+        if self.errors > 0 {
+            2
+        } else if self.matched {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+// readerを1行ずつ読み、matcherに一致した行を"name:line:text"の形でoutに書く。一致した行があればtrue
+// ファイル全体は読み込まないので、どれだけ大きなファイルでも使うメモリは変わらない
+// UTF-8として正しくないバイトは置換文字(U+FFFD)にして、検索を止めないようにする
+// バイナリファイルの行は表示せず、一致したら"Binary file name matches"とだけ書く
+//
+// 次の行がまだ届いていないときはoutをflushするので、少しずつ届く標準入力の行もすぐに書ける
+pub fn search_reader<R: Read, W: Write>(matcher: &dyn Matcher, name: &str, reader: R, out: &mut W) -> io::Result<bool> {
+    let mut lines = LineReader::new(reader);
+    let binary = lines.is_binary()?;
+    let options = OutputOptions { line_numbers: true, ..OutputOptions::default() };
+    let mut searcher = LineSearcher::new(matcher, &SearchOptions::default());
+    let mut formatter = Formatter::new(Some(name), &options);
+
+    while let Some((number, bytes)) = lines.next_line()? {
+        let text = String::from_utf8_lossy(bytes);
+        searcher.feed(number as usize, &text, |line| -> io::Result<()> {
+            if !binary {
+                for output in formatter.format(&line) {
+                    writeln!(out, "{}", output)?;
+                }
+            }
+            Ok(())
+        })?;
+        // バイナリファイルは、1つ見つかれば十分
+        if binary && searcher.matched() > 0 {
+            writeln!(out, "Binary file {} matches", name)?;
+            return Ok(true);
+        }
+        if !lines.line_ready() {
+            out.flush()?;
+        }
+    }
+    Ok(searcher.matched() > 0)
+}
+
+// pathのファイルを検索する。"-"なら標準入力を読む
+pub fn search_file<W: Write>(matcher: &dyn Matcher, path: &str, out: &mut W) -> io::Result<bool> {
+    search_reader(matcher, display_name(path), stream_search::open(path)?, out)
+}
+
+fn display_name(path: &str) -> &str {
+    if stream_search::is_stdin(path) {
+        "(standard input)"
+    } else {
+        path
+    }
+}
+
+// pathsのファイルを並列に検索し、結果を引数の順にoutに書く
+// どのファイルの行も"path:line:text"の形で、ファイルごとにまとめて書く
+// 読めなかったファイルはerrに書き、残りのファイルの検索は続ける
+//
+// 順番が来ているファイルの行は、検索し終わるのを待たずにそのまま書く
+// 後のファイルの出力は順番が来るまで取っておくが、1つのファイルにつきMEMORY_PER_FILEを超えた分は一時ファイルに移し、
+// 検索を始めるのも前のファイルから決まった数先までにするので、使うメモリはファイルの大きさと数によらない
+pub fn search_all<M, W, E>(matcher: &M, paths: &[String], out: &mut W, err: &mut E) -> io::Result<Status>
+where
+    M: Matcher + Sync,
+    W: Write,
+    E: Write,
+{
+    let threads = rayon::current_num_threads();
+    let shared = Shared::new(paths.len(), threads * AHEAD_PER_THREAD);
+    thread::scope(|scope| {
+        // 検索はrayonのスレッドで、引数の順にファイルを取って進める
+        scope.spawn(|| {
+            rayon::scope(|s| {
+                for _ in 0..threads.min(paths.len()) {
+                    s.spawn(|_| {
+                        while let Some(index) = shared.claim() {
+                            let mut spool = Spool::new(&shared, index);
+                            let result = search_file(matcher, &paths[index], &mut spool);
+                            spool.finish(result);
+                        }
+                    });
+                }
+            })
+        });
+
+        let result = write_in_order(paths, &shared, out, err);
+        // 書き込みに失敗したら、残りのファイルの検索はやめる
+        if result.is_err() {
+            shared.stop();
+        }
+        result
+    })
+}
+
+// 届いた出力を、引数の順に書く
+fn write_in_order<W: Write, E: Write>(paths: &[String], shared: &Shared, out: &mut W, err: &mut E) -> io::Result<Status> {
+    let mut status = Status::default();
+    let mut unflushed = false;
+    for (index, path) in paths.iter().enumerate() {
+        loop {
+            // まだ届いていなければ、待つ前に書いた分を出しておく
+            let slot = match shared.take(index, !unflushed) {
+                Some(slot) => slot,
+                None => {
+                    out.flush()?;
+                    unflushed = false;
+                    continue;
+                },
+            };
+            if let Some(mut file) = slot.spilled {
+                file.seek(SeekFrom::Start(0))?;
+                io::copy(&mut file, out)?;
+                unflushed = true;
+            }
+            if !slot.buf.is_empty() {
+                out.write_all(&slot.buf)?;
+                unflushed = true;
+            }
+            let result = match slot.done {
+                Some(result) => result,
+                None => continue,
+            };
+            match result {
+                Ok(matched) => status.matched |= matched,
+                Err(e) => {
+                    status.errors += 1;
+                    // 前のファイルの結果より先にエラーが表示されないように、書いた分を出してしまう
+                    out.flush()?;
+                    unflushed = false;
+                    writeln!(err, "grep-rs: {}: {}", display_name(path), e)?;
+                },
+            }
+            shared.advance();
+            break;
+        }
+    }
+    Ok(status)
+}
+
+// 1つのファイルの、まだ書いていない出力
+#[derive(Default)]
+struct Slot {
+    // 順番が来る前に、一時ファイルに移した分。bufより前に書く
+    spilled: Option<File>,
+    buf: Vec<u8>,
+    // 検索し終わっていれば、その結果。一致した行があればtrue
+    done: Option<io::Result<bool>>,
+}
+
+struct State {
+    // 次に書くファイル
+    next: usize,
+    // 次に検索を始めるファイル
+    claimed: usize,
+    // 書き込みに失敗したので、検索をやめる
+    stopped: bool,
+    slots: Vec<Slot>,
+}
+
+// 検索するスレッドと書くスレッドで共有するもの
+// どれかが変わるたびに、changedで待っている側を起こす
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    // next + ahead より前のファイルだけを検索する
+    ahead: usize,
+}
+
+impl Shared {
+    fn new(files: usize, ahead: usize) -> Shared {
+        let slots = (0..files).map(|_| Slot::default()).collect();
+        Shared {
+            state: Mutex::new(State { next: 0, claimed: 0, stopped: false, slots }),
+            changed: Condvar::new(),
+            ahead: ahead.max(1),
+        }
+    }
+
+    // 次に検索するファイルを取る。書く側より先に進みすぎていれば、前のファイルが書き終わるまで待つ
+    // ファイルは引数の順に取るので、次に書くファイルはいつもどれかのスレッドが検索している
+    // 全部取り終わったか、やめることになったらNone
+    fn claim(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped || state.claimed == state.slots.len() {
+                return None;
+            }
+            if state.claimed < state.next + self.ahead {
+                state.claimed += 1;
+                return Some(state.claimed - 1);
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    // index番目のファイルの、届いている出力を受け取る。何も届いていなければ、waitなら届くまで待つ
+    fn take(&self, index: usize, wait: bool) -> Option<Slot> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let slot = &mut state.slots[index];
+            if slot.spilled.is_some() || !slot.buf.is_empty() || slot.done.is_some() {
+                return Some(mem::take(slot));
+            }
+            if !wait {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    // 次のファイルに順番を移す
+    fn advance(&self) {
+        self.state.lock().unwrap().next += 1;
+        self.changed.notify_all();
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.changed.notify_all();
+    }
+}
+
+// 検索するスレッドが、1つのファイルの出力を書くところ
+// 順番が来ていれば書く側に渡し、来ていなければ手元に溜めて、溜まりすぎたら一時ファイルに移す
+struct Spool<'s> {
+    shared: &'s Shared,
+    index: usize,
+    buf: Vec<u8>,
+    spilled: Option<File>,
+}
+
+impl<'s> Spool<'s> {
+    fn new(shared: &'s Shared, index: usize) -> Spool<'s> {
+        Spool { shared, index, buf: Vec::new(), spilled: None }
+    }
+
+    // 順番が来ていれば、溜めた分を書く側に渡してtrueを返す
+    // 書く側がやめていれば、エラーにして検索も止める
+    fn pass(&mut self) -> io::Result<bool> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.stopped {
+            return Err(io::Error::other("output closed"));
+        }
+        if state.next != self.index {
+            return Ok(false);
+        }
+        if self.spilled.is_some() || !self.buf.is_empty() {
+            let slot = &mut state.slots[self.index];
+            slot.spilled = self.spilled.take();
+            slot.buf.append(&mut self.buf);
+            drop(state);
+            self.shared.changed.notify_all();
+        }
+        Ok(true)
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let file = match &mut self.spilled {
+            Some(file) => file,
+            None => self.spilled.insert(tempfile::tempfile()?),
+        };
+        file.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    // 検索し終えたら、残りを結果と一緒に渡す
+    fn finish(mut self, result: io::Result<bool>) {
+        let mut state = self.shared.state.lock().unwrap();
+        let slot = &mut state.slots[self.index];
+        slot.spilled = self.spilled.take();
+        slot.buf.append(&mut self.buf);
+        slot.done = Some(result);
+        drop(state);
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Write for Spool<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= BATCH_SIZE && !self.pass()? && self.buf.len() >= MEMORY_PER_FILE {
+            self.spill()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pass().map(|_| ())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use grep_core::RegexMatcher;
+    use std::fs;
+
+    fn search(pattern: &str, paths: &[String]) -> (Status, String, String) {
+        let matcher = RegexMatcher::new(pattern, true).unwrap();
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let status = search_all(&matcher, paths, &mut out, &mut err).unwrap();
+        (status, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    #[test]
+    fn reads_lines_lossily_and_detects_binary() {
+        let matcher = RegexMatcher::new(r"ca(t|f)", true).unwrap();
+        let search = |input: &[u8]| {
+            let mut out = Vec::new();
+            let matched = search_reader(&matcher, "pets.txt", input, &mut out).unwrap();
+            (matched, String::from_utf8(out).unwrap())
+        };
+        assert_eq!(
+            search(b"a cat\nno\ncaf\xe9\n"),
+            (true, String::from("pets.txt:1:a cat\npets.txt:3:caf\u{fffd}\n"))
+        );
+        assert_eq!(search(b"\x00\x01cat\ncat\n"), (true, String::from("Binary file pets.txt matches\n")));
+        assert_eq!(search(b"\x00\x01dog\n"), (false, String::new()));
+    }
+
+    #[test]
+    fn output_follows_argument_order() {
+        let dir = tempfile::tempdir().unwrap();
+        // 先のファイルほど大きくして、後のファイルの方が先に検索し終わるようにする
+        let paths: Vec<String> = (0..24)
+            .map(|i| {
+                let path = dir.path().join(format!("{:02}.txt", i));
+                let mut contents = "filler line\n".repeat((24 - i) * 5000);
+                contents.push_str(&format!("match {}\nfiller\nmatch {} again\n", i, i));
+                fs::write(&path, contents).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+
+        let (status, out, err) = search("^match", &paths);
+        assert_eq!(status, Status { matched: true, errors: 0 });
+        assert!(err.is_empty());
+        let expected: Vec<String> = paths
+            .iter()
+            .enumerate()
+            .flat_map(|(i, path)| {
+                let first = (24 - i) * 5000 + 1;
+                vec![format!("{}:{}:match {}", path, first, i), format!("{}:{}:match {} again", path, first + 2, i)]
+            })
+            .collect();
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn streams_the_current_file_and_holds_back_the_rest() {
+        use std::sync::mpsc::{self, Sender};
+        use std::time::Duration;
+
+        // 書かれるたびに、その内容を送るWriter
+        struct Channel(Sender<Vec<u8>>);
+        impl Write for Channel {
+            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+                self.0.send(data.to_vec()).unwrap();
+                Ok(data.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        // 最初のファイルは、こちらが書くまで続きが届かないFIFOにする
+        let fifo = dir.path().join("fifo");
+        assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+        // 後のファイルは、メモリに置いておける分より多く出力する
+        let big = dir.path().join("big.txt");
+        fs::write(&big, (0..20_000).map(|i| format!("match {}\n", i)).collect::<String>()).unwrap();
+        let [fifo_path, big_path] = [&fifo, &big].map(|path| path.to_str().unwrap().to_string());
+
+        let (sender, receiver) = mpsc::channel();
+        let paths = vec![fifo_path.clone(), big_path.clone()];
+        let search = thread::spawn(move || {
+            let matcher = RegexMatcher::new("match", true).unwrap();
+            search_all(&matcher, &paths, &mut Channel(sender), &mut Vec::new()).unwrap()
+        });
+
+        let mut writer = fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+        writer.write_all(b"match first\n").unwrap();
+        // 最初のファイルの行は、そのファイルが終わるのを待たずに届く
+        let first = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(String::from_utf8(first).unwrap(), format!("{}:1:match first\n", fifo_path));
+        writer.write_all(b"no\nmatch last\n").unwrap();
+        drop(writer);
+
+        assert_eq!(search.join().unwrap(), Status { matched: true, errors: 0 });
+        let rest = String::from_utf8(receiver.iter().flatten().collect()).unwrap();
+        let mut expected = format!("{}:3:match last\n", fifo_path);
+        for i in 0..20_000 {
+            expected.push_str(&format!("{}:{}:match {}\n", big_path, i + 1, i));
+        }
+        assert_eq!(rest, expected);
+    }
+
+    #[test]
+    fn exit_codes_like_grep() {
+        let dir = tempfile::tempdir().unwrap();
+        let poem = dir.path().join("poem.txt");
+        fs::write(&poem, "I'm nobody! Who are you?\nAre you nobody, too?\n").unwrap();
+        let image = dir.path().join("image.bin");
+        fs::write(&image, b"PNG\x00nobody").unwrap();
+        let missing = dir.path().join("missing.txt");
+        let [poem, image, missing] = [poem, image, missing].map(|path| path.to_str().unwrap().to_string());
+
+        let (status, out, _) = search("nobody", &[poem.clone(), image.clone()]);
+        assert_eq!(status.exit_code(), 0);
+        assert_eq!(
+            out,
+            format!("{0}:1:I'm nobody! Who are you?\n{0}:2:Are you nobody, too?\nBinary file {1} matches\n", poem, image)
+        );
+
+        let (status, out, _) = search("somebody", std::slice::from_ref(&poem));
+        assert_eq!((status.exit_code(), out.as_str()), (1, ""));
+
+        // 読めないファイルがあっても残りは検索し、終了コードは2にする
+        let (status, out, err) = search("too", &[missing.clone(), poem.clone()]);
+        assert_eq!(status, Status { matched: true, errors: 1 });
+        assert_eq!(status.exit_code(), 2);
+        assert_eq!(out, format!("{}:2:Are you nobody, too?\n", poem));
+        assert!(err.starts_with(&format!("grep-rs: {}: ", missing)), "{}", err);
+    }
+
+    #[test]
+    fn stops_when_output_fails() {
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = (0..8)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.txt", i));
+                fs::write(&path, "match\n").unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let matcher = RegexMatcher::new("match", true).unwrap();
+        let e = search_all(&matcher, &paths, &mut Closed, &mut Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
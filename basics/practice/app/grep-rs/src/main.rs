@@ -1,34 +1,49 @@
-use std::fs::read_to_string;
-use rayon::prelude::*;
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use grep_core::RegexMatcher;
+use grep_rs::search_all;
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
-#[structopt(name = "rsgrep")]
+#[structopt(name = "grep-rs")]
 struct GrepArgs {
+    // 正規表現として扱う
     #[structopt(name = "PATTERN")]
     pattern: String,
+    // 検索するファイル。"-"か、1つも指定しなければ標準入力を読む
     #[structopt(name = "FILE")]
     path: Vec<String>,
 }
 
-fn grep(content: &str, pattern: &str, path: &str) {
-    for line in content.lines() {
-        if line.contains(pattern) {
-            println!("{}: {}", path, line);
-        }
-    }
-}
-
-fn run(state: GrepArgs) {
-    state
-        .path
-        .par_iter()
-        .for_each(|file| match read_to_string(file) {
-            Ok(content) => grep(&content, &state.pattern, file),
-            Err(reason) => println!("{}", reason),
-        });
+// grepと同じく、引数や正規表現の誤りも終了コード2にする
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
 }
 
 fn main() {
-    run(GrepArgs::from_args());
+    let args = GrepArgs::from_args_safe().unwrap_or_else(|e| match e.kind {
+        ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+        _ => usage_error(&e.message),
+    });
+    let matcher = RegexMatcher::new(&args.pattern, true).unwrap_or_else(|e| usage_error(&format!("grep-rs: {}", e)));
+    let paths = if args.path.is_empty() { vec![String::from("-")] } else { args.path };
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let status = search_all(&matcher, &paths, &mut out, &mut io::stderr()).and_then(|status| {
+        out.flush()?;
+        Ok(status)
+    });
+    match status {
+        Ok(status) => process::exit(status.exit_code()),
+        // 書き込むのは一致した行だけなので、`| head`などで出力先が閉じられたときは一致している
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => {
+            eprintln!("grep-rs: {}", e);
+            process::exit(2);
+        },
+    }
 }
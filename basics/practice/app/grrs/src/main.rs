@@ -1,3 +1,6 @@
+use std::io::{self, BufWriter, Write};
+
+use anyhow::{Context, Result};
 use structopt::StructOpt;
 
 // ファイル内のパターンを検索し、そのパターンを含む行を表示する。
@@ -5,18 +8,28 @@ use structopt::StructOpt;
 struct Cli {
     // 探すべきパターン
     pattern: String,
-    // 読むべきファイルのパス
+    // 読むべきファイルのパス。"-"なら標準入力から読む
     #[structopt(parse(from_os_str))]
     path: std::path::PathBuf,
 }
 
-fn main() {
+fn main() -> Result<()> {
     let args = Cli::from_args();
-    let content = std::fs::read_to_string(&args.path).expect("could not read file");
+    // ファイル全体をメモリに読み込まず、少しずつ読みながら1行ずつ調べる
+    let input = stream_search::open(&args.path)
+        .with_context(|| format!("could not read file `{}`", args.path.display()))?;
 
-    for line in content.lines() {
-        if line.contains(&args.pattern) {
-            println!("{}", line);
-        }
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let result = stream_search::search_reader(
+        input,
+        |line| line.contains(&args.pattern),
+        |_, line| writeln!(out, "{}", line),
+    )
+    .and_then(|_| out.flush());
+    match result {
+        // `| head`などで出力先が先に閉じられたら、そこで止める
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.with_context(|| format!("could not search file `{}`", args.path.display())),
     }
 }
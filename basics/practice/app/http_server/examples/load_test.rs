@@ -0,0 +1,126 @@
+// 遅いクライアントを大勢同時につないだとき、スレッドで処理するサーバーとtokioで処理するサーバーが
+// それぞれ何人に応えられるかを比べる
+//
+//     cargo run --release --example load_test -- [同時に接続する数...]
+//
+// どのクライアントもリクエストを途中まで送り、`HOLD`だけ待ってから残りを送る。
+// スレッドのサーバーは1人に1つのWorkerを使うので、Workerと待ち行列の数を超えた分には503を返す。
+// 503を返されたクライアントは、残りを送るときに接続をリセットされて"failed"に数えられることもある
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use http_server::async_server::AsyncServer;
+use http_server::config::Config;
+use http_server::handler::Response;
+use http_server::parser::Request;
+use http_server::server::Server;
+
+const HOLD: Duration = Duration::from_millis(200);
+
+fn handler(_: &Request) -> Response {
+    Response::text(200, "ok")
+}
+
+// どちらも4つのスレッドで動かす
+fn config() -> Config {
+    Config {
+        addr: String::from("127.0.0.1"),
+        port: 0,
+        workers: 4,
+        ..Config::default()
+    }
+}
+
+fn start_threaded() -> SocketAddr {
+    let server = Server::bind(&config(), handler).unwrap().access_log(io::sink());
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+fn start_tokio() -> SocketAddr {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let config = config();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(config.workers)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let server = AsyncServer::bind(&config, handler).await.unwrap().access_log(io::sink());
+            sender.send(server.local_addr().unwrap()).unwrap();
+            server.run().await
+        })
+    });
+    receiver.recv().unwrap()
+}
+
+#[derive(Default)]
+struct Outcome {
+    ok: usize,
+    rejected: usize,
+    failed: usize,
+}
+
+// 1人のクライアント。返ってきたステータスラインを返す
+async fn slow_client(addr: SocketAddr, id: usize) -> io::Result<String> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n", id).as_bytes()).await?;
+    tokio::time::sleep(HOLD).await;
+    // 先に503を返されていれば書き込みに失敗するが、届いているレスポンスは読んでみる
+    let _ = stream.write_all(b"\r\n").await;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+    Ok(response.lines().next().unwrap_or("").to_string())
+}
+
+async fn load(addr: SocketAddr, connections: usize) -> Outcome {
+    let clients: Vec<_> = (0..connections).map(|id| tokio::spawn(slow_client(addr, id))).collect();
+    let mut outcome = Outcome::default();
+    for client in clients {
+        match client.await.unwrap() {
+            Ok(ref status) if status.starts_with("HTTP/1.1 200") => outcome.ok += 1,
+            Ok(ref status) if status.starts_with("HTTP/1.1 503") => outcome.rejected += 1,
+            _ => outcome.failed += 1,
+        }
+    }
+    outcome
+}
+
+fn main() {
+    let counts: Vec<usize> = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("connection counts must be numbers"))
+        .collect();
+    let counts = if counts.is_empty() { vec![50, 200, 1000] } else { counts };
+
+    let servers = [("threads", start_threaded()), ("tokio", start_tokio())];
+    let client = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+
+    println!("each client holds its request for {:?}; both servers use 4 threads", HOLD);
+    println!("{:<8} {:>11} {:>6} {:>6} {:>7} {:>9}", "server", "connections", "200", "503", "failed", "elapsed");
+    for &connections in &counts {
+        for &(name, addr) in &servers {
+            let start = Instant::now();
+            let outcome = client.block_on(load(addr, connections));
+            println!(
+                "{:<8} {:>11} {:>6} {:>6} {:>7} {:>8.2}s",
+                name,
+                connections,
+                outcome.ok,
+                outcome.rejected,
+                outcome.failed,
+                start.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::time::{timeout, timeout_at};
+
+use crate::config::Config;
+use crate::handler::{Handler, Response};
+use crate::parser::{self, Version};
+use crate::server::{respond, too_large, unavailable, write_log, AccessLog, IO_TIMEOUT};
+
+// `Server`と同じパーサーとハンドラーを使い、コネクションをtokioのタスクで処理するサーバー
+// 読み込みを待っている間はスレッドを使わないので、遅いクライアントを大勢抱えられる
+// ハンドラーは普通の関数として呼ぶので、時間のかかる処理をするとtokioのスレッドを止めてしまう
+pub struct AsyncServer {
+    listener: TcpListener,
+    handler: Arc<dyn Handler>,
+    max_connections: usize,
+    log: AccessLog,
+}
+
+impl AsyncServer {
+    // tokioのランタイムの中で呼ぶ
+    pub async fn bind<H: Handler + 'static>(config: &Config, handler: H) -> io::Result<AsyncServer> {
+        Ok(AsyncServer {
+            listener: TcpListener::bind(config.bind_addr()).await?,
+            handler: Arc::new(handler),
+            max_connections: config.max_connections,
+            log: Arc::new(Mutex::new(Box::new(io::stdout()))),
+        })
+    }
+
+    // アクセスログの書き出し先を変える。デフォルトは標準出力
+    pub fn access_log<W: Write + Send + 'static>(mut self, w: W) -> AsyncServer {
+        self.log = Arc::new(Mutex::new(Box::new(w)));
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // コネクションごとにタスクを立てて処理する
+    // 同時に開いているコネクションが`max_connections`個に達していたら、503を返して閉じる
+    pub async fn run(self) -> io::Result<()> {
+        let permits = Arc::new(Semaphore::new(self.max_connections));
+        loop {
+            // エラーが出てもループを継続するために`?`は使わない
+            let (stream, peer) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    println!("An error occurred while accepting a connection: {}", e);
+                    continue;
+                }
+            };
+            let log = Arc::clone(&self.log);
+            // 許可はタスクが終わるまで持ち、コネクションを閉じたら返す
+            let permit = match Arc::clone(&permits).try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    tokio::spawn(async move {
+                        let _ = reject(stream, peer, &log).await;
+                    });
+                    continue;
+                }
+            };
+            let handler = Arc::clone(&self.handler);
+            tokio::spawn(async move {
+                if let Err(e) = serve(stream, peer, &*handler, &log).await {
+                    println!("Connection error: {}", e);
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+// 1つのコネクションで1つのリクエストを読み、ハンドラーのレスポンスを返して閉じる
+// `Partial`の間は読み足して、同じバッファでもう一度`parse`を呼ぶ
+async fn serve(mut stream: TcpStream, peer: SocketAddr, handler: &dyn Handler, log: &AccessLog) -> io::Result<()> {
+    use crate::parser::ParseResult::*;
+    let start = Instant::now();
+    // 少しずつ送り続けるクライアントもいるので、1回のreadではなくリクエスト全体を読む時間を区切る
+    let deadline = tokio::time::Instant::from_std(start + IO_TIMEOUT);
+
+    let mut buf = Vec::new();
+    loop {
+        let mut b = [0; 1024];
+        let n = match timeout_at(deadline, stream.read(&mut b)).await {
+            Ok(n) => n?,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out")),
+        };
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&b[0..n]);
+        // `Request`はbufを借用しているので、レスポンスを作ったらすぐに手放す
+        let (response, line, version) = match parser::parse(buf.as_slice()) {
+            // 大きくなりすぎたら、それ以上は読まずに断る
+            Partial => match too_large(&buf) {
+                Some(response) => (response, String::from("-"), Version::Http11),
+                None => continue,
+            },
+            Error(e) => (
+                Response::text(400, format!("Bad Request: {}\r\n", e)),
+                String::from("-"),
+                Version::Http11,
+            ),
+            Complete(req) => (
+                respond(handler, &req),
+                format!("{} {} {}", req.method, req.path, req.version.as_str()),
+                req.version,
+            ),
+        };
+        write_response(&mut stream, &response, version).await?;
+        return write_log(log, peer, &line, &response, start);
+    }
+}
+
+async fn reject(mut stream: TcpStream, peer: SocketAddr, log: &AccessLog) -> io::Result<()> {
+    let start = Instant::now();
+    let response = unavailable();
+    write_response(&mut stream, &response, Version::Http11).await?;
+    write_log(log, peer, "-", &response, start)
+}
+
+async fn write_response(stream: &mut TcpStream, response: &Response, version: Version) -> io::Result<()> {
+    let mut out = Vec::new();
+    response.write_to(&mut out, version)?;
+    with_timeout(stream.write_all(&out)).await
+}
+
+// 遅いクライアントのタスクがいつまでも残らないように、読み書きは`IO_TIMEOUT`で諦める
+async fn with_timeout<T, F>(f: F) -> io::Result<T>
+where
+    F: std::future::Future<Output = io::Result<T>>,
+{
+    match timeout(IO_TIMEOUT, f).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "connection timed out")),
+    }
+}
+
+
+// 別のスレッドでtokioのランタイムを動かし、空いているポートでサーバーを起動する
+#[cfg(test)]
+fn start<H: Handler + 'static>(max_connections: usize, handler: H) -> (SocketAddr, crate::server::SharedLog) {
+    let config = Config {
+        addr: String::from("127.0.0.1"),
+        port: 0,
+        workers: 1,
+        max_connections,
+        ..Config::default()
+    };
+    let log = crate::server::SharedLog::default();
+    let server_log = log.clone();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        // 1つのスレッドでも、大勢のコネクションを待てることを確かめる
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let server = AsyncServer::bind(&config, handler).await.unwrap().access_log(server_log);
+            sender.send(server.local_addr().unwrap()).unwrap();
+            server.run().await
+        })
+    });
+    (receiver.recv().unwrap(), log)
+}
+
+#[test]
+fn answers_like_the_threaded_server() {
+    use crate::handler::Routes;
+    use crate::parser::Request;
+    use crate::server::fetch;
+
+    let routes = Routes::new()
+        .route("/panic", |_: &Request| -> Response { panic!("handler failed") })
+        .fallback(|req: &Request| Response::text(200, format!("OK {}", req.path)));
+    let (addr, log) = start(16, routes);
+
+    assert_eq!(fetch(addr, "GET /foo\r\n"), "OK /foo");
+    let response = fetch(addr, "POST /form HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nOK /form"));
+    assert!(fetch(addr, "GET /panic HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 500 "));
+    assert!(fetch(addr, "GET / HTTP/3\r\n\r\n").starts_with("HTTP/1.1 400 "));
+
+    let lines = log.lines();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].contains(" \"POST /form HTTP/1.1\" 200 8 "), "{}", lines[1]);
+}
+
+#[test]
+fn holds_many_slow_clients_on_one_thread() {
+    use std::io::Read;
+    use std::net::TcpStream;
+    use crate::parser::Request;
+
+    let (addr, log) = start(1000, |req: &Request| Response::text(200, req.path.to_string()));
+    // 全員がリクエストを途中まで送って止まっても、他のコネクションを受け付け続ける
+    let mut clients: Vec<TcpStream> = (0..200)
+        .map(|i| {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(format!("GET /{} HTTP/1.1\r\nHost:", i).as_bytes()).unwrap();
+            client
+        })
+        .collect();
+    for client in &mut clients {
+        client.write_all(b" a\r\n\r\n").unwrap();
+    }
+    for (i, client) in clients.iter_mut().enumerate() {
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with(&format!("\r\n\r\n/{}", i)), "{}", response);
+    }
+    assert_eq!(log.lines().len(), 200);
+}
+
+#[test]
+fn closes_requests_that_grow_too_large() {
+    use std::io::Read;
+    use std::net::TcpStream;
+    use crate::parser::Request;
+
+    let (addr, log) = start(16, |_: &Request| Response::text(200, "ok"));
+    for (request, status) in crate::server::oversized_requests().iter() {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with(status), "{}", response);
+    }
+    assert_eq!(log.lines().len(), 2);
+}
+
+#[test]
+fn rejects_connections_over_the_limit() {
+    use std::io::Read;
+    use std::net::TcpStream;
+    use crate::parser::Request;
+    use crate::server::fetch;
+
+    let (addr, _) = start(1, |_: &Request| Response::text(200, "ok"));
+    // 1つ目のコネクションがリクエストを送り終えるまで、許可を持ち続ける
+    let mut first = TcpStream::connect(addr).unwrap();
+    first.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    // 断られる側は何も送らない。読まれないまま閉じられると、レスポンスより先にRSTが届くことがある
+    assert!(fetch(addr, "").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    first.write_all(b"\r\n").unwrap();
+    let mut response = String::new();
+    first.read_to_string(&mut response).unwrap();
+    assert!(response.ends_with("\r\n\r\nok"));
+}
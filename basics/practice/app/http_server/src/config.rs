@@ -0,0 +1,224 @@
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: http_server [OPTIONS]
+
+Options:
+    --addr <HOST>       address to listen on [env: HTTP_SERVER_ADDR] (default: 127.0.0.1)
+    --port <PORT>       port to listen on [env: HTTP_SERVER_PORT] (default: 8080)
+    --runtime <R>       'threads' (blocking worker threads) or 'tokio' (async)
+                        [env: HTTP_SERVER_RUNTIME] (default: threads)
+    --workers <N>       number of worker threads [env: HTTP_SERVER_WORKERS] (default: 4)
+    --queue <N>         threads: connections waiting for a worker before answering 503
+                        [env: HTTP_SERVER_QUEUE] (default: 64)
+    --max-connections <N>
+                        tokio: open connections before answering 503
+                        [env: HTTP_SERVER_MAX_CONNECTIONS] (default: 10000)
+    -h, --help          print this help";
+
+// コネクションをどう処理するか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    // コネクションごとにWorkerのスレッドを1つ使う
+    Threads,
+    // tokioのタスクで処理する。少ないスレッドで多くのコネクションを待てる
+    Tokio,
+}
+
+impl std::str::FromStr for Runtime {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Runtime, ()> {
+        match s {
+            "threads" => Ok(Runtime::Threads),
+            "tokio" => Ok(Runtime::Tokio),
+            _ => Err(()),
+        }
+    }
+}
+
+// サーバーの設定。コマンドライン引数が環境変数より優先される
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub addr: String,
+    pub port: u16,
+    pub runtime: Runtime,
+    // Threadsでは処理するスレッドの数、Tokioではtokioのワーカースレッドの数
+    pub workers: usize,
+    // Threadsだけで使う
+    pub queue: usize,
+    // Tokioだけで使う
+    pub max_connections: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addr: String::from("127.0.0.1"),
+            port: 8080,
+            runtime: Runtime::Threads,
+            workers: 4,
+            queue: 64,
+            max_connections: 10000,
+        }
+    }
+}
+
+// 設定を読めなかった理由
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    // `--help`が指定された。エラーではないが、サーバーは起動しない
+    Help,
+    UnknownOption(String),
+    // 値が必要なオプションの後に何もなかった
+    MissingValue(String),
+    // オプション（または環境変数）の名前と、読めなかった値
+    InvalidValue(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::UnknownOption(ref option) => write!(f, "unknown option '{}'\n\n{}", option, USAGE),
+            ConfigError::MissingValue(ref option) => write!(f, "'{}' needs a value", option),
+            ConfigError::InvalidValue(ref name, ref value) => write!(f, "invalid value '{}' for {}", value, name),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // 環境変数を読んでから、コマンドライン引数で上書きする
+    // `env`に関数を渡すので、テストでは本物の環境変数を触らなくてよい
+    // argsにはプログラム名を含めない
+    pub fn parse<I, E>(args: I, env: E) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let mut config = Config::default();
+        if let Some(addr) = env("HTTP_SERVER_ADDR") {
+            config.addr = addr;
+        }
+        if let Some(port) = env("HTTP_SERVER_PORT") {
+            config.port = parse_value("HTTP_SERVER_PORT", &port)?;
+        }
+        if let Some(workers) = env("HTTP_SERVER_WORKERS") {
+            config.workers = positive("HTTP_SERVER_WORKERS", &workers)?;
+        }
+        if let Some(runtime) = env("HTTP_SERVER_RUNTIME") {
+            config.runtime = parse_value("HTTP_SERVER_RUNTIME", &runtime)?;
+        }
+        if let Some(queue) = env("HTTP_SERVER_QUEUE") {
+            config.queue = parse_value("HTTP_SERVER_QUEUE", &queue)?;
+        }
+        if let Some(max) = env("HTTP_SERVER_MAX_CONNECTIONS") {
+            config.max_connections = positive("HTTP_SERVER_MAX_CONNECTIONS", &max)?;
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // `--port=8080`の形も受け付ける
+            let (option, inline) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+                _ => (arg, None),
+            };
+            if option == "-h" || option == "--help" {
+                return Err(ConfigError::Help);
+            }
+            if !["--addr", "--port", "--runtime", "--workers", "--queue", "--max-connections"].contains(&option.as_str()) {
+                return Err(ConfigError::UnknownOption(option));
+            }
+            let value = match inline.or_else(|| args.next()) {
+                Some(value) => value,
+                None => return Err(ConfigError::MissingValue(option)),
+            };
+            match option.as_str() {
+                "--addr" => config.addr = value,
+                "--port" => config.port = parse_value(&option, &value)?,
+                "--runtime" => config.runtime = parse_value(&option, &value)?,
+                "--workers" => config.workers = positive(&option, &value)?,
+                "--queue" => config.queue = parse_value(&option, &value)?,
+                _ => config.max_connections = positive(&option, &value)?,
+            }
+        }
+        Ok(config)
+    }
+
+    // `TcpListener::bind`に渡す形。IPv6のアドレスは`[]`で囲む
+    pub fn bind_addr(&self) -> String {
+        if self.addr.contains(':') && !self.addr.starts_with('[') {
+            format!("[{}]:{}", self.addr, self.port)
+        } else {
+            format!("{}:{}", self.addr, self.port)
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+    value.trim().parse().map_err(|_| ConfigError::InvalidValue(name.to_string(), value.to_string()))
+}
+
+// Workerが0人だとリクエストを処理できない
+fn positive(name: &str, value: &str) -> Result<usize, ConfigError> {
+    match parse_value(name, value)? {
+        0 => Err(ConfigError::InvalidValue(name.to_string(), value.to_string())),
+        n => Ok(n),
+    }
+}
+
+
+#[cfg(test)]
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn defaults_without_args_or_env() {
+    let config = Config::parse(args(&[]), |_| None).unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.bind_addr(), "127.0.0.1:8080");
+}
+
+#[test]
+fn args_override_env() {
+    let env = |name: &str| match name {
+        "HTTP_SERVER_ADDR" => Some(String::from("0.0.0.0")),
+        "HTTP_SERVER_PORT" => Some(String::from("9000")),
+        "HTTP_SERVER_WORKERS" => Some(String::from("8")),
+        _ => None,
+    };
+    let config = Config::parse(args(&[]), env).unwrap();
+    assert_eq!((config.addr.as_str(), config.port, config.workers), ("0.0.0.0", 9000, 8));
+
+    let config = Config::parse(args(&["--port", "0", "--workers=2", "--addr", "::1"]), env).unwrap();
+    assert_eq!((config.port, config.workers, config.queue), (0, 2, 64));
+    assert_eq!(config.bind_addr(), "[::1]:0");
+
+    let env = |name: &str| if name == "HTTP_SERVER_RUNTIME" { Some(String::from("tokio")) } else { None };
+    assert_eq!(Config::parse(args(&[]), env).unwrap().runtime, Runtime::Tokio);
+    assert_eq!(Config::parse(args(&["--runtime", "threads"]), env).unwrap().runtime, Runtime::Threads);
+}
+
+#[test]
+fn reports_bad_args_and_env() {
+    assert_eq!(Config::parse(args(&["--help"]), |_| None), Err(ConfigError::Help));
+    assert_eq!(Config::parse(args(&["--verbose"]), |_| None), Err(ConfigError::UnknownOption(String::from("--verbose"))));
+    assert_eq!(Config::parse(args(&["--port"]), |_| None), Err(ConfigError::MissingValue(String::from("--port"))));
+    assert_eq!(
+        Config::parse(args(&["--runtime", "async"]), |_| None),
+        Err(ConfigError::InvalidValue(String::from("--runtime"), String::from("async")))
+    );
+    assert_eq!(
+        Config::parse(args(&["--workers", "0"]), |_| None),
+        Err(ConfigError::InvalidValue(String::from("--workers"), String::from("0")))
+    );
+    // 環境変数が不正でも、どの変数が悪いのかがわかる
+    let env = |name: &str| if name == "HTTP_SERVER_PORT" { Some(String::from("http")) } else { None };
+    assert_eq!(
+        Config::parse(args(&["--port", "80"]), env),
+        Err(ConfigError::InvalidValue(String::from("HTTP_SERVER_PORT"), String::from("http")))
+    );
+}
@@ -0,0 +1,161 @@
+use std::io::{self, Write};
+
+use crate::parser::{Request, Version};
+
+// ハンドラーが返すレスポンス
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    // text/plainのレスポンス
+    pub fn text<S: Into<String>>(status: u16, body: S) -> Response {
+        Response::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.into())
+    }
+
+    pub fn header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Response {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    // HTTP/0.9にはステータスラインもヘッダーもないので、ボディだけを書く
+    // それ以外ではContent-Lengthを付け、1つ返したらコネクションを閉じる
+    pub fn write_to<W: Write>(&self, w: &mut W, version: Version) -> io::Result<()> {
+        if version != Version::Http09 {
+            write!(w, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+            for (name, value) in &self.headers {
+                write!(w, "{}: {}\r\n", name, value)?;
+            }
+            write!(w, "Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len())?;
+        }
+        w.write_all(&self.body)
+    }
+}
+
+// ステータスラインに書く理由句
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+// アプリケーションはこのトレイトを実装して、リクエストに応じたレスポンスを返す
+// 複数のWorkerから同時に呼ばれるので、`Send`と`Sync`が必要
+pub trait Handler: Send + Sync {
+    fn handle(&self, req: &Request) -> Response;
+}
+
+// `|req| Response::text(200, "hello")`のようなクロージャもそのままハンドラーとして使える
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync,
+{
+    fn handle(&self, req: &Request) -> Response {
+        self(req)
+    }
+}
+
+// パスごとにハンドラーを振り分けるハンドラー
+// 完全に一致するパスがなければ`fallback`に任せる
+pub struct Routes {
+    routes: Vec<(String, Box<dyn Handler>)>,
+    fallback: Box<dyn Handler>,
+}
+
+impl Default for Routes {
+    fn default() -> Self {
+        Routes {
+            routes: Vec::new(),
+            fallback: Box::new(|_: &Request| Response::text(404, "Not Found\r\n")),
+        }
+    }
+}
+
+impl Routes {
+    pub fn new() -> Routes {
+        Routes::default()
+    }
+
+    // クエリ文字列（`?`の後）は比べない
+    pub fn route<H: Handler + 'static>(mut self, path: &str, handler: H) -> Routes {
+        self.routes.push((path.to_string(), Box::new(handler)));
+        self
+    }
+
+    pub fn fallback<H: Handler + 'static>(mut self, handler: H) -> Routes {
+        self.fallback = Box::new(handler);
+        self
+    }
+}
+
+impl Handler for Routes {
+    fn handle(&self, req: &Request) -> Response {
+        let path = req.path.split('?').next().unwrap_or(req.path);
+        match self.routes.iter().find(|(route, _)| route == path) {
+            Some((_, handler)) => handler.handle(req),
+            None => self.fallback.handle(req),
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn request(path: &str) -> Request<'_> {
+    match crate::parser::parse(path.as_bytes()) {
+        crate::parser::ParseResult::Complete(req) => req,
+        other => panic!("not a complete request: {:?}", other),
+    }
+}
+
+#[test]
+fn routes_by_path() {
+    let routes = Routes::new()
+        .route("/hello", |_: &Request| Response::text(200, "hello"))
+        .fallback(|req: &Request| Response::text(200, format!("OK {}", req.path)));
+    assert_eq!(routes.handle(&request("GET /hello?name=a HTTP/1.1\r\n\r\n")).body, b"hello");
+    assert_eq!(routes.handle(&request("GET /other\r\n")).body, b"OK /other");
+    assert_eq!(Routes::new().handle(&request("GET /\r\n")).status, 404);
+}
+
+#[test]
+fn writes_status_line_except_for_http09() {
+    let response = Response::text(404, "nope");
+    let mut out = Vec::new();
+    response.write_to(&mut out, Version::Http11).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnope"
+    );
+
+    let mut out = Vec::new();
+    response.write_to(&mut out, Version::Http09).unwrap();
+    assert_eq!(out, b"nope");
+}
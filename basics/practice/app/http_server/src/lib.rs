@@ -0,0 +1,7 @@
+// リクエストのパーサー、ハンドラー、スレッドで処理するサーバーとtokioで処理するサーバー
+// 2つのサーバーは同じパーサーとハンドラーを使う
+pub mod async_server;
+pub mod config;
+pub mod handler;
+pub mod parser;
+pub mod server;
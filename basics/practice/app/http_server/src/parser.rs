@@ -1,13 +1,18 @@
-use std::str::from_utf8;
+use std::fmt;
+use std::str::{from_utf8, Utf8Error};
+
+// ヘッダーの数の上限。これを超えるリクエストはエラーにする
+const MAX_HEADERS: usize = 100;
 
 // パーサの結果は成功/失敗の他に「入力が途中で終わってしまった」もあり、`Result`が使えないので自前定義
 // モジュールを使うと可視性が必要になるが、`pub`がつくと上位のモジュールから見えるようになる
+#[derive(Debug)]
 pub enum ParseResult<T> {
     // 列挙型の列挙子に`pub`は必要ない
     Complete(T),
     Partial,
-    // 簡単のため今回はエラーデータを省略
-    Error,
+    // 何が不正だったのかを`ParseError`で伝える
+    Error(ParseError),
 }
 
 // ジェネリクス型の`impl`を書くにはこのようにする
@@ -18,31 +23,141 @@ impl<T> ParseResult<T> {
         // 実はこのようにで列挙型の列挙子をインポートすることができる
         // この`self`は`parser`モジュールを指す
         use self::ParseResult::*;
-        match *self {
-            // インポートしてしまうとプリフィクスなしで参照できる
-            Complete(_) => true,
-            _ => false
-        }
+        // インポートしてしまうとプリフィクスなしで参照できる
+        matches!(*self, Complete(_))
     }
     #[allow(dead_code)]
     fn is_partial(&self) -> bool {
         use self::ParseResult::*;
-        match *self {
-            Partial => true,
-            _ => false
-        }
+        matches!(*self, Partial)
+    }
+    #[allow(dead_code)]
+    fn is_error(&self) -> bool {
+        use self::ParseResult::*;
+        matches!(*self, Error(_))
     }
 
 }
 
 // 便利のため、標準ライブラリの`From<T>`を実装する
 // これで普通の`Result`を`ParseResult`に変換できるようになる
-impl<T, E> From<Result<T, E>> for ParseResult<T> {
+// エラーは`ParseError`に変換できるものなら何でもよく、変換したエラーをそのまま持ち運ぶ
+impl<T, E: Into<ParseError>> From<Result<T, E>> for ParseResult<T> {
     fn from(r: Result<T, E>) -> Self {
         use self::ParseResult::*;
         match r {
             Ok(t) => Complete(t),
-            Err(_) => Error,
+            Err(e) => Error(e.into()),
+        }
+    }
+}
+
+// リクエストが不正だった理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // リクエストラインの形が正しくない
+    RequestLine,
+    // HTTP/1.0とHTTP/1.1以外のバージョン
+    Version,
+    // ヘッダーの行の形が正しくない
+    Header,
+    // ヘッダーが`MAX_HEADERS`より多い
+    TooManyHeaders,
+    // Content-Lengthが数字でないか、複数の値が食い違っている
+    ContentLength,
+    // chunkedでないTransfer-Encoding、またはContent-Lengthとの併用
+    TransferEncoding,
+    // チャンクの大きさやチャンクの後の改行が正しくない
+    Chunk,
+    // UTF-8として読めなかった。`Utf8Error`は読めなかった位置を持っている
+    Utf8(Utf8Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ParseError::*;
+        match *self {
+            RequestLine => write!(f, "malformed request line"),
+            Version => write!(f, "unsupported HTTP version"),
+            Header => write!(f, "malformed header line"),
+            TooManyHeaders => write!(f, "more than {} headers", MAX_HEADERS),
+            ContentLength => write!(f, "invalid Content-Length"),
+            TransferEncoding => write!(f, "unsupported Transfer-Encoding"),
+            Chunk => write!(f, "malformed chunk"),
+            Utf8(ref e) => write!(f, "invalid UTF-8: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// `?`や`into`で`from_utf8`のエラーをそのまま`ParseError`にできるようにする
+impl From<Utf8Error> for ParseError {
+    fn from(e: Utf8Error) -> Self {
+        ParseError::Utf8(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    // リクエストラインだけで、ヘッダーもボディもない
+    Http09,
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::Http09 => "HTTP/0.9",
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+// ヘッダーの名前と値。どちらも入力のバッファを借用していて、コピーはしない
+#[derive(Debug, Default)]
+pub struct Headers<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> Headers<'a> {
+    // 名前は大文字と小文字を区別しない。同じ名前が複数あれば最初のものを返す
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.get_all(name).next()
+    }
+
+    pub fn get_all<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'a str> + 'b {
+        self.0.iter().filter(move |(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.0.iter().cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// ボディもバッファを借用する。chunkedのボディはつなげるとコピーになるので、チャンクごとに持つ
+#[derive(Debug, PartialEq, Eq)]
+pub enum Body<'a> {
+    Empty,
+    Full(&'a [u8]),
+    Chunked(Vec<&'a [u8]>),
+}
+
+impl<'a> Body<'a> {
+    // 中身をつなげてコピーする
+    pub fn to_vec(&self) -> Vec<u8> {
+        match *self {
+            Body::Empty => Vec::new(),
+            Body::Full(body) => body.to_vec(),
+            Body::Chunked(ref chunks) => chunks.concat(),
         }
     }
 }
@@ -51,37 +166,206 @@ impl<T, E> From<Result<T, E>> for ParseResult<T> {
 // 構造体の定義で'aというパラメータが使われているのは、
 // &strのライフタイムを越えてRequestが参照されないように、
 // 参照のライフタイムを明示するため
-pub struct Request<'a>(pub &'a str);
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub version: Version,
+    pub headers: Headers<'a>,
+    pub body: Body<'a>,
+    // このリクエストが使ったバイト数。パイプラインで送られた次のリクエストはここから始まる
+    pub len: usize,
+}
+
+// 途中までの入力なら`Ok(None)`を返す。`?`でエラーと「途中」の両方を素通しできるようにするための型
+type Step<T> = Result<Option<T>, ParseError>;
 
-// HTTP/0.9のリクエストは `METHOD path\r\n`の形をしている
-// 今回はGETだけパースする
-pub fn parse(mut buf: &[u8]) -> ParseResult<Request> {
+// `Step`を返す関数の中で、`Option`が`None`（入力が途中）なら呼び出し元にも`Ok(None)`を返すマクロ
+macro_rules! need {
+    ($e:expr) => {
+        match $e {
+            Some(v) => v,
+            None => return Ok(None),
+        }
+    };
+}
+
+// HTTP/0.9のリクエストは `GET path\r\n`、
+// HTTP/1.xのリクエストは `METHOD path HTTP/1.x\r\n` にヘッダーと空行、ボディが続く
+// bufの先頭から1つのリクエストを読む。入力が足りなければ`Partial`を返すので、
+// 呼び出し側は読み足したバッファでもう一度呼び出す
+pub fn parse(buf: &[u8]) -> ParseResult<Request<'_>> {
     use self::ParseResult::*;
-    // `b".."` でバイト列リテラル
-    let get = b"GET ";
-    let end = b"\r\n";
-    // GET がこなければエラー
-    if !buf.starts_with(get) {
-        return Error;
-    }
-    // 残りはパスネーム
-    buf = &buf[get.len()..];
-    if buf.ends_with(end) {
-        buf = &buf[0..buf.len() - end.len()]
-    } else {
-        // 末尾が`\r\n`でなければ入力が完了していないとみなす
-        return Partial;
-    }
-
-    // `from_utf8`で`&[u8]`から`&str`が作れる。データのコピーはしない。
-    // ただし失敗するかもしれないので返り値は`Result`に包まれる
-    from_utf8(buf)
-        // タプル構造体は関数としても扱える
-        // Result<&str, Utf8Error> -> Result<Request, Utf8Error>
-        .map(Request)
-        // `From`を実装したので自動で実装された`Into`の`into`メソッドを呼んでいる
-        //  Result<Request, Utf8Error> -> ParseResult<Request>
-        .into()
+    match parse_request(buf) {
+        Ok(Some(request)) => Complete(request),
+        Ok(None) => Partial,
+        Err(e) => Error(e),
+    }
+}
+
+fn parse_request(buf: &[u8]) -> Step<Request<'_>> {
+    // 改行が届く前でも、メソッドが不正だとわかればすぐにエラーにする
+    let method_end = buf.iter().position(|&b| b == b' ').unwrap_or(buf.len());
+    if !buf[..method_end].iter().all(|&b| is_token(b)) {
+        return Err(ParseError::RequestLine);
+    }
+
+    let (line, mut pos) = need!(next_line(buf, 0));
+    let (method, path, version) = parse_request_line(line)?;
+    if version == Version::Http09 {
+        return Ok(Some(Request {
+            method,
+            path,
+            version,
+            headers: Headers::default(),
+            body: Body::Empty,
+            len: pos,
+        }));
+    }
+
+    // 空行までがヘッダー
+    let mut headers = Headers::default();
+    loop {
+        let (line, next) = need!(next_line(buf, pos));
+        pos = next;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(ParseError::TooManyHeaders);
+        }
+        headers.0.push(parse_header(line)?);
+    }
+
+    let (body, len) = need!(parse_body(buf, pos, &headers)?);
+    Ok(Some(Request {
+        method,
+        path,
+        version,
+        headers,
+        body,
+        len,
+    }))
+}
+
+fn parse_request_line(line: &[u8]) -> Result<(&str, &str, Version), ParseError> {
+    let line = from_utf8(line)?;
+    let mut parts = line.split(' ');
+    let method = parts.next().filter(|m| !m.is_empty()).ok_or(ParseError::RequestLine)?;
+    let path = parts.next().filter(|p| !p.is_empty()).ok_or(ParseError::RequestLine)?;
+    if path.bytes().any(|b| b.is_ascii_control()) {
+        return Err(ParseError::RequestLine);
+    }
+    let version = match (parts.next(), parts.next()) {
+        // HTTP/0.9にはGETしかない
+        (None, _) if method == "GET" => Version::Http09,
+        (Some("HTTP/1.0"), None) => Version::Http10,
+        (Some("HTTP/1.1"), None) => Version::Http11,
+        (Some(v), None) if v.starts_with("HTTP/") => return Err(ParseError::Version),
+        _ => return Err(ParseError::RequestLine),
+    };
+    Ok((method, path, version))
+}
+
+// `Name: value`。名前の後に空白は置けず、値の前後の空白は取り除く
+fn parse_header(line: &[u8]) -> Result<(&str, &str), ParseError> {
+    let colon = line.iter().position(|&b| b == b':').ok_or(ParseError::Header)?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    // 行頭の空白で前の行の続きを書く書き方（obs-fold）は受け付けない
+    if name.is_empty() || !name.iter().all(|&b| is_token(b)) {
+        return Err(ParseError::Header);
+    }
+    let value = from_utf8(value)?.trim_matches([' ', '\t']);
+    if value.bytes().any(|b| b.is_ascii_control() && b != b'\t') {
+        return Err(ParseError::Header);
+    }
+    Ok((from_utf8(name)?, value))
+}
+
+// ヘッダーからボディの長さを決めて、ボディと、リクエストの終わりの位置を返す
+fn parse_body<'a>(buf: &'a [u8], pos: usize, headers: &Headers) -> Step<(Body<'a>, usize)> {
+    if let Some(encoding) = headers.get("Transfer-Encoding") {
+        // 長さの決め方が2通りあると、途中のプロキシと解釈が食い違うおそれがある
+        let chunked = encoding.rsplit(',').next().is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"));
+        if !chunked || headers.get("Content-Length").is_some() {
+            return Err(ParseError::TransferEncoding);
+        }
+        return parse_chunked(buf, pos);
+    }
+
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::ContentLength);
+        }
+        let value: usize = value.parse().map_err(|_| ParseError::ContentLength)?;
+        if length.is_some_and(|length| length != value) {
+            return Err(ParseError::ContentLength);
+        }
+        length = Some(value);
+    }
+    match length {
+        None | Some(0) => Ok(Some((Body::Empty, pos))),
+        Some(length) if buf.len() - pos < length => Ok(None),
+        Some(length) => Ok(Some((Body::Full(&buf[pos..pos + length]), pos + length))),
+    }
+}
+
+// `大きさ(16進数)\r\nデータ\r\n`の繰り返しで、大きさ0のチャンクとトレーラー、空行で終わる
+fn parse_chunked(buf: &[u8], mut pos: usize) -> Step<(Body<'_>, usize)> {
+    let mut chunks = Vec::new();
+    loop {
+        let (line, next) = need!(next_line(buf, pos));
+        // `;`の後のチャンク拡張は読み飛ばす
+        let size = line.split(|&b| b == b';').next().unwrap_or(line);
+        let size = from_utf8(size).map_err(|_| ParseError::Chunk)?.trim_end_matches([' ', '\t']);
+        if size.is_empty() || size.len() > 16 {
+            return Err(ParseError::Chunk);
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::Chunk)?;
+        pos = next;
+        if size == 0 {
+            break;
+        }
+
+        // データの後の改行まで届いていなければ待つ
+        let end = pos.checked_add(size).ok_or(ParseError::Chunk)?;
+        if buf.len() < end {
+            return Ok(None);
+        }
+        // データの直後は改行だけでなければならない
+        let (line, next) = need!(next_line(buf, end));
+        if !line.is_empty() {
+            return Err(ParseError::Chunk);
+        }
+        chunks.push(&buf[pos..end]);
+        pos = next;
+    }
+
+    // トレーラーは読み飛ばす
+    loop {
+        let (line, next) = need!(next_line(buf, pos));
+        pos = next;
+        if line.is_empty() {
+            break;
+        }
+        parse_header(line)?;
+    }
+    Ok(Some((Body::Chunked(chunks), pos)))
+}
+
+// startから次の改行までの行と、次の行の先頭の位置を返す
+// 改行は`\r\n`だが、`\n`だけでも受け付ける
+fn next_line(buf: &[u8], start: usize) -> Option<(&[u8], usize)> {
+    let len = buf[start..].iter().position(|&b| b == b'\n')?;
+    let line = &buf[start..start + len];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Some((line, start + len + 1))
+}
+
+// メソッドやヘッダーの名前に使える文字
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
 }
 
 
@@ -117,4 +401,111 @@ fn http09_post_failure() {
     let req = b"POST /\r\n";
     let res = parse(req);
     assert!(res.is_complete());
-}
\ No newline at end of file
+}
+
+// テストの中で`Complete`の中身を取り出す
+#[cfg(test)]
+fn complete(buf: &[u8]) -> Request<'_> {
+    match parse(buf) {
+        ParseResult::Complete(req) => req,
+        other => panic!("expected a complete request: {:?}", other),
+    }
+}
+
+#[cfg(test)]
+fn error(buf: &[u8]) -> ParseError {
+    match parse(buf) {
+        ParseResult::Error(e) => e,
+        other => panic!("expected an error: {:?}", other),
+    }
+}
+
+#[test]
+fn http11_headers_borrow_from_buffer() {
+    let buf = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\nAccept:  text/html \r\naccept: */*\r\n\r\n";
+    let req = complete(buf);
+    assert_eq!((req.method, req.path, req.version), ("GET", "/index.html", Version::Http11));
+    assert_eq!(req.headers.get("host"), Some("example.com"));
+    assert_eq!(req.headers.get_all("ACCEPT").collect::<Vec<_>>(), vec!["text/html", "*/*"]);
+    assert_eq!(req.body, Body::Empty);
+    assert_eq!(req.len, buf.len());
+    // コピーせずに入力のバッファを指している
+    assert_eq!(req.path.as_ptr(), buf[4..].as_ptr());
+}
+
+#[test]
+fn http10_content_length_body() {
+    let buf = b"POST /form HTTP/1.0\nContent-Length: 5\n\nhelloGET / HTTP/1.1\r\n\r\n";
+    let req = complete(buf);
+    assert_eq!(req.version, Version::Http10);
+    assert_eq!(req.body, Body::Full(b"hello"));
+    // 続けて送られたリクエストは、lenの位置から読める
+    assert_eq!(complete(&buf[req.len..]).path, "/");
+}
+
+#[test]
+fn chunked_body_keeps_each_chunk() {
+    let buf = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n1\r\n!\r\n0\r\nX-Trailer: 1\r\n\r\n";
+    let req = complete(buf);
+    assert_eq!(req.body, Body::Chunked(vec![&b"hello"[..], &b"!"[..]]));
+    assert_eq!(req.body.to_vec(), b"hello!");
+    assert_eq!(req.len, buf.len());
+}
+
+#[test]
+fn errors_carry_the_reason() {
+    assert_eq!(error(b"GET / HTTP/2.0\r\n\r\n"), ParseError::Version);
+    assert_eq!(error(b"GET / HTTP/1.1 extra\r\n\r\n"), ParseError::RequestLine);
+    assert_eq!(error(b"G(T"), ParseError::RequestLine);
+    assert_eq!(error(b"GET / HTTP/1.1\r\nHost : a\r\n\r\n"), ParseError::Header);
+    assert_eq!(error(b"GET / HTTP/1.1\r\n folded\r\n\r\n"), ParseError::Header);
+    assert_eq!(error(b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"), ParseError::ContentLength);
+    assert_eq!(error(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"), ParseError::ContentLength);
+    assert_eq!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n"), ParseError::TransferEncoding);
+    assert_eq!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"), ParseError::Chunk);
+    assert_eq!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab\r\n"), ParseError::Chunk);
+    assert_eq!(error(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nax\n0\r\n\r\n"), ParseError::Chunk);
+    assert_eq!(error(&[&b"GET / HTTP/1.1\r\n"[..], &b"A: 1\r\n".repeat(MAX_HEADERS + 1)].concat()), ParseError::TooManyHeaders);
+
+    // `from_utf8`の失敗は、読めなかった位置と一緒に返す
+    match error(b"GET /\xff HTTP/1.1\r\n\r\n") {
+        ParseError::Utf8(e) => assert_eq!(e.valid_up_to(), 5),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+// どこで分けて届いても、最後のバイトが届くまでは`Partial`、届いたら`Complete`になる
+#[test]
+fn split_at_every_byte_boundary() {
+    let requests: [&[u8]; 5] = [
+        b"GET /\r\n",
+        b"GET /a HTTP/1.0\r\n\r\n",
+        b"GET /a?b=c HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\n",
+        b"POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhello world",
+        b"PUT / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n3\r\nabc\r\n10\r\n0123456789abcdef\r\n0\r\nT: 1\r\n\r\n",
+    ];
+    for request in requests.iter() {
+        let whole = complete(request);
+        for split in 0..request.len() {
+            assert!(parse(&request[..split]).is_partial(), "{:?} at {}", String::from_utf8_lossy(request), split);
+        }
+        // 後ろに次のリクエストの一部が続いていても、同じ位置で終わる
+        let pipelined = [*request, &b"GET /next"[..]].concat();
+        let req = complete(&pipelined);
+        assert_eq!(req.len, request.len());
+        assert_eq!(req.body, whole.body);
+    }
+}
+
+// 壊れた入力をどこで切っても、パニックせずに`Partial`か`Error`になる
+#[test]
+fn truncated_garbage_never_panics() {
+    let garbage: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n\xff\xfe";
+    for split in 0..garbage.len() {
+        let res = parse(&garbage[..split]);
+        assert!(res.is_partial() || res.is_error());
+        for start in 0..split {
+            let _ = parse(&garbage[start..split]);
+        }
+    }
+}
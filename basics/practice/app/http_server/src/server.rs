@@ -0,0 +1,409 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::handler::{Handler, Response};
+use crate::parser::{self, Request, Version};
+
+// 遅いクライアントがWorkerを占有し続けないように、読み書きはこの時間で諦める
+pub(crate) const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// 読み終わっていないリクエストを溜めておける大きさ
+// ヘッダーの終わりの空行が`MAX_HEADER_SIZE`までに来なければ431、全体が`MAX_REQUEST_SIZE`を超えたら413を返して閉じる
+// `Partial`のたびに先頭から読み直すので、溜める量を抑えれば読み直しの手間も抑えられる
+pub(crate) const MAX_HEADER_SIZE: usize = 8 * 1024;
+pub(crate) const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+// 503を書くのを待っていられるコネクションの数。これも埋まっていたら、何も返さずに閉じる
+const REJECT_QUEUE: usize = 64;
+
+// アクセスログの書き出し先。複数のWorkerから1行ずつ書く
+pub(crate) type AccessLog = Arc<Mutex<Box<dyn Write + Send>>>;
+
+pub struct Server {
+    listener: TcpListener,
+    handler: Arc<dyn Handler>,
+    workers: usize,
+    queue: usize,
+    log: AccessLog,
+}
+
+impl Server {
+    // ポートに0を指定すると空いているポートを使う。実際のポートは`local_addr`でわかる
+    pub fn bind<H: Handler + 'static>(config: &Config, handler: H) -> io::Result<Server> {
+        Ok(Server {
+            listener: TcpListener::bind(config.bind_addr())?,
+            handler: Arc::new(handler),
+            workers: config.workers,
+            queue: config.queue,
+            log: Arc::new(Mutex::new(Box::new(io::stdout()))),
+        })
+    }
+
+    // アクセスログの書き出し先を変える。デフォルトは標準出力
+    pub fn access_log<W: Write + Send + 'static>(mut self, w: W) -> Server {
+        self.log = Arc::new(Mutex::new(Box::new(w)));
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // `workers`人のWorkerでコネクションを処理する
+    // Workerが全員ふさがっているときは、`queue`個までコネクションを待たせ、
+    // それも埋まっていたら503を返して閉じる
+    // 503は1つのスレッドがまとめて返すので、どれだけコネクションが来てもスレッドの数は増えない
+    pub fn run(self) -> io::Result<()> {
+        let (rejects, rejected) = mpsc::sync_channel::<(TcpStream, Instant)>(REJECT_QUEUE);
+        let log = Arc::clone(&self.log);
+        thread::Builder::new().name(String::from("http-rejecter")).spawn(move || {
+            for (stream, start) in rejected {
+                if let Err(e) = reject(stream, start, &log) {
+                    println!("Connection error: {}", e);
+                }
+            }
+        })?;
+
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(self.queue);
+        // 1つの受信側を、Mutexで順番にWorkerに使わせる
+        let receiver = Arc::new(Mutex::new(receiver));
+        for id in 0..self.workers {
+            let receiver = Arc::clone(&receiver);
+            let handler = Arc::clone(&self.handler);
+            let log = Arc::clone(&self.log);
+            thread::Builder::new()
+                .name(format!("http-worker-{}", id))
+                .spawn(move || worker(&receiver, &*handler, &log))?;
+        }
+
+        // コネクションがあるたびに`stream`を取り出す
+        for stream in self.listener.incoming() {
+            // streamを包むResultを一旦剥がす
+            // エラーが出てもループを継続するために`?`は使わない
+            let stream = match stream {
+                Ok(stream) => stream,
+                // acceptでエラーが起きたらそれを通知して次のループへ
+                Err(e) => {
+                    println!("An error occurred while accepting a connection: {}", e);
+                    continue;
+                }
+            };
+            // 待ち行列がいっぱいなら、Workerを待たずにここで断る
+            // 断るのも503を返すスレッドに任せ、書き込みを待つ間も次のコネクションを受け付ける
+            // そちらも手一杯なら、streamを捨てて何も返さずに閉じる
+            if let Err(TrySendError::Full(stream)) = sender.try_send(stream) {
+                let _ = rejects.try_send((stream, Instant::now()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn worker(receiver: &Mutex<Receiver<TcpStream>>, handler: &dyn Handler, log: &AccessLog) {
+    loop {
+        // ロックは受け取ったらすぐに手放し、処理中は他のWorkerが次のコネクションを受け取れるようにする
+        let stream = match receiver.lock().unwrap().recv() {
+            Ok(stream) => stream,
+            // サーバーがなくなった
+            Err(_) => return,
+        };
+        if let Err(e) = serve(stream, handler, log) {
+            println!("Connection error: {}", e);
+        }
+    }
+}
+
+// 1つのコネクションで1つのリクエストを読み、ハンドラーのレスポンスを返して閉じる
+fn serve(mut stream: TcpStream, handler: &dyn Handler, log: &AccessLog) -> io::Result<()> {
+    use crate::parser::ParseResult::*;
+    let start = Instant::now();
+    let peer = stream.peer_addr()?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    // 少しずつ送り続けるクライアントもいるので、1回のreadではなくリクエスト全体を読む時間を区切る
+    let deadline = start + IO_TIMEOUT;
+
+    let mut buf = Vec::new();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out"));
+        }
+        stream.set_read_timeout(Some(left))?;
+        // 1回のread分を格納する一時バッファ
+        let mut b = [0; 1024];
+        // バッファにクライアントからの入力を読み込み
+        let n = stream.read(&mut b)?;
+        // 読み込んだバイト数が0ならストリームの終了
+        if n == 0 {
+            return Ok(());
+        }
+        // リクエスト全体のバッファに、読み込んだ分を追記
+        buf.extend_from_slice(&b[0..n]);
+        match parser::parse(buf.as_slice()) {
+            // 入力の途中なら新たな入力を待つため次のイテレーションへ
+            // ただし大きくなりすぎたら、それ以上は読まずに断る
+            Partial => {
+                if let Some(response) = too_large(&buf) {
+                    response.write_to(&mut stream, Version::Http11)?;
+                    return write_log(log, peer, "-", &response, start);
+                }
+            },
+            // 不正な入力には理由を付けて400を返す
+            Error(e) => {
+                let response = Response::text(400, format!("Bad Request: {}\r\n", e));
+                response.write_to(&mut stream, Version::Http11)?;
+                return write_log(log, peer, "-", &response, start);
+            },
+            Complete(req) => {
+                let response = respond(handler, &req);
+                response.write_to(&mut stream, req.version)?;
+                let line = format!("{} {} {}", req.method, req.path, req.version.as_str());
+                // コネクションを閉じる前にログを書くので、クライアントが応答を受け取ったときには書かれている
+                return write_log(log, peer, &line, &response, start);
+            },
+        };
+    }
+}
+
+// 読み終わっていないリクエストが大きすぎれば、返すレスポンス
+// 空行を探すのは先頭の`MAX_HEADER_SIZE`バイトだけなので、読み直しが長引くことはない
+pub(crate) fn too_large(buf: &[u8]) -> Option<Response> {
+    if buf.len() > MAX_HEADER_SIZE {
+        let head = &buf[..MAX_HEADER_SIZE];
+        let headers_done = head.windows(2).any(|w| w == b"\n\n") || head.windows(3).any(|w| w == b"\n\r\n");
+        if !headers_done {
+            return Some(Response::text(431, "Request Header Fields Too Large\r\n"));
+        }
+    }
+    if buf.len() > MAX_REQUEST_SIZE {
+        return Some(Response::text(413, "Payload Too Large\r\n"));
+    }
+    None
+}
+
+// ハンドラーがパニックしても、Workerは巻き込まずに500を返す
+pub(crate) fn respond(handler: &dyn Handler, req: &Request) -> Response {
+    panic::catch_unwind(AssertUnwindSafe(|| handler.handle(req)))
+        .unwrap_or_else(|_| Response::text(500, "Internal Server Error\r\n"))
+}
+
+// Workerの手が空いていないことを伝えて閉じる
+fn reject(mut stream: TcpStream, start: Instant, log: &AccessLog) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    // 断るコネクションは1つずつ順に書くので、相手が読まなくても長くは待たない
+    stream.set_write_timeout(Some(Duration::from_millis(100)))?;
+    let response = unavailable();
+    response.write_to(&mut stream, Version::Http11)?;
+    write_log(log, peer, "-", &response, start)
+}
+
+pub(crate) fn unavailable() -> Response {
+    Response::text(503, "Service Unavailable\r\n").header("Retry-After", "1")
+}
+
+// `127.0.0.1:50000 "GET / HTTP/1.1" 200 12 3ms` の形で1行書く
+pub(crate) fn write_log(log: &AccessLog, peer: SocketAddr, line: &str, response: &Response, start: Instant) -> io::Result<()> {
+    let mut log = log.lock().unwrap();
+    writeln!(
+        log,
+        "{} \"{}\" {} {} {}ms",
+        peer,
+        line,
+        response.status,
+        response.body.len(),
+        start.elapsed().as_millis()
+    )?;
+    log.flush()
+}
+
+
+// テストでログを読むために、書いた内容を共有するバッファ
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SharedLog(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl SharedLog {
+    pub(crate) fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(String::from).collect()
+    }
+}
+
+// 空いているポートでサーバーを起動する。サーバーのスレッドはテストが終わるまで動き続ける
+#[cfg(test)]
+fn start<H: Handler + 'static>(workers: usize, queue: usize, handler: H) -> (SocketAddr, SharedLog) {
+    let config = Config {
+        addr: String::from("127.0.0.1"),
+        port: 0,
+        workers,
+        queue,
+        ..Config::default()
+    };
+    let log = SharedLog::default();
+    let server = Server::bind(&config, handler).unwrap().access_log(log.clone());
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    (addr, log)
+}
+
+#[cfg(test)]
+pub(crate) fn fetch(addr: SocketAddr, raw: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(raw.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serves_handlers_and_writes_access_log() {
+    use crate::handler::Routes;
+
+    let routes = Routes::new()
+        .route("/hello", |_: &Request| Response::text(200, "hello"))
+        .route("/panic", |_: &Request| -> Response { panic!("handler failed") })
+        .fallback(|req: &Request| Response::text(200, format!("OK {}", req.path)));
+    let (addr, log) = start(2, 4, routes);
+
+    let response = fetch(addr, "GET /hello HTTP/1.1\r\nHost: a\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nhello"));
+    // HTTP/0.9ならボディだけを返す
+    assert_eq!(fetch(addr, "GET /foo/bar\r\n"), "OK /foo/bar");
+    assert!(fetch(addr, "GET /panic HTTP/1.0\r\n\r\n").starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    let response = fetch(addr, "GET / HTTP/2.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(response.ends_with("Bad Request: unsupported HTTP version\r\n"));
+
+    let lines = log.lines();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("127.0.0.1:"));
+    assert!(lines[0].contains(" \"GET /hello HTTP/1.1\" 200 5 "), "{}", lines[0]);
+    assert!(lines[1].contains(" \"GET /foo/bar HTTP/0.9\" 200 11 "), "{}", lines[1]);
+    assert!(lines[2].contains(" \"GET /panic HTTP/1.0\" 500 "), "{}", lines[2]);
+    assert!(lines[3].contains(" \"-\" 400 "), "{}", lines[3]);
+}
+
+// 送られた分を読み切ってから断らせないと、閉じたときにレスポンスより先にRSTが届くことがある
+// 大きさの上限を1バイトだけ超えるように送る
+#[cfg(test)]
+pub(crate) fn oversized_requests() -> [(Vec<u8>, &'static str); 2] {
+    let mut endless_header = b"GET / HTTP/1.1\r\nX-Long: ".to_vec();
+    endless_header.resize(MAX_HEADER_SIZE + 1, b'a');
+    let mut huge_body = b"POST / HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n".to_vec();
+    huge_body.resize(MAX_REQUEST_SIZE + 1, b'a');
+    [
+        (endless_header, "HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+        (huge_body, "HTTP/1.1 413 Payload Too Large\r\n"),
+    ]
+}
+
+#[test]
+fn closes_requests_that_grow_too_large() {
+    let (addr, log) = start(1, 1, |_: &Request| Response::text(200, "ok"));
+    for (request, status) in oversized_requests().iter() {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with(status), "{}", response);
+    }
+    assert!(log.lines()[0].contains(" \"-\" 431 "), "{:?}", log.lines());
+    // 上限より小さければ、同じ形のリクエストも普通に処理する
+    let body = "a".repeat(MAX_HEADER_SIZE * 2);
+    let response = fetch(addr, &format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+}
+
+#[test]
+fn rejects_connections_when_workers_are_busy() {
+    // 1人のWorkerを、`release`に送るまでハンドラーの中で止めておく
+    let (entered, wait_entered) = mpsc::channel();
+    let (release, wait_release) = mpsc::channel::<()>();
+    let (entered, wait_release) = (Mutex::new(entered), Mutex::new(wait_release));
+    let handler = move |req: &Request| {
+        entered.lock().unwrap().send(()).unwrap();
+        wait_release.lock().unwrap().recv().unwrap();
+        Response::text(200, req.path.to_string())
+    };
+    let (addr, log) = start(1, 1, handler);
+
+    let first = thread::spawn(move || fetch(addr, "GET /first\r\n"));
+    wait_entered.recv().unwrap();
+    // Workerがふさがっているので、次のコネクションは待ち行列に入り、その次は断られる
+    let mut second = TcpStream::connect(addr).unwrap();
+    second.write_all(b"GET /second\r\n").unwrap();
+    // 断られる側は何も送らない。読まれないまま閉じられると、レスポンスより先にRSTが届くことがある
+    let response = fetch(addr, "");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(response.contains("Retry-After: 1\r\n"));
+
+    release.send(()).unwrap();
+    assert_eq!(first.join().unwrap(), "/first");
+    wait_entered.recv().unwrap();
+    release.send(()).unwrap();
+    let mut response = String::new();
+    second.read_to_string(&mut response).unwrap();
+    assert_eq!(response, "/second");
+    assert_eq!(log.lines().len(), 3);
+}
+
+// 動いているスレッドの数
+#[cfg(all(test, target_os = "linux"))]
+fn thread_count() -> usize {
+    std::fs::read_dir("/proc/self/task").unwrap().count()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn rejecting_a_flood_does_not_spawn_threads() {
+    // 1人のWorkerを、テストが終わるまでハンドラーの中で止めておく
+    let (entered, wait_entered) = mpsc::channel();
+    let (release, wait_release) = mpsc::channel::<()>();
+    let (entered, wait_release) = (Mutex::new(entered), Mutex::new(wait_release));
+    let handler = move |_: &Request| {
+        entered.lock().unwrap().send(()).unwrap();
+        let _ = wait_release.lock().unwrap().recv();
+        Response::text(200, "ok")
+    };
+    let (addr, _) = start(1, 1, handler);
+    let busy = TcpStream::connect(addr).unwrap();
+    (&busy).write_all(b"GET /\r\n").unwrap();
+    wait_entered.recv().unwrap();
+    let queued = TcpStream::connect(addr).unwrap();
+
+    // 断られるコネクションを、読まずに開いたままにしておく
+    let before = thread_count();
+    let mut most = before;
+    let flood: Vec<TcpStream> = (0..300)
+        .map(|_| {
+            let stream = TcpStream::connect(addr).unwrap();
+            most = most.max(thread_count());
+            stream
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(200));
+    most = most.max(thread_count());
+    // 並んで動く他のテストのスレッドの分だけ、余裕を見る
+    assert!(most < before + 16, "{} threads before, {} while flooding", before, most);
+
+    drop((flood, queued, busy));
+    drop(release);
+}
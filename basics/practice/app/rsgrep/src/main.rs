@@ -1,11 +1,11 @@
-use std::fs::File;
-use std::io::{BufReader, BufRead};
 use std::env;
+use std::io::{self, BufWriter, Write};
 extern crate regex;
 use regex::Regex;
 
 fn usage() {
     println!("rsgrep PATTERN FILENAME");
+    println!("FILENAME can be - to read from standard input");
 }
 
 fn main() {
@@ -40,38 +40,32 @@ fn main() {
         }
     };
 
-    // File構造体の`open`関連関数でファイルを開く
+    // ファイルを開く。ファイル名が"-"なら標準入力から読む
     // 失敗する可能性があるので、`Result`で返される
     // 下で`filename`を使用するため、ここでは`&filename`と参照で渡す
-    let file = match File::open(&filename) {
+    let input = match stream_search::open(&filename) {
         // 成功すれば取り出す
-        Ok(file) => file,
+        Ok(input) => input,
         Err(e) => {
             println!("An error occurred while opening file {}:{}", filename, e);
             return;
         }
     };
 
-    // Fileをそのまま使うと遅いことと、`lines`メソッドを使用するために`BufReader`に包む
-    // `new`は関連関数
-    let input = BufReader::new(file);
-    // `BufReader`が実装するトレイトの`BufRead`にある`lines`メソッドを呼び出す
-    // 返り値はイテレーターなので、`for`式で繰り返しができる
-    for line in input.lines() {
-        // 入力がUTF-8でないなどの理由で行のパースに失敗することがあるので、`lines`メソッドもResultに包まれている
-        let line = match line {
-            Ok(line) => line,
-            // 失敗したらそのまま終了することにする
-            Err(e) => {
-                println!("An error occurred while reading a line {}", e);
-                return;
-            }
-        };
-        // パターンマッチしたら標準出力する
-        // is_matchはread onlyなので、参照型で受け取る
-        if reg.is_match(&line) {
-            // 参照型で引数を渡しているので、ここで使用できる
-            println!("{}", line);
-        }
+    // ファイル全体は読み込まず、少しずつ読みながら1行ずつ調べる
+    // UTF-8として正しくない行も、置換文字(U+FFFD)にして検索を続ける
+    // 1行ごとにロックを取らないように、標準出力はまとめて書く
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    // パターンマッチしたら標準出力する
+    // is_matchはread onlyなので、参照型で受け取る
+    let result = stream_search::search_reader(input, |line| reg.is_match(line), |_, line| writeln!(out, "{}", line))
+        .and_then(|_| out.flush());
+    match result {
+        Ok(()) => {},
+        // `| head`などで出力先が先に閉じられたら、そこで止める
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {},
+        // 読み込みに失敗したらそのまま終了することにする
+        Err(e) => println!("An error occurred while reading {}:{}", filename, e),
     }
 }
@@ -0,0 +1,92 @@
+// 大きなファイルや標準入力を、全体を読み込まずに1行ずつ検索するためのもの
+// minigrep、minigrep2、rsgrep、grrsで共有する
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+// 読み込んだバイト列を行に分ける
+pub mod reader;
+
+pub use reader::{LineReader, BINARY_SNIFF_LEN, DEFAULT_CAPACITY};
+
+// 標準入力を表すパス
+pub const STDIN: &str = "-";
+
+pub fn is_stdin<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref() == Path::new(STDIN)
+}
+
+// パスが"-"なら標準入力を、そうでなければファイルを開く
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read>> {
+    if is_stdin(&path) {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+// readerを1行ずつ読み、is_matchがtrueを返した行の番号と中身をfoundに渡す
+// UTF-8として正しくないバイトは置換文字(U+FFFD)にして、検索を止めないようにする
+// 戻り値は一致した行の数
+pub fn search_reader<R, M, F>(reader: R, mut is_match: M, mut found: F) -> io::Result<u64>
+where
+    R: Read,
+    M: FnMut(&str) -> bool,
+    F: FnMut(u64, &str) -> io::Result<()>,
+{
+    let mut lines = LineReader::new(reader);
+    let mut count = 0;
+    while let Some((number, line)) = lines.next_line()? {
+        let line = String::from_utf8_lossy(line);
+        if is_match(&line) {
+            count += 1;
+            found(number, &line)?;
+        }
+    }
+    Ok(count)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_matching_lines_with_numbers() {
+        let mut found = Vec::new();
+        let input = &b"alpha\nbeta\xff\nalphabet\r\n"[..];
+        let count = search_reader(input, |line| line.contains("alpha") || line.contains("beta"), |number, line| {
+            found.push((number, line.to_string()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(
+            found,
+            vec![(1, String::from("alpha")), (2, String::from("beta\u{fffd}")), (3, String::from("alphabet"))]
+        );
+    }
+
+    #[test]
+    fn stops_at_the_first_sink_error() {
+        let mut seen = 0;
+        let result = search_reader(&b"a\na\na\n"[..], |_| true, |_, _| {
+            seen += 1;
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn opens_files_and_recognizes_stdin() {
+        assert!(is_stdin("-"));
+        assert!(!is_stdin("./-"));
+        let path = std::env::temp_dir().join(format!("stream-search-{}.txt", std::process::id()));
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        let count = search_reader(open(&path).unwrap(), |line| line == "two", |_, _| Ok(())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(count, 1);
+        assert!(open(&path).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, Read};
+
+use memchr::memchr;
+
+// 最初に確保するバッファの大きさ
+pub const DEFAULT_CAPACITY: usize = 64 * 1024;
+// 先頭のこのバイト数にNULが含まれていれば、バイナリとみなす（gitと同じ長さ）
+pub const BINARY_SNIFF_LEN: usize = 8000;
+
+// 読み込んだバイト列を1行ずつに分けるもの
+// ファイル全体ではなく、決まった大きさのバッファに少しずつ読み込むので、
+// 使うメモリはファイルの大きさではなく、一番長い行の長さで決まる
+//
+// 行はUTF-8でなくてもよい。改行は"\n"と"\r\n"のどちらでも、行の末尾から取り除く
+pub struct LineReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    // buf[start..end]が、まだ返していない読み込み済みのバイト
+    start: usize,
+    end: usize,
+    // buf[start..scanned]には改行がないことがわかっている
+    // 長い行を読み足すたびに、先頭から探し直さないようにする
+    scanned: usize,
+    eof: bool,
+    line_number: u64,
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(reader: R) -> LineReader<R> {
+        LineReader::with_capacity(DEFAULT_CAPACITY, reader)
+    }
+
+    pub fn with_capacity(capacity: usize, reader: R) -> LineReader<R> {
+        LineReader {
+            reader,
+            buf: vec![0; capacity.max(1)],
+            start: 0,
+            end: 0,
+            scanned: 0,
+            eof: false,
+            line_number: 0,
+        }
+    }
+
+    // 次の行と、1から数えた行番号を返す。最後まで読んだらNone
+    // 返した行はバッファを借用しているので、次の行を読む前に使い終える
+    pub fn next_line(&mut self) -> io::Result<Option<(u64, &[u8])>> {
+        loop {
+            if let Some(i) = memchr(b'\n', &self.buf[self.scanned..self.end]) {
+                let line = self.start..self.scanned + i;
+                self.start = line.end + 1;
+                self.scanned = self.start;
+                return Ok(Some(self.take(line)));
+            }
+            self.scanned = self.end;
+            if self.eof {
+                // 最後の行に改行がなければ、残りをそのまま1行として返す
+                if self.start == self.end {
+                    return Ok(None);
+                }
+                let line = self.start..self.end;
+                self.start = self.end;
+                return Ok(Some(self.take(line)));
+            }
+            self.fill()?;
+        }
+    }
+
+    // 先頭を覗いて、バイナリかどうかを調べる
+    // 読み始める前に呼ぶ。読み込んだバイトは、そのまま行として返す
+    // 調べるのは最初の1回のreadで届いた分だけなので、少しずつ届く標準入力でも続きを待たずに済む
+    pub fn is_binary(&mut self) -> io::Result<bool> {
+        if self.start == self.end && !self.eof {
+            self.fill()?;
+        }
+        let end = self.end.min(self.start + BINARY_SNIFF_LEN);
+        Ok(memchr(0, &self.buf[self.start..end]).is_some())
+    }
+
+    // 次のnext_lineが、読み足さずに返せるか
+    // falseなら次の行を待つことになるので、その前にここまでの出力を済ませておける
+    pub fn line_ready(&self) -> bool {
+        self.eof || memchr(b'\n', &self.buf[self.scanned..self.end]).is_some()
+    }
+
+    // 最後に返した行の番号
+    pub fn line_number(&self) -> u64 {
+        self.line_number
+    }
+
+    // 今確保しているバッファの大きさ
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn take(&mut self, line: std::ops::Range<usize>) -> (u64, &[u8]) {
+        self.line_number += 1;
+        let mut line = &self.buf[line];
+        if let Some(rest) = line.strip_suffix(b"\r") {
+            line = rest;
+        }
+        (self.line_number, line)
+    }
+
+    // バッファに読み足す
+    // 返していない行の続きを先頭に寄せ、それでも空きがなければ（1行がバッファより長ければ）広げる
+    fn fill(&mut self) -> io::Result<()> {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.scanned -= self.start;
+            self.start = 0;
+        }
+        if self.end == self.buf.len() {
+            let capacity = self.buf.len() * 2;
+            self.buf.resize(capacity, 0);
+        }
+        loop {
+            match self.reader.read(&mut self.buf[self.end..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.end += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            return Ok(());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 1回のreadで、決まったバイト数までしか返さないReader
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn lines<R: Read>(mut reader: LineReader<R>) -> Vec<(u64, Vec<u8>)> {
+        let mut lines = Vec::new();
+        while let Some((number, line)) = reader.next_line().unwrap() {
+            lines.push((number, line.to_vec()));
+        }
+        lines
+    }
+
+    #[test]
+    fn splits_lines_across_every_chunk_size() {
+        let data = b"first\r\n\nthird line\nno newline at end";
+        let expected: Vec<(u64, Vec<u8>)> = vec![
+            (1, b"first".to_vec()),
+            (2, b"".to_vec()),
+            (3, b"third line".to_vec()),
+            (4, b"no newline at end".to_vec()),
+        ];
+        for capacity in 1..8 {
+            for step in 1..data.len() + 1 {
+                let reader = LineReader::with_capacity(capacity, Trickle { data, step });
+                assert_eq!(lines(reader), expected, "capacity {} step {}", capacity, step);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_input_and_trailing_newline() {
+        assert!(lines(LineReader::new(&b""[..])).is_empty());
+        assert_eq!(lines(LineReader::new(&b"\n"[..])), vec![(1, Vec::new())]);
+        assert_eq!(lines(LineReader::new(&b"a\n"[..])), vec![(1, b"a".to_vec())]);
+    }
+
+    #[test]
+    fn keeps_invalid_utf8_bytes() {
+        let reader = LineReader::with_capacity(2, &b"caf\xe9\n\xff\xfe\n"[..]);
+        assert_eq!(lines(reader), vec![(1, b"caf\xe9".to_vec()), (2, b"\xff\xfe".to_vec())]);
+    }
+
+    #[test]
+    fn grows_only_for_long_lines() {
+        let mut data = vec![b'x'; 1000];
+        data.extend_from_slice(b"\nshort\n");
+        let mut reader = LineReader::with_capacity(16, Trickle { data: &data, step: 7 });
+        assert_eq!(reader.next_line().unwrap().unwrap().1.len(), 1000);
+        assert_eq!(reader.capacity(), 1024);
+        assert_eq!(reader.next_line().unwrap().unwrap(), (2, &b"short"[..]));
+        assert_eq!(reader.line_number(), 2);
+    }
+
+    #[test]
+    fn knows_when_the_next_line_needs_a_read() {
+        let mut reader = LineReader::with_capacity(16, Trickle { data: b"one\ntwo\nthr", step: 10 });
+        assert!(!reader.line_ready());
+        assert_eq!(reader.next_line().unwrap(), Some((1, &b"one"[..])));
+        // "two\n"まで届いている
+        assert!(reader.line_ready());
+        assert_eq!(reader.next_line().unwrap(), Some((2, &b"two"[..])));
+        assert!(!reader.line_ready());
+        assert_eq!(reader.next_line().unwrap(), Some((3, &b"thr"[..])));
+        assert!(reader.line_ready());
+        assert_eq!(reader.next_line().unwrap(), None);
+    }
+
+    #[test]
+    fn sniffs_nul_in_the_first_bytes() {
+        let mut text = LineReader::with_capacity(4, Trickle { data: b"plain\ntext\n", step: 3 });
+        assert!(!text.is_binary().unwrap());
+        // 調べるために読んだ分も、行として返ってくる
+        assert_eq!(lines(text), vec![(1, b"plain".to_vec()), (2, b"text".to_vec())]);
+
+        let mut data = vec![b'a'; 100];
+        data.push(0);
+        assert!(LineReader::with_capacity(128, Trickle { data: &data, step: 101 }).is_binary().unwrap());
+        // 最初のreadで届いた分だけを調べ、続きは待たない
+        assert!(!LineReader::with_capacity(128, Trickle { data: &data, step: 5 }).is_binary().unwrap());
+
+        // NULが先頭から離れていればテキストとして扱う
+        let mut late = vec![b'a'; BINARY_SNIFF_LEN];
+        late.push(0);
+        assert!(!LineReader::new(&late[..]).is_binary().unwrap());
+    }
+
+    #[test]
+    fn retries_interrupted_reads_and_reports_errors() {
+        struct Flaky(u32);
+        impl Read for Flaky {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0 += 1;
+                match self.0 {
+                    1 => Err(io::Error::new(io::ErrorKind::Interrupted, "signal")),
+                    2 => {
+                        buf[..3].copy_from_slice(b"ok\n");
+                        Ok(3)
+                    },
+                    _ => Err(io::Error::other("disk on fire")),
+                }
+            }
+        }
+        let mut reader = LineReader::new(Flaky(0));
+        assert_eq!(reader.next_line().unwrap(), Some((1, &b"ok"[..])));
+        assert_eq!(reader.next_line().unwrap_err().to_string(), "disk on fire");
+    }
+}
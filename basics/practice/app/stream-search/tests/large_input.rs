@@ -0,0 +1,104 @@
+// 数十MBの入力を生成しながら検索し、確保したメモリの最大値が入力の大きさに比例しないことを確かめる
+// アロケータを差し替えるので、ほかのテストと混ざらないように別のファイルにしている
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use stream_search::{search_reader, LineReader};
+
+struct Counting;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let now = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(now, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// "line N ..."という行を、lines行分だけその場で作って返すReader
+// 10万行ごとに"needle"を含む行と、UTF-8として正しくない行を混ぜる
+struct Generated {
+    next: u64,
+    lines: u64,
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl Generated {
+    fn new(lines: u64) -> Generated {
+        Generated { next: 0, lines, pending: Vec::new(), offset: 0 }
+    }
+}
+
+impl Read for Generated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.pending.len() {
+            if self.next == self.lines {
+                return Ok(0);
+            }
+            self.pending.clear();
+            self.offset = 0;
+            let n = self.next;
+            self.next += 1;
+            let line: &[u8] = match n % 100_000 {
+                0 => b"needle in a haystack",
+                1 => b"latin-1 caf\xe9 needle",
+                _ => b"the quick brown fox jumps over the lazy dog",
+            };
+            self.pending.extend_from_slice(format!("{:08} ", n).as_bytes());
+            self.pending.extend_from_slice(line);
+            self.pending.push(b'\n');
+        }
+        let n = buf.len().min(self.pending.len() - self.offset);
+        buf[..n].copy_from_slice(&self.pending[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+#[test]
+fn memory_stays_flat_for_large_inputs() {
+    // 約100MB分の行。リリースビルドでなくても数秒で終わる大きさにしている
+    const LINES: u64 = 2_000_000;
+
+    let before = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+
+    let mut last = 0;
+    let mut bytes = 0;
+    let count = search_reader(Generated::new(LINES), |line| {
+        bytes += line.len() as u64 + 1;
+        line.contains("needle")
+    }, |number, line| {
+        assert!(number > last);
+        last = number;
+        assert!(line.ends_with("needle in a haystack") || line.ends_with("caf\u{fffd} needle"), "{}", line);
+        Ok(())
+    })
+    .unwrap();
+
+    assert_eq!(count, 2 * LINES / 100_000);
+    assert_eq!(last, LINES - 100_000 + 2);
+    assert!(bytes > 100 * 1000 * 1000, "{} bytes", bytes);
+    // 読み込み用のバッファと、行ごとの小さな確保だけで済んでいる
+    let peak = PEAK.load(Ordering::SeqCst) - before;
+    assert!(peak < 4 * stream_search::DEFAULT_CAPACITY, "peak {} bytes", peak);
+
+    // 1行が長いときだけ、その行が入る分までバッファを広げる
+    let long = vec![b'x'; 3 * 1024 * 1024];
+    let mut reader = LineReader::new(&long[..]);
+    assert_eq!(reader.next_line().unwrap().unwrap().1.len(), long.len());
+    assert!(reader.capacity() <= 4 * 1024 * 1024);
+}